@@ -0,0 +1,222 @@
+use anyhow::{anyhow, Result};
+use babypi::lens::calibration::{calibrate, find_corners, Checkerboard};
+use babypi::lens::{LensCorrection, LensModel, LENS_DEFAULT_ZOOM};
+use image::{GrayImage, Luma};
+
+/// Board pose for synthetic pictures: rotation around x and y in degrees, then translation
+type SyntheticPose = (f32, f32, [f32; 3]);
+
+const SYNTHETIC_POSES: [SyntheticPose; 6] = [
+    (0.0, 0.0, [-120.0, -75.0, 300.0]),
+    (25.0, 0.0, [-150.0, -60.0, 280.0]),
+    (0.0, 30.0, [-60.0, -90.0, 260.0]),
+    (-20.0, -25.0, [-220.0, -120.0, 330.0]),
+    (15.0, 35.0, [20.0, -20.0, 240.0]),
+    (-30.0, 10.0, [-200.0, 10.0, 300.0]),
+];
+
+/// Render a checkerboard seen through the lens, 2x2 supersampled
+fn render(model: &LensModel, board: &Checkerboard, pose: &SyntheticPose) -> GrayImage {
+    let (width, height) = (model.width, model.height);
+    let (ax, ay) = (pose.0.to_radians(), pose.1.to_radians());
+    let (sx, cx) = ax.sin_cos();
+    let (sy, cy) = ay.sin_cos();
+    // R = Ry * Rx
+    let r = [
+        [cy, sy * sx, sy * cx],
+        [0.0, cx, -sx],
+        [-sy, cy * sx, cy * cx],
+    ];
+    let t = pose.2;
+
+    // camera origin and rays in board coordinates, R transposed
+    let to_board = |v: [f32; 3]| {
+        [
+            r[0][0] * v[0] + r[1][0] * v[1] + r[2][0] * v[2],
+            r[0][1] * v[0] + r[1][1] * v[1] + r[2][1] * v[2],
+            r[0][2] * v[0] + r[1][2] * v[1] + r[2][2] * v[2],
+        ]
+    };
+    let origin = to_board([-t[0], -t[1], -t[2]]);
+    let size = board.square_size;
+
+    let sample = |u: f32, v: f32| -> f32 {
+        let Some((a, b)) = model.unproject(u, v, width, height) else {
+            return 0.0;
+        };
+
+        let direction = to_board([a, b, 1.0]);
+        if direction[2].abs() < f32::EPSILON {
+            return 100.0;
+        }
+
+        let s = -origin[2] / direction[2];
+        if s <= 0.0 {
+            return 100.0;
+        }
+
+        let (x, y) = (
+            (origin[0] + s * direction[0]) / size,
+            (origin[1] + s * direction[1]) / size,
+        );
+
+        let inside = |value: f32, count: usize| value >= -1.0 && value < count as f32;
+        let margin = |value: f32, count: usize| value >= -2.0 && value < count as f32 + 1.0;
+
+        if inside(x, board.cols) && inside(y, board.rows) {
+            if (x.floor() as i64 + y.floor() as i64).rem_euclid(2) == 0 {
+                20.0
+            } else {
+                235.0
+            }
+        } else if margin(x, board.cols) && margin(y, board.rows) {
+            235.0
+        } else {
+            100.0
+        }
+    };
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (u, v) = (x as f32, y as f32);
+        let value = (sample(u - 0.25, v - 0.25)
+            + sample(u + 0.25, v - 0.25)
+            + sample(u - 0.25, v + 0.25)
+            + sample(u + 0.25, v + 0.25))
+            / 4.0;
+
+        Luma([value.round() as u8])
+    })
+}
+
+fn print_model(model: &LensModel) {
+    println!("[hardware.camera.lens]");
+    println!("enabled = true");
+    println!("zoom = {:.2}", LENS_DEFAULT_ZOOM);
+    println!();
+    println!("[hardware.camera.lens.model]");
+    println!("width = {}", model.width);
+    println!("height = {}", model.height);
+    println!("fx = {:.3}", model.fx);
+    println!("fy = {:.3}", model.fy);
+    println!("cx = {:.3}", model.cx);
+    println!("cy = {:.3}", model.cy);
+    println!(
+        "k = [{:.6}, {:.6}, {:.6}, {:.6}]",
+        model.k[0], model.k[1], model.k[2], model.k[3]
+    );
+}
+
+/// Recover a known lens from rendered boards
+fn synthetic(board: &Checkerboard) -> Result<()> {
+    let truth = LensModel {
+        width: 1280,
+        height: 720,
+        fx: 420.0,
+        fy: 422.0,
+        cx: 648.0,
+        cy: 352.0,
+        k: [-0.03, 0.012, -0.004, 0.0],
+    };
+
+    let images: Vec<GrayImage> = SYNTHETIC_POSES
+        .iter()
+        .map(|pose| render(&truth, board, pose))
+        .collect();
+
+    for (i, image) in images.iter().enumerate() {
+        let found = find_corners(image, board).is_some();
+        println!(
+            "picture {}: board {}",
+            i,
+            if found { "found" } else { "missing" }
+        );
+    }
+
+    let calibration = calibrate(&images, board)?;
+    let model = &calibration.model;
+
+    println!(
+        "used {} pictures, rms error {:.3} px",
+        calibration.images_used, calibration.rms_error
+    );
+    println!(
+        "expected fx {} fy {} cx {} cy {} k {:?}",
+        truth.fx, truth.fy, truth.cx, truth.cy, truth.k
+    );
+    print_model(model);
+
+    // compare where both models send the same rays, within the area the boards covered
+    let worst = (3..=7)
+        .flat_map(|i| (3..=7).map(move |j| (i as f32 / 10.0, j as f32 / 10.0)))
+        .filter_map(|(x, y)| truth.unproject(x * 1279.0, y * 719.0, 1280, 720))
+        .map(|(a, b)| {
+            let (u0, v0) = truth.project(a, b, 1280, 720);
+            let (u1, v1) = model.project(a, b, 1280, 720);
+            ((u0 - u1).powi(2) + (v0 - v1).powi(2)).sqrt()
+        })
+        .fold(0.0f32, f32::max);
+
+    println!("worst model disagreement {:.3} px", worst);
+
+    if calibration.rms_error > 0.5 || worst > 1.0 {
+        return Err(anyhow!("Calibration did not recover the lens"));
+    }
+
+    let corrected = LensCorrection::new(model.clone(), 0.6).apply_gray(&images[0]);
+    corrected.save("/tmp/calibrate-corrected.png")?;
+    images[0].save("/tmp/calibrate-original.png")?;
+    println!("Wrote /tmp/calibrate-original.png and /tmp/calibrate-corrected.png");
+
+    Ok(())
+}
+
+/// Compute fisheye lens coefficients from checkerboard pictures
+///
+/// Usage: `cargo run --release --example calibrate -- <cols> <rows> <square size> board1.jpg [...]`
+///
+/// `cols` and `rows` count inner corners. Without pictures, a known lens is recovered
+/// from rendered boards instead.
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut next = |name: &str, default: &str| {
+        args.next()
+            .unwrap_or(default.to_string())
+            .parse::<f32>()
+            .map_err(|_| anyhow!("Invalid {}", name))
+    };
+
+    let board = Checkerboard::new(
+        next("cols", "9")? as usize,
+        next("rows", "6")? as usize,
+        next("square size", "25")?,
+    );
+    let pictures: Vec<String> = args.collect();
+
+    if pictures.is_empty() {
+        return synthetic(&board);
+    }
+
+    let images = pictures
+        .iter()
+        .map(|picture| Ok(image::open(picture)?.to_luma8()))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (picture, image) in pictures.iter().zip(images.iter()) {
+        let found = find_corners(image, &board).is_some();
+        println!(
+            "{}: board {}",
+            picture,
+            if found { "found" } else { "missing" }
+        );
+    }
+
+    let calibration = calibrate(&images, &board)?;
+    println!(
+        "used {} pictures, rms error {:.3} px",
+        calibration.images_used, calibration.rms_error
+    );
+    println!();
+    print_model(&calibration.model);
+
+    Ok(())
+}
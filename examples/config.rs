@@ -2,20 +2,29 @@ use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
 use babypi::{
+    analysis::motion::MotionZone,
+    audio_monitor::{level::AudioCalibration, source::AudioBackend},
     config::{
-        AccelerometerConfigV1, CameraConfigV1, CliArgs, IrCamConfigV1, MicrophoneConfigV1,
-        MmWaveConfigV1, TomlConfig, TomlConfigHardwareV1, TomlConfigMonitoringV1,
-        TomlConfigNotificationsV1, TomlConfigRecordingV1, TomlConfigServerV1, TomlConfigStreamV1,
-        TomlConfigTelemetryV1, TomlParity, TOML_CONFIG_DEFAULT_FILENAME,
+        AccelerometerConfigV1, CameraConfigV1, CliArgs, IrCamConfigV1, LensConfigV1,
+        MicrophoneConfigV1, MmWaveConfigV1, TomlConfig, TomlConfigAnalysisV1, TomlConfigCryV1,
+        TomlConfigHardwareV1, TomlConfigInferenceV1, TomlConfigMonitoringV1, TomlConfigMotionV1,
+        TomlConfigNotificationsV1, TomlConfigProcessLimitsV1, TomlConfigRecordingV1,
+        TomlConfigSafeSleepV1, TomlConfigServerV1, TomlConfigSnapshotV1, TomlConfigStreamV1,
+        TomlConfigTelemetryV1, TomlConfigTimelapseV1, TomlParity, TOML_CONFIG_DEFAULT_FILENAME,
     },
     ffmpeg::{
         audio::{
             FfmpegAudioDeviceType, FfmpegAudioFormat, FfmpegAudioSampleFormat,
             FFMPEG_DEFAULT_AUDIO_OUTPUT_BITRATE, FFMPEG_DEFAULT_AUDIO_SAMPLE_RATE,
         },
-        FFMPEG_DEFAULT_STREAM_DIR,
+        FFMPEG_DEFAULT_STREAM_DIR, FFMPEG_DEFAULT_VIDEO_BITRATE, FFMPEG_DEFAULT_VIDEO_ENCODER,
     },
     file_exists,
+    lens::LENS_DEFAULT_ZOOM,
+    live_stream::frame_hub::FrameDecodeMode,
+    process_control::limits::{IoClass, ProcessLimits},
+    snapshot::SnapshotFormat,
+    supervisor::RestartPolicy,
 };
 use clap::Parser;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
@@ -59,12 +68,24 @@ async fn main() -> Result<()> {
                     width: Some(1920),
                     height: Some(1080),
                     fps: Some(30),
+                    intra: Some(30),
                     tuning_file: Some("/usr/share/libcamera/ipa/rpi/vc4/imx219_noir.json".into()),
                     hflip: Some(true),
                     vflip: Some(true),
                     extra_args: Some("".to_string()),
                     ircut_gpio_pin: Some(23),
                     ircut_on_state: Some(true),
+                    lens: LensConfigV1 {
+                        enabled: false,
+                        model: None,
+                        zoom: Some(LENS_DEFAULT_ZOOM),
+                        snapshots: Some(true),
+                        analysis: Some(true),
+                        mjpeg: Some(true),
+                        stream: Some(false),
+                        stream_encoder: Some(FFMPEG_DEFAULT_VIDEO_ENCODER.to_string()),
+                        stream_bitrate: Some(FFMPEG_DEFAULT_VIDEO_BITRATE.to_string()),
+                    },
                 },
                 ircam: IrCamConfigV1 {
                     enabled: true,
@@ -103,11 +124,40 @@ async fn main() -> Result<()> {
             },
             stream: TomlConfigStreamV1 {
                 audio: Some(true),
+                av_sync: Some(false),
                 data_dir: Some(FFMPEG_DEFAULT_STREAM_DIR.into()),
                 extra_args_setup: Some("".to_string()),
                 extra_args_video_input: Some("".to_string()),
                 extra_args_audio_input: Some("".to_string()),
                 extra_args_output: Some("".to_string()),
+                restart_delay: Some(1),
+                restart_delay_max: Some(300),
+                restart_retries: Some(10),
+                restart_healthy_period: Some(120),
+                restart_policy: Some(RestartPolicy::Always),
+                restart_limit: Some(20),
+                restart_limit_window: Some(3600),
+                stall_timeout: Some(10),
+                memory_limit: Some(256),
+                mjpeg_fps: Some(5.0),
+                mjpeg_width: Some(1280),
+                mjpeg_height: Some(720),
+                mjpeg_quality: Some(80),
+                ring_buffer_duration: Some(30),
+                ring_buffer_max_size: Some(64),
+                clips_dir: Some("/tmp/stream/clips".into()),
+                limits: TomlConfigProcessLimitsV1 {
+                    rpicam: None,
+                    ffmpeg: Some(ProcessLimits {
+                        nice: Some(10),
+                        ionice_class: Some(IoClass::BestEffort),
+                        ionice_level: Some(7),
+                        cpu_affinity: Some(vec![1, 2, 3]),
+                        cpu_max: Some(2.0),
+                        memory_max: Some(256),
+                        cgroup_root: None,
+                    }),
+                },
             },
             server: TomlConfigServerV1 {
                 bind: Some("0.0.0.0:8080".to_string()),
@@ -116,18 +166,103 @@ async fn main() -> Result<()> {
                 basic_password: Some("password".to_string()), 
                 webroot: Some("/var/lib/babypi/static".to_string()) 
             },
-            recording: TomlConfigRecordingV1 { enabled: true },
+            recording: TomlConfigRecordingV1 {
+                enabled: true,
+                dir: Some("/var/lib/babypi/recordings".into()),
+                timelapse: TomlConfigTimelapseV1 {
+                    enabled: true,
+                    interval: Some(40),
+                    start: Some("20:00".to_string()),
+                    end: Some("07:00".to_string()),
+                    fps: Some(30),
+                    max_width: Some(1280),
+                    max_height: None,
+                    retention: Some(14),
+                },
+            },
             monitoring: TomlConfigMonitoringV1 {
                 enabled: true,
-                rms_threshold: Some(0.1),
+                rms_threshold: None,
+                threshold_db: Some(10.0),
+                min_dbfs: Some(-60.0),
+                noise_floor_window_secs: Some(60),
+                hysteresis_db: Some(3.0),
+                activity_debounce_ms: Some(600),
+                activity_hangover_secs: Some(5),
+                activity_summary_interval_secs: Some(10),
+                calibration: Some(AudioCalibration::new(-30.0, 65.0)),
+                source: Some(AudioBackend::Pulse),
+                device: None,
+                file: None,
+                cry: TomlConfigCryV1 {
+                    enabled: true,
+                    sensitivity: Some(0.5),
+                    min_bursts: Some(2),
+                },
+            },
+            telemetry: TomlConfigTelemetryV1 {
+                enabled: true,
+                process_stats_interval: Some(5),
+                process_stats_events: Some(false),
             },
-            telemetry: TomlConfigTelemetryV1 { enabled: true },
             notifications: TomlConfigNotificationsV1 {
                 browser: Some("push".to_string()),
                 pushover: Some("push".to_string()),
                 homeassistant: Some("hass".to_string()),
                 mqtt: Some("mqtt".to_string()),
             },
+            analysis: TomlConfigAnalysisV1 {
+                fps: Some(2.0),
+                max_width: Some(320),
+                max_height: None,
+                decode_mode: Some(FrameDecodeMode::Keyframes),
+                motion: TomlConfigMotionV1 {
+                    enabled: true,
+                    sensitivity: Some(0.5),
+                    min_area: Some(0.01),
+                    learning_rate: Some(0.05),
+                    on_frames: Some(2),
+                    off_frames: Some(6),
+                    report_frames: Some(20),
+                    zones: Some(vec![MotionZone::new(
+                        "crib",
+                        vec![[0.2, 0.3], [0.8, 0.3], [0.8, 1.0], [0.2, 1.0]],
+                    )]),
+                    masks: Some(vec![vec![[0.4, 0.3], [0.6, 0.3], [0.6, 0.45], [0.4, 0.45]]]),
+                },
+                presence: TomlConfigInferenceV1 {
+                    enabled: false,
+                    model: Some("/etc/babypi/models/yolov8n.onnx".into()),
+                    input_size: Some(320),
+                    threshold: Some(0.5),
+                    interval: Some(5),
+                },
+                pose: TomlConfigInferenceV1 {
+                    enabled: false,
+                    model: Some("/etc/babypi/models/movenet.onnx".into()),
+                    input_size: Some(192),
+                    threshold: Some(0.3),
+                    interval: Some(5),
+                },
+                safe_sleep: TomlConfigSafeSleepV1 {
+                    enabled: false,
+                    keypoint_threshold: Some(0.3),
+                    stomach_duration: Some(15),
+                    face_hidden_duration: Some(20),
+                    clear_duration: Some(10),
+                },
+            },
+            snapshot: TomlConfigSnapshotV1 {
+                enabled: Some(true),
+                interval: Some(60),
+                format: Some(SnapshotFormat::Webp),
+                quality: Some(85),
+                max_width: None,
+                max_height: None,
+                dir: Some("/tmp/stream/snapshots".into()),
+                history_count: Some(60),
+                history_max_age: Some(86400),
+            },
         };
 
         let config_content = toml::to_string_pretty(&config)?;
@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use babypi::audio_monitor::cry::{CryConfig, CryDetector, CryUpdate};
+use babypi::audio_monitor::source::file::FileSource;
+use babypi::audio_monitor::source::{AudioSource, AudioSpec};
+use babypi::ffmpeg::audio::FfmpegAudioSampleFormat;
+
+/// Run the cry detector over labelled WAV fixtures
+///
+/// Files within a `cry` directory, or named `cry*.wav`, are expected to be detected, every
+/// other file is expected to stay quiet.
+///
+/// Usage: `cargo run --example cry -- tests/fixtures/audio [sensitivity]`
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let dir = PathBuf::from(args.next().unwrap_or("tests/fixtures/audio".to_string()));
+    let sensitivity = args
+        .next()
+        .map(|arg| arg.parse())
+        .transpose()?
+        .unwrap_or(CryConfig::default().sensitivity);
+
+    let mut fixtures = Vec::new();
+    collect_wav_files(&dir, &mut fixtures)?;
+    fixtures.sort();
+
+    let mut passed = 0;
+
+    for path in fixtures.iter() {
+        let expected = is_cry(path);
+        let mut source = FileSource::open(
+            path.clone(),
+            AudioSpec::new(FfmpegAudioSampleFormat::S16le, 16_000, 1),
+        )?
+        .with_realtime(false);
+
+        let spec = source.spec().clone();
+        let config = CryConfig {
+            sensitivity,
+            ..Default::default()
+        };
+        let mut detector = CryDetector::new(config, spec.sample_rate, spec.channels);
+        let mut samples = vec![0f32; spec.samples_for(300)];
+        let mut detection = None;
+
+        loop {
+            let read = source.read(&mut samples)?;
+            if read == 0 {
+                break;
+            }
+
+            for update in detector.process(&samples[..read]) {
+                if let CryUpdate::Ended(cry) = update {
+                    detection.get_or_insert(cry);
+                }
+            }
+        }
+
+        if let Some(cry) = detector.finish() {
+            detection.get_or_insert(cry);
+        }
+
+        let detected = detection.is_some();
+        if detected == expected {
+            passed += 1;
+        }
+
+        println!(
+            "{} {}: expected {}, {}",
+            if detected == expected { "PASS" } else { "FAIL" },
+            path.display(),
+            if expected { "cry" } else { "quiet" },
+            match detection {
+                Some(cry) => format!(
+                    "cry for {:.1}s, confidence {:.2}",
+                    cry.duration.as_secs_f32(),
+                    cry.confidence
+                ),
+                None => "no cry".to_string(),
+            }
+        );
+    }
+
+    println!("{}/{} fixtures passed", passed, fixtures.len());
+
+    Ok(())
+}
+
+fn is_cry(path: &Path) -> bool {
+    path.components()
+        .any(|component| component.as_os_str() == "cry")
+        || path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("cry"))
+}
+
+fn collect_wav_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_wav_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
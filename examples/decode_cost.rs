@@ -0,0 +1,99 @@
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use babypi::live_stream::frame_hub::{
+    luma_frame, rgb_frame, FrameDecodeMode, FrameHubConfig, FrameSampler,
+};
+use babypi::live_stream::h264::{is_first_slice, NalSplitter};
+use openh264::decoder::Decoder;
+use openh264::formats::YUVSource;
+
+/// Measure frame hub CPU cost per decode mode over a raw H.264 fixture clip
+///
+/// Usage: `cargo run --release --example decode_cost -- test.h264 [fps]`
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or("test.h264".to_string());
+    let fps = args
+        .next()
+        .and_then(|fps| fps.parse::<f64>().ok())
+        .unwrap_or(30.0);
+
+    println!("Loading {}...", path);
+    let mut input = OpenOptions::new().read(true).open(&path)?;
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf)?;
+
+    // trailing start code flushes the last unit out of the splitter
+    let mut splitter = NalSplitter::new();
+    let mut units = splitter.push(&buf);
+    units.extend(splitter.push(&[0, 0, 0, 1]));
+
+    let pictures = units.iter().filter(|unit| is_first_slice(unit)).count();
+    let video = Duration::from_secs_f64(pictures as f64 / fps);
+    let hub_config = FrameHubConfig::new(0.0, Some(640), Some(480));
+
+    println!(
+        "{} pictures, {:.1} s of video at {} fps",
+        pictures,
+        video.as_secs_f64(),
+        fps
+    );
+    println!(
+        "{:<12} {:>8} {:>8} {:>10} {:>10} {:>8}",
+        "mode", "decoded", "output", "decode ms", "convert ms", "load"
+    );
+
+    for mode in [
+        FrameDecodeMode::All,
+        FrameDecodeMode::Every(15),
+        FrameDecodeMode::Keyframes,
+    ] {
+        let mut decoder = Decoder::new()?;
+        let mut sampler = FrameSampler::new(mode);
+        let (mut decoded, mut published) = (0, 0);
+        let (mut decode_time, mut convert_time) = (Duration::ZERO, Duration::ZERO);
+
+        for unit in units.iter() {
+            if !sampler.decode(unit) {
+                continue;
+            }
+
+            let time_start = Instant::now();
+            let result = decoder.decode(unit);
+            decode_time += time_start.elapsed();
+
+            let Ok(Some(yuv)) = result else {
+                continue;
+            };
+            decoded += 1;
+
+            if !sampler.publish() {
+                continue;
+            }
+
+            let time_start = Instant::now();
+            let (width, height) = yuv.dimensions();
+            let (target_width, target_height) =
+                hub_config.target_dimensions(width as u32, height as u32);
+            let _ = luma_frame(&yuv, published, target_width, target_height);
+            let _ = rgb_frame(&yuv, published, target_width, target_height);
+            convert_time += time_start.elapsed();
+            published += 1;
+        }
+
+        println!(
+            "{:<12} {:>8} {:>8} {:>10} {:>10} {:>7.1}%",
+            mode.to_string(),
+            decoded,
+            published,
+            decode_time.as_millis(),
+            convert_time.as_millis(),
+            (decode_time + convert_time).as_secs_f64() / video.as_secs_f64() * 100.0
+        );
+    }
+
+    Ok(())
+}
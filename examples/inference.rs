@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use babypi::analysis::onnx::{
+    OnnxPoseEstimator, OnnxPresenceDetector, POSE_DEFAULT_INPUT_SIZE, POSE_DEFAULT_INTERVAL,
+    PRESENCE_DEFAULT_INPUT_SIZE, PRESENCE_DEFAULT_INTERVAL, PRESENCE_DEFAULT_THRESHOLD,
+};
+use babypi::analysis::pose::{SleepingPosition, POSE_DEFAULT_KEYPOINT_THRESHOLD};
+use image::imageops::{resize, FilterType};
+
+/// Run an ONNX presence or pose model over fixture pictures
+///
+/// Usage: `cargo run --features onnx --example inference -- <presence|pose> model.onnx crib.jpg [...]`
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let kind = args.next().unwrap_or("presence".to_string());
+    let model = PathBuf::from(args.next().ok_or_else(|| anyhow!("Missing model path"))?);
+    let pictures: Vec<String> = args.collect();
+
+    println!("Loading {}...", model.display());
+
+    match kind.as_str() {
+        "presence" => {
+            let detector = OnnxPresenceDetector::new(
+                &model,
+                PRESENCE_DEFAULT_INPUT_SIZE,
+                PRESENCE_DEFAULT_THRESHOLD,
+                PRESENCE_DEFAULT_INTERVAL,
+            )?;
+
+            for picture in pictures {
+                let image = image::open(&picture)?.to_rgb8();
+                let image = resize(
+                    &image,
+                    PRESENCE_DEFAULT_INPUT_SIZE,
+                    PRESENCE_DEFAULT_INPUT_SIZE,
+                    FilterType::Triangle,
+                );
+
+                let time_start = Instant::now();
+                match detector.detect(&image)? {
+                    Some((score, bbox)) => println!(
+                        "{}: person {:.2} bbox {:.2},{:.2} {:.2}x{:.2} in {} ms",
+                        picture,
+                        score,
+                        bbox.x,
+                        bbox.y,
+                        bbox.width,
+                        bbox.height,
+                        time_start.elapsed().as_millis()
+                    ),
+                    None => println!(
+                        "{}: nobody in {} ms",
+                        picture,
+                        time_start.elapsed().as_millis()
+                    ),
+                }
+            }
+        }
+        "pose" => {
+            let estimator = OnnxPoseEstimator::new(
+                &model,
+                POSE_DEFAULT_INPUT_SIZE,
+                POSE_DEFAULT_KEYPOINT_THRESHOLD,
+                POSE_DEFAULT_INTERVAL,
+            )?;
+
+            for picture in pictures {
+                let image = image::open(&picture)?.to_rgb8();
+                let image = resize(
+                    &image,
+                    POSE_DEFAULT_INPUT_SIZE,
+                    POSE_DEFAULT_INPUT_SIZE,
+                    FilterType::Triangle,
+                );
+
+                let time_start = Instant::now();
+                let keypoints = estimator.estimate(&image)?;
+                let position =
+                    SleepingPosition::from_keypoints(&keypoints, POSE_DEFAULT_KEYPOINT_THRESHOLD);
+
+                println!(
+                    "{}: {:?} in {} ms",
+                    picture,
+                    position,
+                    time_start.elapsed().as_millis()
+                );
+                for keypoint in keypoints {
+                    println!(
+                        "  {:<16} {:.2},{:.2} score {:.2}",
+                        keypoint.name, keypoint.x, keypoint.y, keypoint.score
+                    );
+                }
+            }
+        }
+        _ => return Err(anyhow!("Unknown model kind `{}`", kind)),
+    }
+
+    Ok(())
+}
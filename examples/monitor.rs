@@ -24,11 +24,11 @@ async fn main() -> Result<()> {
 
     let mut monitor = AudioMonitor::new(
         AudioMonitorContext::new(
-            FfmpegAudioSampleFormat::S16le.into(),
+            FfmpegAudioSampleFormat::S16le,
             44_100,
             1,
             Some("alsa_input.usb-DCMT_Technology_USB_Lavalier_Microphone_214b206000000178-00.mono-fallback".to_string()),
-            Some(0.01),
+            Some(-40.0),
         ),
         Some(tx),
     );
@@ -41,8 +41,20 @@ async fn main() -> Result<()> {
                 info!("State: {}", monitor.is_running());
             }
             event = rx.recv() => {
-                if let Ok(Event::AudioMonitor { rms }) = event {
-                    info!("RMS: {:.3}", rms);
+                match event {
+                    Ok(Event::AudioActivityStarted(level)) => info!(
+                        "Activity started: {:.1} dBFS, floor {:.1} dBFS",
+                        level.dbfs, level.noise_floor_dbfs
+                    ),
+                    Ok(Event::AudioActivityOngoing(activity)) => info!(
+                        "Activity ongoing: {:.1}s, peak {:.1} dBFS, mean {:.1} dBFS",
+                        activity.duration, activity.peak, activity.mean
+                    ),
+                    Ok(Event::AudioActivityEnded(activity)) => info!(
+                        "Activity ended: {:.1}s, peak {:.1} dBFS, mean {:.1} dBFS",
+                        activity.duration, activity.peak, activity.mean
+                    ),
+                    _ => {}
                 }
             }
             _ = tokio::signal::ctrl_c() => {
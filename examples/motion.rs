@@ -0,0 +1,83 @@
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use babypi::analysis::motion::{MotionConfig, MotionDetector, MotionUpdate, MotionZone};
+use babypi::live_stream::frame_hub::{luma_frame, FrameHubConfig};
+use babypi::live_stream::h264::NalSplitter;
+use openh264::decoder::Decoder;
+use openh264::formats::YUVSource;
+
+/// Run the motion detector over a raw H.264 fixture clip
+///
+/// Usage: `cargo run --example motion -- test.h264 [crib]`
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or("test.h264".to_string());
+    let crib_only = args.next().is_some_and(|arg| arg == "crib");
+
+    println!("Loading {}...", path);
+    let mut input = OpenOptions::new().read(true).open(&path)?;
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf)?;
+
+    let mut config = MotionConfig::default();
+    if crib_only {
+        config.zones = vec![MotionZone::new(
+            "crib",
+            vec![[0.2, 0.3], [0.8, 0.3], [0.8, 1.0], [0.2, 1.0]],
+        )];
+    }
+
+    let hub_config = FrameHubConfig::new(0.0, Some(320), Some(240));
+    let mut detector = MotionDetector::new(config);
+    let mut decoder = Decoder::new()?;
+    let mut splitter = NalSplitter::new();
+
+    let time_start = Instant::now();
+    let mut frame_count = 0u64;
+
+    // trailing start code flushes the last unit out of the splitter
+    let mut units = splitter.push(&buf);
+    units.extend(splitter.push(&[0, 0, 0, 1]));
+
+    for unit in units {
+        let Ok(Some(yuv)) = decoder.decode(&unit) else {
+            continue;
+        };
+
+        let (width, height) = yuv.dimensions();
+        let (target_width, target_height) =
+            hub_config.target_dimensions(width as u32, height as u32);
+
+        let frame = luma_frame(&yuv, frame_count, target_width, target_height)
+            .ok_or_else(|| anyhow!("Invalid frame"))?;
+        let image = frame
+            .to_gray_image()
+            .ok_or_else(|| anyhow!("Invalid frame"))?;
+
+        frame_count += 1;
+
+        for update in detector.process(&image) {
+            match update {
+                MotionUpdate::Motion { zone, score, bbox } => println!(
+                    "Frame {}: motion in {} score {:.3} bbox {:.2},{:.2} {:.2}x{:.2}",
+                    frame_count, zone, score, bbox.x, bbox.y, bbox.width, bbox.height
+                ),
+                MotionUpdate::Settled { zone } => {
+                    println!("Frame {}: {} settled", frame_count, zone)
+                }
+            }
+        }
+    }
+
+    println!(
+        "Processed {} frames in {} ms",
+        frame_count,
+        time_start.elapsed().as_millis()
+    );
+
+    Ok(())
+}
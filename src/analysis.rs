@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio::sync::broadcast::Sender;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::live_stream::frame_hub::{Frame, FrameFormat, FrameHub};
+use crate::telemetry::events::Event;
+
+pub mod motion;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+pub mod pose;
+pub mod safe_sleep;
+
+/// Video analysis fed with decoded frames
+pub trait Analyzer: Send + 'static {
+    fn name(&self) -> &str;
+
+    /// Frame format to subscribe to
+    fn format(&self) -> FrameFormat;
+
+    /// Minimum time between two analyzed frames
+    fn interval(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Process a frame, CPU heavy, runs on a blocking thread
+    fn analyze(&mut self, frame: &Frame) -> Result<Vec<Event>>;
+}
+
+/// Runs analyzers on frames from the frame hub, publishing their events
+#[derive(Debug)]
+pub struct AnalyzerRunner {
+    frame_hub: FrameHub,
+    handles: Vec<JoinHandle<()>>,
+    channel: Option<Sender<Event>>,
+}
+
+impl AnalyzerRunner {
+    pub fn new(frame_hub: FrameHub, channel: Option<Sender<Event>>) -> Self {
+        Self {
+            frame_hub,
+            handles: Vec::new(),
+            channel,
+        }
+    }
+
+    /// Start feeding frames to an analyzer
+    pub fn start(&mut self, analyzer: Box<dyn Analyzer>) {
+        let name = analyzer.name().to_string();
+        let interval = analyzer.interval();
+        let mut frames = self.frame_hub.subscribe(analyzer.format());
+        let mut analyzer = Some(analyzer);
+        let channel = self.channel.clone();
+
+        info!(target = "analysis", "Starting {} analyzer", name);
+
+        self.handles.push(tokio::spawn(async move {
+            let mut last_run: Option<Instant> = None;
+
+            while frames.changed().await.is_ok() {
+                if last_run.is_some_and(|t| t.elapsed() < interval) {
+                    continue;
+                }
+
+                let Some(frame) = frames.borrow_and_update().clone() else {
+                    continue;
+                };
+                let Some(mut inner) = analyzer.take() else {
+                    break;
+                };
+
+                last_run = Some(Instant::now());
+
+                let Ok((inner, result)) = tokio::task::spawn_blocking(move || {
+                    let result = inner.analyze(&frame);
+                    (inner, result)
+                })
+                .await
+                else {
+                    error!(target = "analysis", "{} analyzer crashed", name);
+                    break;
+                };
+                analyzer = Some(inner);
+
+                match result {
+                    Ok(events) => {
+                        debug!(
+                            target = "analysis",
+                            "{} analyzer took {} ms",
+                            name,
+                            last_run
+                                .map(|t| t.elapsed().as_millis())
+                                .unwrap_or_default()
+                        );
+
+                        if let Some(channel) = channel.as_ref() {
+                            events.into_iter().for_each(|event| {
+                                let _ = channel.send(event);
+                            });
+                        }
+                    }
+                    Err(e) => error!(target = "analysis", "{} analyzer failed: {}", name, e),
+                }
+            }
+        }));
+    }
+
+    /// Stop all analyzers
+    pub fn stop(&mut self) {
+        self.handles.drain(..).for_each(|handle| handle.abort());
+
+        info!(target = "analysis", "Analyzers stopped");
+    }
+
+    /// Is any analyzer running?
+    pub fn is_running(&self) -> bool {
+        self.handles.iter().any(|h| !h.is_finished())
+    }
+}
@@ -0,0 +1,516 @@
+use anyhow::{anyhow, Result};
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::Analyzer;
+use crate::live_stream::frame_hub::{Frame, FrameFormat};
+use crate::telemetry::events::Event;
+
+pub const MOTION_DEFAULT_SENSITIVITY: f32 = 0.5;
+pub const MOTION_DEFAULT_MIN_AREA: f32 = 0.01;
+pub const MOTION_DEFAULT_LEARNING_RATE: f32 = 0.05;
+pub const MOTION_DEFAULT_ON_FRAMES: u32 = 2;
+pub const MOTION_DEFAULT_OFF_FRAMES: u32 = 6;
+/// Ten seconds at the default analysis frame rate
+pub const MOTION_DEFAULT_REPORT_FRAMES: u32 = 20;
+pub const MOTION_DEFAULT_ZONE: &str = "frame";
+
+/// Changed share of the whole picture treated as a lighting change, e.g. IR cut switching
+const MOTION_LIGHTING_CHANGE_AREA: f32 = 0.7;
+
+/// Point in normalized picture coordinates, `[x, y]` within `0.0..=1.0`
+pub type MotionPoint = [f32; 2];
+
+/// Named polygon to watch for motion
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MotionZone {
+    pub name: String,
+    pub polygon: Vec<MotionPoint>,
+}
+
+impl MotionZone {
+    pub fn new(name: &str, polygon: Vec<MotionPoint>) -> Self {
+        Self {
+            name: name.to_string(),
+            polygon,
+        }
+    }
+}
+
+/// Bounding box in normalized picture coordinates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MotionBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct MotionConfig {
+    /// `0.0..=1.0`, higher picks up smaller brightness changes
+    pub sensitivity: f32,
+    /// Changed share of a zone needed to count as motion
+    pub min_area: f32,
+    /// Background adaptation speed per frame, `0.0..=1.0`
+    pub learning_rate: f32,
+    /// Consecutive frames with motion before a zone turns active
+    pub on_frames: u32,
+    /// Consecutive quiet frames before an active zone settles
+    pub off_frames: u32,
+    /// Frames between two updates of an active zone, the first one is sent right away
+    pub report_frames: u32,
+    /// Whole picture when empty
+    pub zones: Vec<MotionZone>,
+    /// Areas ignored everywhere, e.g. a mobile above the crib
+    pub masks: Vec<Vec<MotionPoint>>,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: MOTION_DEFAULT_SENSITIVITY,
+            min_area: MOTION_DEFAULT_MIN_AREA,
+            learning_rate: MOTION_DEFAULT_LEARNING_RATE,
+            on_frames: MOTION_DEFAULT_ON_FRAMES,
+            off_frames: MOTION_DEFAULT_OFF_FRAMES,
+            report_frames: MOTION_DEFAULT_REPORT_FRAMES,
+            zones: Vec::new(),
+            masks: Vec::new(),
+        }
+    }
+}
+
+impl MotionConfig {
+    /// Per pixel brightness difference considered a change
+    fn pixel_threshold(&self) -> f32 {
+        let sensitivity = self.sensitivity.clamp(0.0, 1.0);
+
+        60.0 - sensitivity * 52.0
+    }
+}
+
+/// Zone motion state change or ongoing activity
+#[derive(Clone, Debug, PartialEq)]
+pub enum MotionUpdate {
+    /// Sent when a zone turns active, then with the peak score and overall box since the last one
+    Motion {
+        zone: String,
+        score: f32,
+        bbox: MotionBox,
+    },
+    Settled {
+        zone: String,
+    },
+}
+
+#[derive(Debug)]
+struct ZoneState {
+    name: String,
+    /// Pixel indices inside the zone and outside every mask
+    pixels: Vec<usize>,
+    active: bool,
+    streak: u32,
+    since_report: u32,
+    /// Peak score and changed pixel bounds since the last report
+    pending: Option<(f32, PixelBox)>,
+}
+
+/// Inclusive pixel bounds, `[min_x, min_y, max_x, max_y]`
+type PixelBox = [u32; 4];
+
+/// Background subtraction motion detector working on luma frames
+#[derive(Debug)]
+pub struct MotionDetector {
+    config: MotionConfig,
+    width: u32,
+    height: u32,
+    background: Vec<f32>,
+    zones: Vec<ZoneState>,
+}
+
+impl MotionDetector {
+    pub fn new(config: MotionConfig) -> Self {
+        Self {
+            config,
+            width: 0,
+            height: 0,
+            background: Vec::new(),
+            zones: Vec::new(),
+        }
+    }
+
+    /// Feed the next frame, returns zone updates
+    pub fn process(&mut self, frame: &GrayImage) -> Vec<MotionUpdate> {
+        if frame.dimensions() != (self.width, self.height) {
+            self.reset(frame);
+            return Vec::new();
+        }
+
+        let threshold = self.config.pixel_threshold();
+        let changed = frame
+            .as_raw()
+            .iter()
+            .zip(&self.background)
+            .map(|(pixel, background)| (*pixel as f32 - background).abs() > threshold)
+            .collect::<Vec<bool>>();
+
+        let changed_total = changed.iter().filter(|c| **c).count();
+        if changed_total as f32 >= changed.len() as f32 * MOTION_LIGHTING_CHANGE_AREA {
+            // the whole scene changed, nothing to compare against anymore
+            self.background = frame.as_raw().iter().map(|p| *p as f32).collect();
+            return Vec::new();
+        }
+
+        let updates = self
+            .zones
+            .iter_mut()
+            .filter_map(|zone| zone_update(zone, &changed, self.width, self.height, &self.config))
+            .collect();
+
+        // adapt slowly where things move, so a still baby doesn't vanish into the background
+        let rate = self.config.learning_rate.clamp(0.0, 1.0);
+        self.background
+            .iter_mut()
+            .zip(frame.as_raw())
+            .zip(&changed)
+            .for_each(|((background, pixel), changed)| {
+                let rate = if *changed { rate / 4.0 } else { rate };
+                *background += (*pixel as f32 - *background) * rate;
+            });
+
+        updates
+    }
+
+    fn reset(&mut self, frame: &GrayImage) {
+        let (width, height) = frame.dimensions();
+
+        self.width = width;
+        self.height = height;
+        self.background = frame.as_raw().iter().map(|p| *p as f32).collect();
+
+        let zones = if self.config.zones.is_empty() {
+            vec![MotionZone::new(
+                MOTION_DEFAULT_ZONE,
+                vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+            )]
+        } else {
+            self.config.zones.clone()
+        };
+
+        self.zones = zones
+            .into_iter()
+            .map(|zone| ZoneState {
+                pixels: (0..(width * height) as usize)
+                    .filter(|i| {
+                        let x = ((*i as u32 % width) as f32 + 0.5) / width as f32;
+                        let y = ((*i as u32 / width) as f32 + 0.5) / height as f32;
+
+                        polygon_contains(&zone.polygon, x, y)
+                            && !self
+                                .config
+                                .masks
+                                .iter()
+                                .any(|mask| polygon_contains(mask, x, y))
+                    })
+                    .collect(),
+                name: zone.name,
+                active: false,
+                streak: 0,
+                since_report: 0,
+                pending: None,
+            })
+            .collect();
+    }
+}
+
+fn zone_update(
+    zone: &mut ZoneState,
+    changed: &[bool],
+    width: u32,
+    height: u32,
+    config: &MotionConfig,
+) -> Option<MotionUpdate> {
+    if zone.pixels.is_empty() {
+        return None;
+    }
+
+    let mut count = 0;
+    let mut bounds: PixelBox = [u32::MAX, u32::MAX, 0, 0];
+
+    for i in zone.pixels.iter().filter(|i| changed[**i]) {
+        let (x, y) = (*i as u32 % width, *i as u32 / width);
+
+        count += 1;
+        bounds = union(bounds, [x, y, x, y]);
+    }
+
+    let score = count as f32 / zone.pixels.len() as f32;
+    let moving = count > 0 && score >= config.min_area;
+
+    // count frames going against the current state
+    if moving != zone.active {
+        zone.streak += 1;
+    } else {
+        zone.streak = 0;
+    }
+
+    if !zone.active {
+        if zone.streak < config.on_frames.max(1) {
+            return None;
+        }
+
+        zone.active = true;
+        zone.streak = 0;
+        zone.since_report = 0;
+        zone.pending = None;
+
+        return Some(motion_update(zone, score, bounds, width, height));
+    }
+
+    if zone.streak >= config.off_frames.max(1) {
+        zone.active = false;
+        zone.streak = 0;
+        zone.pending = None;
+
+        return Some(MotionUpdate::Settled {
+            zone: zone.name.clone(),
+        });
+    }
+
+    if moving {
+        zone.pending = Some(match zone.pending {
+            Some((peak, pending)) => (peak.max(score), union(pending, bounds)),
+            None => (score, bounds),
+        });
+    }
+
+    zone.since_report += 1;
+    if zone.since_report < config.report_frames.max(1) {
+        return None;
+    }
+
+    zone.since_report = 0;
+    let (peak, bounds) = zone.pending.take()?;
+
+    Some(motion_update(zone, peak, bounds, width, height))
+}
+
+fn union(a: PixelBox, b: PixelBox) -> PixelBox {
+    [
+        a[0].min(b[0]),
+        a[1].min(b[1]),
+        a[2].max(b[2]),
+        a[3].max(b[3]),
+    ]
+}
+
+fn motion_update(
+    zone: &ZoneState,
+    score: f32,
+    [min_x, min_y, max_x, max_y]: PixelBox,
+    width: u32,
+    height: u32,
+) -> MotionUpdate {
+    MotionUpdate::Motion {
+        zone: zone.name.clone(),
+        score,
+        bbox: MotionBox {
+            x: min_x as f32 / width as f32,
+            y: min_y as f32 / height as f32,
+            width: (max_x - min_x + 1) as f32 / width as f32,
+            height: (max_y - min_y + 1) as f32 / height as f32,
+        },
+    }
+}
+
+/// Even-odd rule point in polygon test
+fn polygon_contains(polygon: &[MotionPoint], x: f32, y: f32) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+
+    for i in 0..polygon.len() {
+        let [xi, yi] = polygon[i];
+        let [xj, yj] = polygon[j];
+
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+
+        j = i;
+    }
+
+    inside
+}
+
+impl Analyzer for MotionDetector {
+    fn name(&self) -> &str {
+        "motion"
+    }
+
+    fn format(&self) -> FrameFormat {
+        FrameFormat::Luma
+    }
+
+    fn analyze(&mut self, frame: &Frame) -> Result<Vec<Event>> {
+        let image = frame
+            .to_gray_image()
+            .ok_or_else(|| anyhow!("Motion detection needs luma frames"))?;
+
+        Ok(self
+            .process(&image)
+            .into_iter()
+            .map(|update| match update {
+                MotionUpdate::Motion { zone, score, bbox } => Event::Motion { zone, score, bbox },
+                MotionUpdate::Settled { zone } => Event::MotionSettled { zone },
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live_stream::frame_hub::{luma_frame, FrameHubConfig};
+    use crate::live_stream::h264::NalSplitter;
+    use openh264::decoder::Decoder;
+    use openh264::formats::YUVSource;
+
+    /// Checkerboard with a square crossing the crib from frame 15 to 35, and a light flickering
+    /// under the mobile on every other frame
+    const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/motion.h264");
+
+    fn fixture_frames() -> Vec<GrayImage> {
+        let hub_config = FrameHubConfig::default();
+        let mut decoder = Decoder::new().unwrap();
+        let mut splitter = NalSplitter::new();
+
+        let mut units = splitter.push(FIXTURE);
+        units.extend(splitter.push(&[0, 0, 0, 1]));
+
+        let mut frames = Vec::new();
+        for unit in units {
+            let Ok(Some(yuv)) = decoder.decode(&unit) else {
+                continue;
+            };
+
+            let (width, height) = yuv.dimensions();
+            let (width, height) = hub_config.target_dimensions(width as u32, height as u32);
+            let frame = luma_frame(&yuv, frames.len() as u64, width, height).unwrap();
+
+            frames.push(frame.to_gray_image().unwrap());
+        }
+
+        frames
+    }
+
+    fn crib_config() -> MotionConfig {
+        MotionConfig {
+            zones: vec![MotionZone::new(
+                "crib",
+                vec![[0.2, 0.3], [0.8, 0.3], [0.8, 1.0], [0.2, 1.0]],
+            )],
+            masks: vec![vec![[0.4, 0.3], [0.6, 0.3], [0.6, 0.45], [0.4, 0.45]]],
+            ..Default::default()
+        }
+    }
+
+    fn run(config: MotionConfig) -> Vec<(usize, MotionUpdate)> {
+        let mut detector = MotionDetector::new(config);
+
+        fixture_frames()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, frame)| {
+                detector
+                    .process(frame)
+                    .into_iter()
+                    .map(move |update| (i, update))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixture_decodes() {
+        let frames = fixture_frames();
+
+        assert_eq!(frames.len(), 60);
+        assert_eq!(frames[0].dimensions(), (320, 240));
+    }
+
+    #[test]
+    fn reports_once_on_entry_then_settles() {
+        let updates = run(crib_config());
+
+        assert_eq!(updates.len(), 3, "{:?}", updates);
+
+        let (entry, MotionUpdate::Motion { zone, bbox, .. }) = &updates[0] else {
+            panic!("expected motion first, got {:?}", updates[0]);
+        };
+        assert_eq!(zone, "crib");
+        assert!((15..20).contains(entry));
+        // the square enters at a quarter of the width, in the bottom half
+        assert!(bbox.x > 0.2 && bbox.x < 0.35 && bbox.y > 0.55);
+
+        // one summary spanning the whole crossing
+        let (_, MotionUpdate::Motion { bbox, .. }) = &updates[1] else {
+            panic!("expected a summary, got {:?}", updates[1]);
+        };
+        assert!(bbox.width > 0.3);
+
+        let (settled, MotionUpdate::Settled { zone }) = &updates[2] else {
+            panic!("expected settling last, got {:?}", updates[2]);
+        };
+        assert_eq!(zone, "crib");
+        assert!((35..45).contains(settled));
+    }
+
+    #[test]
+    fn masks_hide_the_mobile() {
+        let mut config = crib_config();
+        config.masks.clear();
+
+        // the flicker keeps the zone busy long after the square left
+        let updates = run(config);
+        assert!(!updates
+            .iter()
+            .any(|(_, update)| matches!(update, MotionUpdate::Settled { .. })));
+    }
+
+    #[test]
+    fn still_picture_stays_quiet() {
+        let frames = fixture_frames();
+        let mut detector = MotionDetector::new(crib_config());
+
+        // first frames only, before the square shows up
+        assert!(frames[..15]
+            .iter()
+            .all(|frame| detector.process(frame).is_empty()));
+    }
+
+    #[test]
+    fn no_changed_pixels_is_no_motion() {
+        let frames = fixture_frames();
+        let mut detector = MotionDetector::new(MotionConfig {
+            min_area: 0.0,
+            ..crib_config()
+        });
+
+        assert!(frames[..15]
+            .iter()
+            .all(|frame| detector.process(frame).is_empty()));
+    }
+
+    #[test]
+    fn polygon_contains_even_odd() {
+        let square = [[0.2, 0.2], [0.8, 0.2], [0.8, 0.8], [0.2, 0.8]];
+
+        assert!(polygon_contains(&square, 0.5, 0.5));
+        assert!(!polygon_contains(&square, 0.1, 0.5));
+        assert!(!polygon_contains(&square[..2], 0.5, 0.5));
+    }
+}
@@ -0,0 +1,482 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use image::imageops::{resize, FilterType};
+use image::RgbImage;
+use tract_onnx::prelude::*;
+use tract_onnx::tract_hir::infer::Factoid;
+
+use crate::analysis::motion::MotionBox;
+use crate::analysis::pose::{Keypoint, SleepingPosition, POSE_KEYPOINT_NAMES};
+use crate::analysis::Analyzer;
+use crate::live_stream::frame_hub::{Frame, FrameFormat};
+use crate::telemetry::events::Event;
+
+pub const PRESENCE_DEFAULT_INPUT_SIZE: u32 = 320;
+pub const PRESENCE_DEFAULT_THRESHOLD: f32 = 0.5;
+pub const PRESENCE_DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+pub const POSE_DEFAULT_INPUT_SIZE: u32 = 192;
+pub const POSE_DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+type OnnxModel = Arc<TypedRunnableModel>;
+
+/// Load and optimize an ONNX model for a fixed input shape
+fn load_model(path: &Path, datum_type: DatumType, shape: [usize; 4]) -> Result<OnnxModel> {
+    let model = tract_onnx::onnx()
+        .model_for_path(path)
+        .map_err(|e| anyhow!("Failed to load model `{}`: {}", path.display(), e))?
+        .with_input_fact(0, InferenceFact::dt_shape(datum_type, shape))?
+        .into_optimized()?
+        .into_runnable()?;
+
+    Ok(model)
+}
+
+/// Input element type declared by the model, `f32` when unspecified
+fn input_datum_type(path: &Path) -> Result<DatumType> {
+    let model = tract_onnx::onnx()
+        .model_for_path(path)
+        .map_err(|e| anyhow!("Failed to load model `{}`: {}", path.display(), e))?;
+
+    Ok(model
+        .input_fact(0)?
+        .datum_type
+        .concretize()
+        .unwrap_or(DatumType::F32))
+}
+
+fn frame_image(frame: &Frame, size: u32) -> Result<RgbImage> {
+    let image = frame
+        .to_rgb_image()
+        .ok_or_else(|| anyhow!("Inference needs RGB frames"))?;
+
+    // plain stretch, normalized outputs then map straight back onto the frame
+    Ok(resize(&image, size, size, FilterType::Triangle))
+}
+
+/// Person detector for YOLOv8 style models
+///
+/// Expects a `[1, 3, size, size]` input in `0.0..=1.0` and a `[1, 4 + classes, anchors]`
+/// output of center based boxes in input pixels, class 0 being a person.
+#[derive(Debug)]
+pub struct OnnxPresenceDetector {
+    model: OnnxModel,
+    input_size: u32,
+    threshold: f32,
+    interval: Duration,
+}
+
+impl OnnxPresenceDetector {
+    pub fn new(path: &Path, input_size: u32, threshold: f32, interval: Duration) -> Result<Self> {
+        let size = input_size as usize;
+
+        Ok(Self {
+            model: load_model(path, DatumType::F32, [1, 3, size, size])?,
+            input_size,
+            threshold,
+            interval,
+        })
+    }
+
+    /// Most confident person, if any
+    pub fn detect(&self, image: &RgbImage) -> Result<Option<(f32, MotionBox)>> {
+        let size = self.input_size as usize;
+        let input: Tensor =
+            tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+                image.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+            })
+            .into();
+
+        let outputs = self.model.run(tvec!(input.into()))?;
+
+        decode_detections(outputs[0].to_plain_array_view::<f32>()?, self.input_size)
+    }
+}
+
+/// Most confident person in a `[1, 4 + classes, anchors]` detector output
+fn decode_detections(
+    output: tract_ndarray::ArrayViewD<f32>,
+    input_size: u32,
+) -> Result<Option<(f32, MotionBox)>> {
+    let shape = output.shape();
+    if shape.len() != 3 || shape[1] < 5 {
+        return Err(anyhow!("Unexpected detector output shape {:?}", shape));
+    }
+
+    let best = (0..shape[2])
+        .map(|i| (output[[0, 4, i]], i))
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+
+    Ok(best.map(|(score, i)| {
+        let scale = input_size as f32;
+        let (cx, cy) = (output[[0, 0, i]] / scale, output[[0, 1, i]] / scale);
+        let (w, h) = (output[[0, 2, i]] / scale, output[[0, 3, i]] / scale);
+
+        // clipped to the picture, boxes may hang over its edges
+        let (x0, x1) = (
+            (cx - w / 2.0).clamp(0.0, 1.0),
+            (cx + w / 2.0).clamp(0.0, 1.0),
+        );
+        let (y0, y1) = (
+            (cy - h / 2.0).clamp(0.0, 1.0),
+            (cy + h / 2.0).clamp(0.0, 1.0),
+        );
+
+        (
+            score,
+            MotionBox {
+                x: x0,
+                y: y0,
+                width: x1 - x0,
+                height: y1 - y0,
+            },
+        )
+    }))
+}
+
+impl Analyzer for OnnxPresenceDetector {
+    fn name(&self) -> &str {
+        "presence"
+    }
+
+    fn format(&self) -> FrameFormat {
+        FrameFormat::Rgb
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn analyze(&mut self, frame: &Frame) -> Result<Vec<Event>> {
+        let image = frame_image(frame, self.input_size)?;
+        let best = self.detect(&image)?;
+
+        let (confidence, bbox) = match best {
+            Some((score, bbox)) if score >= self.threshold => (score, Some(bbox)),
+            Some((score, _)) => (score, None),
+            None => (0.0, None),
+        };
+
+        Ok(vec![Event::Presence {
+            present: bbox.is_some(),
+            confidence,
+            bbox,
+        }])
+    }
+}
+
+/// Single person keypoint model, MoveNet style
+///
+/// Expects a `[1, size, size, 3]` input in `0..=255`, `f32` or `i32`, and a
+/// `[1, 1, 17, 3]` output of normalized `(y, x, score)` in COCO keypoint order.
+#[derive(Debug)]
+pub struct OnnxPoseEstimator {
+    model: OnnxModel,
+    datum_type: DatumType,
+    input_size: u32,
+    threshold: f32,
+    interval: Duration,
+}
+
+impl OnnxPoseEstimator {
+    pub fn new(path: &Path, input_size: u32, threshold: f32, interval: Duration) -> Result<Self> {
+        let size = input_size as usize;
+        let datum_type = input_datum_type(path)?;
+
+        Ok(Self {
+            model: load_model(path, datum_type, [1, size, size, 3])?,
+            datum_type,
+            input_size,
+            threshold,
+            interval,
+        })
+    }
+
+    pub fn estimate(&self, image: &RgbImage) -> Result<Vec<Keypoint>> {
+        let size = self.input_size as usize;
+        let pixels = tract_ndarray::Array4::from_shape_fn((1, size, size, 3), |(_, y, x, c)| {
+            image.get_pixel(x as u32, y as u32)[c]
+        });
+
+        let input: Tensor = match self.datum_type {
+            DatumType::I32 => pixels.mapv(|p| p as i32).into(),
+            _ => pixels.mapv(|p| p as f32).into(),
+        };
+
+        let outputs = self.model.run(tvec!(input.into()))?;
+
+        decode_keypoints(outputs[0].to_plain_array_view::<f32>()?)
+    }
+}
+
+/// Keypoints from a `[1, 1, 17, 3]` output of normalized `(y, x, score)`
+fn decode_keypoints(output: tract_ndarray::ArrayViewD<f32>) -> Result<Vec<Keypoint>> {
+    let points = output
+        .to_shape((POSE_KEYPOINT_NAMES.len(), 3))
+        .map_err(|_| anyhow!("Unexpected pose output shape {:?}", output.shape()))?;
+
+    Ok(POSE_KEYPOINT_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| Keypoint::new(name, points[[i, 1]], points[[i, 0]], points[[i, 2]]))
+        .collect())
+}
+
+impl Analyzer for OnnxPoseEstimator {
+    fn name(&self) -> &str {
+        "pose"
+    }
+
+    fn format(&self) -> FrameFormat {
+        FrameFormat::Rgb
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    fn analyze(&mut self, frame: &Frame) -> Result<Vec<Event>> {
+        let image = frame_image(frame, self.input_size)?;
+        let keypoints = self.estimate(&image)?;
+        let sleeping_position = SleepingPosition::from_keypoints(&keypoints, self.threshold);
+
+        Ok(vec![Event::Pose {
+            keypoints,
+            sleeping_position,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Instant;
+    use tract_ndarray::{Array3, Array4};
+
+    /// Tiny stand-in models and the pictures they are run on
+    ///
+    /// `presence.onnx` splits its 64 px input into a 4x4 grid and scores each cell by how red it
+    /// is, so the red square of `baby.png` is its person. `pose.onnx` puts the 17 keypoints at
+    /// fixed spots, scoring the face ones by the mean red level and the body ones by the mean
+    /// blue level of its 64 px input: magenta `face_up.png` shows everything, blue
+    /// `face_down.png` only the body.
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/onnx")
+            .join(name)
+    }
+
+    fn frame(name: &str) -> Frame {
+        let image = image::open(fixture(name)).unwrap().to_rgb8();
+
+        Frame {
+            sequence: 0,
+            decoded_at: Instant::now(),
+            width: image.width(),
+            height: image.height(),
+            format: FrameFormat::Rgb,
+            data: image.into_raw(),
+        }
+    }
+
+    fn presence_detector() -> OnnxPresenceDetector {
+        OnnxPresenceDetector::new(
+            &fixture("presence.onnx"),
+            64,
+            PRESENCE_DEFAULT_THRESHOLD,
+            PRESENCE_DEFAULT_INTERVAL,
+        )
+        .unwrap()
+    }
+
+    fn pose_estimator() -> OnnxPoseEstimator {
+        OnnxPoseEstimator::new(&fixture("pose.onnx"), 64, 0.3, POSE_DEFAULT_INTERVAL).unwrap()
+    }
+
+    /// `[1, 5, anchors]` person detector output from `(cx, cy, w, h, score)` rows in input pixels
+    fn detections(anchors: &[[f32; 5]]) -> Array3<f32> {
+        Array3::from_shape_fn((1, 5, anchors.len()), |(_, row, i)| anchors[i][row])
+    }
+
+    /// `[1, 1, 17, 3]` pose output with the given `(y, x, score)` for every keypoint
+    fn keypoints(point: impl Fn(usize) -> [f32; 3]) -> Array4<f32> {
+        Array4::from_shape_fn((1, 1, POSE_KEYPOINT_NAMES.len(), 3), |(_, _, i, c)| {
+            point(i)[c]
+        })
+    }
+
+    #[test]
+    fn detection_picks_most_confident_anchor() {
+        let output = detections(&[
+            [40.0, 40.0, 20.0, 20.0, 0.2],
+            [128.0, 64.0, 64.0, 32.0, 0.9],
+            [200.0, 200.0, 10.0, 10.0, 0.4],
+        ]);
+
+        let (score, bbox) = decode_detections(output.into_dyn().view(), 256)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(score, 0.9);
+        assert_eq!(
+            bbox,
+            MotionBox {
+                x: 0.375,
+                y: 0.1875,
+                width: 0.25,
+                height: 0.125,
+            }
+        );
+    }
+
+    #[test]
+    fn detection_box_stays_in_picture() {
+        let output = detections(&[[0.0, 320.0, 100.0, 100.0, 0.8]]);
+
+        let (_, bbox) = decode_detections(output.into_dyn().view(), 320)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            bbox,
+            MotionBox {
+                x: 0.0,
+                y: 0.84375,
+                width: 0.15625,
+                height: 0.15625,
+            }
+        );
+    }
+
+    #[test]
+    fn detection_without_anchors() {
+        let output = detections(&[]);
+
+        assert!(decode_detections(output.into_dyn().view(), 320)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn detection_rejects_unexpected_shape() {
+        let output = Array3::<f32>::zeros((1, 4, 10));
+
+        assert!(decode_detections(output.into_dyn().view(), 320).is_err());
+    }
+
+    #[test]
+    fn keypoints_swap_to_x_y() {
+        let output = keypoints(|i| [0.1 * i as f32 / 2.0, 0.5, 0.8]);
+
+        let points = decode_keypoints(output.into_dyn().view()).unwrap();
+
+        assert_eq!(points.len(), POSE_KEYPOINT_NAMES.len());
+        assert_eq!(points[0], Keypoint::new("nose", 0.5, 0.0, 0.8));
+        assert_eq!(points[16], Keypoint::new("right_ankle", 0.5, 0.8, 0.8));
+    }
+
+    #[test]
+    fn keypoints_rejects_unexpected_shape() {
+        let output = Array4::<f32>::zeros((1, 1, 13, 3));
+
+        assert!(decode_keypoints(output.into_dyn().view()).is_err());
+    }
+
+    #[test]
+    fn keypoints_to_sleeping_position() {
+        // face hidden, shoulders and hips visible
+        let output = keypoints(|i| [0.5, 0.5, if i >= 5 { 0.9 } else { 0.05 }]);
+        let points = decode_keypoints(output.into_dyn().view()).unwrap();
+
+        assert_eq!(
+            SleepingPosition::from_keypoints(&points, 0.3),
+            SleepingPosition::Stomach
+        );
+
+        let output = keypoints(|_| [0.5, 0.5, 0.9]);
+        let points = decode_keypoints(output.into_dyn().view()).unwrap();
+
+        assert_eq!(
+            SleepingPosition::from_keypoints(&points, 0.3),
+            SleepingPosition::Back
+        );
+    }
+
+    #[test]
+    fn presence_in_picture() {
+        let events = presence_detector().analyze(&frame("baby.png")).unwrap();
+
+        let [Event::Presence {
+            present,
+            confidence,
+            bbox,
+        }] = events.as_slice()
+        else {
+            panic!("Unexpected events {:?}", events);
+        };
+
+        assert!(present);
+        assert!(*confidence > 0.9);
+        assert_eq!(
+            *bbox,
+            Some(MotionBox {
+                x: 0.5,
+                y: 0.25,
+                width: 0.25,
+                height: 0.25,
+            })
+        );
+    }
+
+    #[test]
+    fn presence_in_empty_crib() {
+        let events = presence_detector()
+            .analyze(&frame("empty_crib.png"))
+            .unwrap();
+
+        let [Event::Presence { present, bbox, .. }] = events.as_slice() else {
+            panic!("Unexpected events {:?}", events);
+        };
+
+        assert!(!present);
+        assert_eq!(*bbox, None);
+    }
+
+    #[test]
+    fn pose_in_picture() {
+        let mut estimator = pose_estimator();
+
+        for (name, expected) in [
+            ("face_up.png", SleepingPosition::Back),
+            ("face_down.png", SleepingPosition::Stomach),
+            ("empty_crib.png", SleepingPosition::Unknown),
+        ] {
+            let events = estimator.analyze(&frame(name)).unwrap();
+
+            let [Event::Pose {
+                keypoints,
+                sleeping_position,
+            }] = events.as_slice()
+            else {
+                panic!("Unexpected events {:?}", events);
+            };
+
+            assert_eq!(keypoints.len(), POSE_KEYPOINT_NAMES.len(), "{}", name);
+            assert_eq!(*sleeping_position, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn missing_model_fails_to_load() {
+        assert!(OnnxPresenceDetector::new(
+            Path::new("/nonexistent/model.onnx"),
+            PRESENCE_DEFAULT_INPUT_SIZE,
+            PRESENCE_DEFAULT_THRESHOLD,
+            PRESENCE_DEFAULT_INTERVAL,
+        )
+        .is_err());
+    }
+}
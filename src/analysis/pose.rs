@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::serde_stuff::float_precision_two;
+
+pub const POSE_DEFAULT_KEYPOINT_THRESHOLD: f32 = 0.3;
+
+/// COCO keypoint order used by common single person pose models
+pub const POSE_KEYPOINT_NAMES: [&str; 17] = [
+    "nose",
+    "left_eye",
+    "right_eye",
+    "left_ear",
+    "right_ear",
+    "left_shoulder",
+    "right_shoulder",
+    "left_elbow",
+    "right_elbow",
+    "left_wrist",
+    "right_wrist",
+    "left_hip",
+    "right_hip",
+    "left_knee",
+    "right_knee",
+    "left_ankle",
+    "right_ankle",
+];
+
+/// Body keypoint in normalized picture coordinates
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keypoint {
+    pub name: String,
+    #[serde(with = "float_precision_two")]
+    pub x: f32,
+    #[serde(with = "float_precision_two")]
+    pub y: f32,
+    #[serde(with = "float_precision_two")]
+    pub score: f32,
+}
+
+impl Keypoint {
+    pub fn new(name: &str, x: f32, y: f32, score: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            x,
+            y,
+            score,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SleepingPosition {
+    Back,
+    Side,
+    Stomach,
+    #[default]
+    Unknown,
+}
+
+impl SleepingPosition {
+    /// Rough position guess from face and shoulder keypoints visibility
+    pub fn from_keypoints(keypoints: &[Keypoint], threshold: f32) -> Self {
+        let visible = |name: &str| {
+            keypoints
+                .iter()
+                .any(|k| k.name == name && k.score >= threshold)
+        };
+
+        let nose = visible("nose");
+        let eyes = ["left_eye", "right_eye"]
+            .iter()
+            .filter(|n| visible(n))
+            .count();
+        let ears = ["left_ear", "right_ear"]
+            .iter()
+            .filter(|n| visible(n))
+            .count();
+        let shoulders = ["left_shoulder", "right_shoulder"]
+            .iter()
+            .filter(|n| visible(n))
+            .count();
+
+        if nose && eyes == 2 {
+            Self::Back
+        } else if (nose || eyes > 0) && (eyes == 1 || ears == 1) {
+            Self::Side
+        } else if shoulders > 0 && !nose && eyes == 0 {
+            // body in view, face isn't
+            Self::Stomach
+        } else {
+            Self::Unknown
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::pose::{Keypoint, SleepingPosition, POSE_DEFAULT_KEYPOINT_THRESHOLD};
+
+pub const SAFE_SLEEP_DEFAULT_STOMACH_DURATION: Duration = Duration::from_secs(15);
+pub const SAFE_SLEEP_DEFAULT_FACE_HIDDEN_DURATION: Duration = Duration::from_secs(20);
+pub const SAFE_SLEEP_DEFAULT_CLEAR_DURATION: Duration = Duration::from_secs(10);
+
+/// Pose results further apart than this restart the confirmation windows
+pub const SAFE_SLEEP_MAX_GAP: Duration = Duration::from_secs(60);
+
+const FACE_KEYPOINTS: [&str; 3] = ["nose", "left_eye", "right_eye"];
+const BODY_KEYPOINTS: [&str; 4] = ["left_shoulder", "right_shoulder", "left_hip", "right_hip"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsafeSleepReason {
+    /// Lying face down
+    Stomach,
+    /// Baby in view but the face isn't, e.g. under a blanket
+    FaceHidden,
+}
+
+#[derive(Clone, Debug)]
+pub struct SafeSleepConfig {
+    /// Minimum keypoint score to count it as visible
+    pub keypoint_threshold: f32,
+    /// Time face down before alerting
+    pub stomach_duration: Duration,
+    /// Time without a visible face before alerting
+    pub face_hidden_duration: Duration,
+    /// Time back in a safe position before an alert clears
+    pub clear_duration: Duration,
+}
+
+impl Default for SafeSleepConfig {
+    fn default() -> Self {
+        Self {
+            keypoint_threshold: POSE_DEFAULT_KEYPOINT_THRESHOLD,
+            stomach_duration: SAFE_SLEEP_DEFAULT_STOMACH_DURATION,
+            face_hidden_duration: SAFE_SLEEP_DEFAULT_FACE_HIDDEN_DURATION,
+            clear_duration: SAFE_SLEEP_DEFAULT_CLEAR_DURATION,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SafeSleepUpdate {
+    Alert {
+        reason: UnsafeSleepReason,
+        /// How long the condition has lasted
+        duration: Duration,
+    },
+    Cleared {
+        reason: UnsafeSleepReason,
+    },
+}
+
+/// Confirmation window for a single condition
+#[derive(Debug, Default)]
+struct Window {
+    since: Option<Instant>,
+    clear_since: Option<Instant>,
+    active: bool,
+}
+
+impl Window {
+    fn reset(&mut self) {
+        self.since = None;
+        self.clear_since = None;
+    }
+
+    /// Returns `Some(true)` when the alert fires and `Some(false)` when it clears
+    fn update(
+        &mut self,
+        condition: bool,
+        now: Instant,
+        confirm: Duration,
+        clear: Duration,
+    ) -> Option<bool> {
+        if condition {
+            self.clear_since = None;
+            let since = *self.since.get_or_insert(now);
+
+            if !self.active && now.duration_since(since) >= confirm {
+                self.active = true;
+                return Some(true);
+            }
+        } else {
+            self.since = None;
+            let clear_since = *self.clear_since.get_or_insert(now);
+
+            if self.active && now.duration_since(clear_since) >= clear {
+                self.active = false;
+                return Some(false);
+            }
+        }
+
+        None
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.since
+            .map(|since| now.duration_since(since))
+            .unwrap_or_default()
+    }
+}
+
+/// Safe sleep rules over pose and presence results
+///
+/// Each condition needs to hold for its whole confirmation window before alerting, and an
+/// alert only clears after the baby has been back in a safe position for the clear window.
+/// While a face down alert is active, the overlapping hidden face alert is held back.
+#[derive(Debug)]
+pub struct SafeSleepMonitor {
+    config: SafeSleepConfig,
+    present: Option<bool>,
+    last_update: Option<Instant>,
+    stomach: Window,
+    face_hidden: Window,
+}
+
+impl SafeSleepMonitor {
+    pub fn new(config: SafeSleepConfig) -> Self {
+        Self {
+            config,
+            present: None,
+            last_update: None,
+            stomach: Window::default(),
+            face_hidden: Window::default(),
+        }
+    }
+
+    pub fn config(&self) -> &SafeSleepConfig {
+        &self.config
+    }
+
+    /// Latest person detector result, when one runs
+    pub fn set_presence(&mut self, present: bool) {
+        self.present = Some(present);
+    }
+
+    /// Alerts currently raised
+    pub fn active(&self) -> Vec<UnsafeSleepReason> {
+        let mut reasons = Vec::new();
+        if self.stomach.active {
+            reasons.push(UnsafeSleepReason::Stomach);
+        }
+        if self.face_hidden.active {
+            reasons.push(UnsafeSleepReason::FaceHidden);
+        }
+
+        reasons
+    }
+
+    /// Feed a pose result taken at `now`
+    pub fn update(&mut self, keypoints: &[Keypoint], now: Instant) -> Vec<SafeSleepUpdate> {
+        let threshold = self.config.keypoint_threshold;
+        let visible = |names: &[&str]| {
+            keypoints
+                .iter()
+                .any(|k| k.score >= threshold && names.contains(&k.name.as_str()))
+        };
+
+        if self
+            .last_update
+            .is_some_and(|last| now.duration_since(last) > SAFE_SLEEP_MAX_GAP)
+        {
+            self.stomach.reset();
+            self.face_hidden.reset();
+        }
+        self.last_update = Some(now);
+
+        let position = SleepingPosition::from_keypoints(keypoints, threshold);
+        let present = self.present.unwrap_or(false) || visible(&BODY_KEYPOINTS);
+        let face_hidden = present && !visible(&FACE_KEYPOINTS);
+
+        let mut updates = Vec::new();
+
+        if let Some(raised) = self.stomach.update(
+            position == SleepingPosition::Stomach,
+            now,
+            self.config.stomach_duration,
+            self.config.clear_duration,
+        ) {
+            updates.push(self.transition(UnsafeSleepReason::Stomach, raised, now));
+        }
+
+        // keeps timing in the background so it can take over once face down clears
+        let face_hidden_window = if self.stomach.active {
+            Duration::MAX
+        } else {
+            self.config.face_hidden_duration
+        };
+
+        if let Some(raised) = self.face_hidden.update(
+            face_hidden,
+            now,
+            face_hidden_window,
+            self.config.clear_duration,
+        ) {
+            updates.push(self.transition(UnsafeSleepReason::FaceHidden, raised, now));
+        }
+
+        updates
+    }
+
+    fn transition(&self, reason: UnsafeSleepReason, raised: bool, now: Instant) -> SafeSleepUpdate {
+        if !raised {
+            return SafeSleepUpdate::Cleared { reason };
+        }
+
+        let window = match reason {
+            UnsafeSleepReason::Stomach => &self.stomach,
+            UnsafeSleepReason::FaceHidden => &self.face_hidden,
+        };
+
+        SafeSleepUpdate::Alert {
+            reason,
+            duration: window.elapsed(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::pose::POSE_KEYPOINT_NAMES;
+
+    /// Pose results every 5 seconds, like the default pose interval
+    const STEP: Duration = Duration::from_secs(5);
+
+    /// Synthetic pose with the given keypoints visible
+    fn pose(visible: &[&str]) -> Vec<Keypoint> {
+        POSE_KEYPOINT_NAMES
+            .iter()
+            .map(|name| {
+                let score = if visible.contains(name) { 0.9 } else { 0.05 };
+                Keypoint::new(name, 0.5, 0.5, score)
+            })
+            .collect()
+    }
+
+    fn back() -> Vec<Keypoint> {
+        pose(&[
+            "nose",
+            "left_eye",
+            "right_eye",
+            "left_shoulder",
+            "right_shoulder",
+            "left_hip",
+            "right_hip",
+        ])
+    }
+
+    fn side() -> Vec<Keypoint> {
+        pose(&["nose", "left_eye", "left_ear", "left_shoulder"])
+    }
+
+    fn stomach() -> Vec<Keypoint> {
+        pose(&["left_shoulder", "right_shoulder", "left_hip", "right_hip"])
+    }
+
+    fn blanket() -> Vec<Keypoint> {
+        pose(&["left_hip", "right_hip", "left_knee", "right_knee"])
+    }
+
+    fn empty() -> Vec<Keypoint> {
+        pose(&[])
+    }
+
+    /// Feed a sequence, returning updates with their offset from the start in seconds
+    fn run(
+        monitor: &mut SafeSleepMonitor,
+        start: Instant,
+        sequence: &[(Vec<Keypoint>, u32)],
+    ) -> Vec<(u64, SafeSleepUpdate)> {
+        let mut at = start;
+        let mut updates = Vec::new();
+
+        for (keypoints, count) in sequence {
+            for _ in 0..*count {
+                for update in monitor.update(keypoints, at) {
+                    updates.push(((at - start).as_secs(), update));
+                }
+                at += STEP;
+            }
+        }
+
+        updates
+    }
+
+    fn monitor() -> SafeSleepMonitor {
+        SafeSleepMonitor::new(SafeSleepConfig::default())
+    }
+
+    fn alert(reason: UnsafeSleepReason, secs: u64) -> SafeSleepUpdate {
+        SafeSleepUpdate::Alert {
+            reason,
+            duration: Duration::from_secs(secs),
+        }
+    }
+
+    fn cleared(reason: UnsafeSleepReason) -> SafeSleepUpdate {
+        SafeSleepUpdate::Cleared { reason }
+    }
+
+    #[test]
+    fn sleeping_position_from_keypoints() {
+        let threshold = POSE_DEFAULT_KEYPOINT_THRESHOLD;
+
+        assert_eq!(
+            SleepingPosition::from_keypoints(&back(), threshold),
+            SleepingPosition::Back
+        );
+        assert_eq!(
+            SleepingPosition::from_keypoints(&side(), threshold),
+            SleepingPosition::Side
+        );
+        assert_eq!(
+            SleepingPosition::from_keypoints(&stomach(), threshold),
+            SleepingPosition::Stomach
+        );
+        assert_eq!(
+            SleepingPosition::from_keypoints(&empty(), threshold),
+            SleepingPosition::Unknown
+        );
+        // nothing counts below the threshold
+        assert_eq!(
+            SleepingPosition::from_keypoints(&back(), 0.95),
+            SleepingPosition::Unknown
+        );
+    }
+
+    #[test]
+    fn back_only() {
+        assert_eq!(run(&mut monitor(), Instant::now(), &[(back(), 20)]), []);
+    }
+
+    #[test]
+    fn short_roll_over() {
+        let updates = run(
+            &mut monitor(),
+            Instant::now(),
+            &[(back(), 2), (stomach(), 3), (back(), 4)],
+        );
+
+        assert_eq!(updates, []);
+    }
+
+    #[test]
+    fn face_down_then_back() {
+        let mut monitor = monitor();
+        let start = Instant::now();
+        let updates = run(&mut monitor, start, &[(back(), 2), (stomach(), 6)]);
+
+        assert_eq!(updates, [(25, alert(UnsafeSleepReason::Stomach, 15))]);
+        assert_eq!(monitor.active(), [UnsafeSleepReason::Stomach]);
+
+        let updates = run(&mut monitor, start + STEP * 8, &[(back(), 4)]);
+
+        assert_eq!(updates, [(10, cleared(UnsafeSleepReason::Stomach))]);
+        assert!(monitor.active().is_empty());
+    }
+
+    #[test]
+    fn empty_crib() {
+        let updates = run(&mut monitor(), Instant::now(), &[(back(), 1), (empty(), 8)]);
+
+        assert_eq!(updates, []);
+    }
+
+    #[test]
+    fn detected_person_without_keypoints() {
+        let mut monitor = monitor();
+        monitor.set_presence(true);
+
+        let updates = run(&mut monitor, Instant::now(), &[(empty(), 5)]);
+
+        assert_eq!(updates, [(20, alert(UnsafeSleepReason::FaceHidden, 20))]);
+    }
+
+    #[test]
+    fn blanket_over_the_face() {
+        let updates = run(
+            &mut monitor(),
+            Instant::now(),
+            &[(back(), 1), (blanket(), 8), (back(), 3)],
+        );
+
+        assert_eq!(
+            updates,
+            [
+                (25, alert(UnsafeSleepReason::FaceHidden, 20)),
+                (55, cleared(UnsafeSleepReason::FaceHidden)),
+            ]
+        );
+    }
+
+    #[test]
+    fn face_down_then_face_covered() {
+        let updates = run(
+            &mut monitor(),
+            Instant::now(),
+            &[(stomach(), 4), (blanket(), 8)],
+        );
+
+        // face hidden kept timing behind the face down alert
+        assert_eq!(
+            updates,
+            [
+                (15, alert(UnsafeSleepReason::Stomach, 15)),
+                (30, cleared(UnsafeSleepReason::Stomach)),
+                (30, alert(UnsafeSleepReason::FaceHidden, 30)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_results_restart_the_windows() {
+        let mut monitor = monitor();
+        let start = Instant::now();
+
+        assert_eq!(run(&mut monitor, start, &[(stomach(), 2)]), []);
+
+        // pose analysis paused for a while
+        let resume = start + SAFE_SLEEP_MAX_GAP * 2;
+        assert_eq!(run(&mut monitor, resume, &[(stomach(), 2)]), []);
+    }
+}
@@ -1,77 +1,196 @@
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::RwLock;
 use std::time::Duration;
 
+use crate::audio_monitor::activity::ActivityConfig;
+use crate::audio_monitor::activity::ActivityTracker;
+use crate::audio_monitor::activity::ActivityUpdate;
+use crate::audio_monitor::activity::AUDIO_ACTIVITY_NOISE_FLOOR_SLOWDOWN;
+use crate::audio_monitor::cry::CryConfig;
+use crate::audio_monitor::cry::CryDetector;
+use crate::audio_monitor::cry::CryUpdate;
+use crate::audio_monitor::level::AudioCalibration;
+use crate::audio_monitor::level::AudioLevel;
+use crate::audio_monitor::level::NoiseFloor;
+use crate::audio_monitor::level::AUDIO_MONITOR_DEFAULT_NOISE_FLOOR_WINDOW;
+use crate::audio_monitor::source::alsa::AlsaSource;
+use crate::audio_monitor::source::file::FileSource;
+use crate::audio_monitor::source::pipewire::PipewireSource;
+use crate::audio_monitor::source::pulse::PulseSource;
+use crate::audio_monitor::source::AudioBackend;
+use crate::audio_monitor::source::AudioSource;
+use crate::audio_monitor::source::AudioSpec;
 use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
 use crate::telemetry::events::Event;
 use anyhow::anyhow;
 use anyhow::Result;
-use libpulse_binding as pulse;
-use libpulse_binding::sample::Format as PulseAudioSampleFormat;
-use libpulse_simple_binding as simple;
 use tokio::sync::broadcast::Sender;
 // use tokio::task::JoinHandle;
 use std::thread::JoinHandle;
 use tracing::{debug, error, info};
 
+pub mod activity;
+pub mod cry;
+pub mod level;
+pub mod source;
+
 pub const AUDIO_MONITOR_BOOTSTRAP_RETRY: u8 = 10;
-pub const AUDIO_MONITOR_DEFAULT_RMS_THRESHOLD: f32 = 0.1;
-
-impl From<FfmpegAudioSampleFormat> for PulseAudioSampleFormat {
-    fn from(value: FfmpegAudioSampleFormat) -> Self {
-        match value {
-            // FfmpegAudioSampleFormat::U8 => PulseAudioSampleFormat::U8,
-            // FfmpegAudioSampleFormat::ALaw => PulseAudioSampleFormat::ALaw,
-            // FfmpegAudioSampleFormat::ULaw => PulseAudioSampleFormat::ULaw,
-            FfmpegAudioSampleFormat::S16le => PulseAudioSampleFormat::S16le,
-            // FfmpegAudioSampleFormat::S16be => PulseAudioSampleFormat::S16be,
-            FfmpegAudioSampleFormat::F32le => PulseAudioSampleFormat::F32le,
-            // FfmpegAudioSampleFormat::F32be => PulseAudioSampleFormat::F32be,
-            FfmpegAudioSampleFormat::S32le => PulseAudioSampleFormat::S32le,
-            // FfmpegAudioSampleFormat::S32be => PulseAudioSampleFormat::S32be,
-            // FfmpegAudioSampleFormat::S24le => PulseAudioSampleFormat::S24le,
-            // FfmpegAudioSampleFormat::S24be => PulseAudioSampleFormat::S24be,
-        }
-    }
-}
+/// Samples window for each level measurement
+pub const AUDIO_MONITOR_WINDOW_MS: u32 = 300;
 
 #[derive(Debug, Clone)]
 pub struct AudioMonitorContext {
-    sample_format: PulseAudioSampleFormat,
+    backend: AudioBackend,
+    sample_format: FfmpegAudioSampleFormat,
     sample_rate: u32,
     channels: u8,
+    /// Capture device, or file path for replays
     device: Option<String>,
+    realtime: bool,
 
-    rms_threshold: Option<f32>,
+    activity: ActivityConfig,
+    noise_floor_window: Duration,
+    calibration: Option<AudioCalibration>,
+    cry_detection: Option<CryConfig>,
 }
 
 impl Default for AudioMonitorContext {
     fn default() -> Self {
         Self {
-            sample_format: PulseAudioSampleFormat::S16le,
-            sample_rate: 44_000,
+            backend: AudioBackend::default(),
+            sample_format: FfmpegAudioSampleFormat::S16le,
+            sample_rate: 44_100,
             channels: 1,
             device: None,
-            rms_threshold: Some(AUDIO_MONITOR_DEFAULT_RMS_THRESHOLD),
+            realtime: true,
+            activity: ActivityConfig::default(),
+            noise_floor_window: AUDIO_MONITOR_DEFAULT_NOISE_FLOOR_WINDOW,
+            calibration: None,
+            cry_detection: None,
         }
     }
 }
 
 impl AudioMonitorContext {
     pub fn new(
-        sample_format: PulseAudioSampleFormat,
+        sample_format: FfmpegAudioSampleFormat,
         sample_rate: u32,
         channels: u8,
         device: Option<String>,
-        rms_threshold: Option<f32>,
+        min_dbfs: Option<f32>,
     ) -> Self {
         Self {
             sample_format,
             sample_rate,
             channels,
             device,
-            rms_threshold,
+            activity: ActivityConfig {
+                min_dbfs,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Capture backend, PulseAudio by default
+    pub fn with_backend(mut self, backend: AudioBackend) -> Self {
+        self.backend = backend;
+
+        self
+    }
+
+    /// Replay files at their own pace rather than as fast as possible
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+
+        self
+    }
+
+    /// Trigger this many dB above the ambient noise floor
+    pub fn with_threshold_db(mut self, threshold_db: f32) -> Self {
+        self.activity.threshold_db = threshold_db;
+
+        self
+    }
+
+    /// Drop below the threshold tolerated once an episode started
+    pub fn with_hysteresis_db(mut self, hysteresis_db: f32) -> Self {
+        self.activity.hysteresis_db = hysteresis_db;
+
+        self
+    }
+
+    /// Loud time starting an episode, quiet time ending it and time between summaries
+    pub fn with_activity_timing(
+        mut self,
+        debounce: Duration,
+        hangover: Duration,
+        summary_interval: Duration,
+    ) -> Self {
+        self.activity.debounce = debounce;
+        self.activity.hangover = hangover;
+        self.activity.summary_interval = summary_interval;
+
+        self
+    }
+
+    /// Time constant of the noise floor moving average
+    pub fn with_noise_floor_window(mut self, noise_floor_window: Duration) -> Self {
+        self.noise_floor_window = noise_floor_window;
+
+        self
+    }
+
+    /// Report sound pressure levels from a reference measurement
+    pub fn with_calibration(mut self, calibration: Option<AudioCalibration>) -> Self {
+        self.calibration = calibration;
+
+        self
+    }
+
+    /// Look for crying on top of loudness, disabled by default
+    pub fn with_cry_detection(mut self, cry_detection: Option<CryConfig>) -> Self {
+        self.cry_detection = cry_detection;
+
+        self
+    }
+
+    /// Open the configured capture
+    pub fn open_source(&self) -> Result<Box<dyn AudioSource>> {
+        let spec = AudioSpec::new(self.sample_format.clone(), self.sample_rate, self.channels);
+        let device = self.device.as_deref();
+
+        let source: Box<dyn AudioSource> = match self.backend {
+            AudioBackend::Alsa => Box::new(AlsaSource::open(spec, device)?),
+            AudioBackend::Pulse => Box::new(PulseSource::open(spec, device)?),
+            AudioBackend::Pipewire => Box::new(PipewireSource::open(spec, device)?),
+            AudioBackend::File => {
+                let path = device.ok_or_else(|| anyhow!("Audio file replay requires a file"))?;
+
+                Box::new(FileSource::open(path.into(), spec)?.with_realtime(self.realtime))
+            }
+        };
+
+        Ok(source)
+    }
+}
+
+/// Latest level measured by a monitor, shareable with request handlers
+#[derive(Clone, Debug, Default)]
+pub struct AudioLevelWatch {
+    latest: Arc<RwLock<Option<AudioLevel>>>,
+}
+
+impl AudioLevelWatch {
+    pub fn latest(&self) -> Option<AudioLevel> {
+        self.latest.read().ok().and_then(|level| level.clone())
+    }
+
+    fn set(&self, level: AudioLevel) {
+        if let Ok(mut latest) = self.latest.write() {
+            *latest = Some(level);
         }
     }
 }
@@ -83,6 +202,7 @@ pub struct AudioMonitor {
     shutdown_signal: Arc<AtomicBool>,
     retry_count: u8,
     channel: Option<Sender<Event>>,
+    level: AudioLevelWatch,
 }
 
 impl Default for AudioMonitor {
@@ -93,6 +213,7 @@ impl Default for AudioMonitor {
             shutdown_signal: Arc::new(AtomicBool::new(false)),
             retry_count: 0,
             channel: None,
+            level: AudioLevelWatch::default(),
         }
     }
 }
@@ -145,144 +266,134 @@ impl AudioMonitor {
         let context = self.context.clone();
         let channel = self.channel.clone();
         let shutdown = self.shutdown_signal.clone();
+        let latest_level = self.level.clone();
 
-        Ok(std::thread::spawn(move || {
-            let buffer_size = match context.sample_rate {
-                96_000 => 28_800,
-                48_000 => 14_400,
-                44_100 => 13_230,
-                24_000 => 7_200,
-                _ => {
-                    error!(
-                        target = "audio_monitor",
-                        "Unsupported sample rate: {}", context.sample_rate
-                    );
+        // sources are opened on the capture thread, not all of them can be moved
+        let (opened_tx, opened_rx) = std::sync::mpsc::channel::<Result<()>>();
+
+        let handle = std::thread::spawn(move || {
+            let mut source = match context.open_source() {
+                Ok(source) => source,
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
                     return;
                 }
             };
 
-            let pulse_connection = match simple::Simple::new(
-                None,
-                "babypi",
-                pulse::stream::Direction::Record,
-                context.device.as_deref(),
-                "audio_monitor",
-                &pulse::sample::Spec {
-                    format: context.sample_format,
-                    channels: context.channels,
-                    rate: context.sample_rate,
-                },
-                None,
-                None,
-            ) {
-                Ok(pc) => pc,
+            let spec = source.spec().clone();
+            let mut buffer = vec![0f32; spec.samples_for(AUDIO_MONITOR_WINDOW_MS)];
+
+            // capture commands spawn fine on a busy device, only the first read tells
+            let mut first_read = match source.read(&mut buffer) {
+                Ok(read) => {
+                    let _ = opened_tx.send(Ok(()));
+                    Some(read)
+                }
                 Err(e) => {
-                    error!(
-                        target = "audio_monitor",
-                        "Error connecting to pulseaudio: {}", e
-                    );
+                    let _ = opened_tx.send(Err(e));
                     return;
                 }
             };
-
-            match context.sample_format {
-                PulseAudioSampleFormat::S16le => {
-                    let mut buffer = vec![0i16; buffer_size]; // 300ms
-                    let mut normalized_buffer = vec![0f32; buffer_size];
-
-                    while !shutdown.load(Ordering::SeqCst) {
-                        if let Err(e) = pulse_connection.read(i16_to_u8_slice(&mut buffer)) {
-                            error!(
-                                target = "audio_monitor",
-                                "Error reading from pulseaudio stream: {}", e
-                            );
-                            return;
-                        }
-
-                        buffer
-                            .iter()
-                            .map(|sample| *sample as f32 / 32768.0)
-                            .zip(normalized_buffer.iter_mut())
-                            .for_each(|(b, df)| *df = b);
-
-                        let rms = calculate_rms(&normalized_buffer);
-
-                        if context.rms_threshold.is_some_and(|rms_t| rms > rms_t) {
-                            debug!(target = "audio_monitor", "RMS = {}; TRIGGER = true", rms);
-
-                            if let Some(channel) = &channel {
-                                let _ = channel.send(Event::AudioMonitor { rms });
-                            }
-                        } else {
-                            debug!(target = "audio_monitor", "RMS = {};  TRIGGER = false", rms);
-                        }
+            let mut cry_detector = context
+                .cry_detection
+                .clone()
+                .map(|config| CryDetector::new(config, spec.sample_rate, spec.channels));
+            let mut noise_floor = NoiseFloor::new(context.noise_floor_window);
+            let mut activity = ActivityTracker::new(context.activity.clone());
+            let samples_per_sec = spec.sample_rate.max(1) as f64 * spec.channels.max(1) as f64;
+
+            while !shutdown.load(Ordering::SeqCst) {
+                let read = match first_read
+                    .take()
+                    .map_or_else(|| source.read(&mut buffer), Ok)
+                {
+                    Ok(0) => {
+                        info!(target = "audio_monitor", "Audio source exhausted");
+                        break;
                     }
-                }
-                PulseAudioSampleFormat::F32le => {
-                    let mut buffer = vec![0f32; buffer_size]; // 300ms
-
-                    while !shutdown.load(Ordering::SeqCst) {
-                        if let Err(e) = pulse_connection.read(f32_to_u8_slice(&mut buffer)) {
-                            error!(
-                                target = "audio_monitor",
-                                "Error reading from pulseaudio stream: {}", e
-                            );
-                            return;
-                        }
-
-                        let rms = calculate_rms(&buffer);
-
-                        if context.rms_threshold.is_some_and(|rms_t| rms > rms_t) {
-                            debug!(target = "audio_monitor", "RMS = {}; TRIGGER = true", rms);
-
-                            if let Some(channel) = &channel {
-                                let _ = channel.send(Event::AudioMonitor { rms });
-                            }
-                        } else {
-                            debug!(target = "audio_monitor", "RMS = {};  TRIGGER = false", rms);
-                        }
+                    Ok(read) => read,
+                    Err(e) => {
+                        error!(
+                            target = "audio_monitor",
+                            "Error reading audio samples: {}", e
+                        );
+                        break;
                     }
-                }
-                PulseAudioSampleFormat::S32le => {
-                    let mut buffer = vec![0i32; buffer_size]; // 300ms
-                    let mut normalized_buffer = vec![0f32; buffer_size];
-
-                    while !shutdown.load(Ordering::SeqCst) {
-                        if let Err(e) = pulse_connection.read(i32_to_u8_slice(&mut buffer)) {
-                            error!(
-                                target = "audio_monitor",
-                                "Error reading from pulseaudio stream: {}", e
-                            );
-                            return;
-                        }
-
-                        buffer
-                            .iter()
-                            .map(|sample| *sample as f32 / 32768.0)
-                            .zip(normalized_buffer.iter_mut())
-                            .for_each(|(b, df)| *df = b);
-
-                        let rms = calculate_rms(&normalized_buffer);
-
-                        if context.rms_threshold.is_some_and(|rms_t| rms > rms_t) {
-                            debug!(target = "audio_monitor", "RMS = {}; TRIGGER = true", rms);
-
-                            if let Some(channel) = &channel {
-                                let _ = channel.send(Event::AudioMonitor { rms });
-                            }
-                        } else {
-                            debug!(target = "audio_monitor", "RMS = {};  TRIGGER = false", rms);
-                        }
+                };
+
+                let mut level = AudioLevel::measure(&buffer[..read]);
+                let elapsed = Duration::from_secs_f64(read as f64 / samples_per_sec);
+
+                // judged against the floor before this window pulls it up
+                let floor = noise_floor.level().unwrap_or(level.dbfs);
+
+                level.noise_floor_dbfs = noise_floor.update(
+                    level.dbfs,
+                    if activity.is_active() {
+                        elapsed / AUDIO_ACTIVITY_NOISE_FLOOR_SLOWDOWN
+                    } else {
+                        elapsed
+                    },
+                );
+                level.spl = context
+                    .calibration
+                    .map(|calibration| calibration.spl(level.dbfs));
+
+                let update = activity.update(&level, floor, elapsed);
+
+                debug!(
+                    target = "audio_monitor",
+                    "Level = {:.1} dBFS; PEAK = {:.1} dBFS; FLOOR = {:.1} dBFS; ACTIVE = {}",
+                    level.dbfs,
+                    level.peak_dbfs,
+                    level.noise_floor_dbfs,
+                    activity.is_active()
+                );
+
+                latest_level.set(level);
+
+                let cries = cry_detector
+                    .as_mut()
+                    .map(|detector| detector.process(&buffer[..read]))
+                    .unwrap_or_default();
+
+                for event in update
+                    .map(activity_event)
+                    .into_iter()
+                    .chain(cries.into_iter().map(cry_event))
+                {
+                    if let Some(channel) = &channel {
+                        let _ = channel.send(event);
                     }
                 }
-                _ => {
-                    error!(
-                        target = "audio_monitor",
-                        "Unsupported sample format: {:?}", context.sample_format
-                    );
+            }
+
+            // whatever stopped the capture, episodes in progress end with it
+            let ended = activity.finish().map(ActivityUpdate::Ended);
+            let cry = cry_detector
+                .as_mut()
+                .and_then(|detector| detector.finish())
+                .map(CryUpdate::Ended);
+
+            for event in ended
+                .map(activity_event)
+                .into_iter()
+                .chain(cry.map(cry_event))
+            {
+                if let Some(channel) = &channel {
+                    let _ = channel.send(event);
                 }
             }
-        }))
+        });
+
+        match opened_rx.recv() {
+            Ok(Ok(())) => Ok(handle),
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                Err(e)
+            }
+            Err(_) => Err(anyhow!("Audio capture thread exited unexpectedly")),
+        }
     }
 
     /// Stop monitor
@@ -300,38 +411,45 @@ impl AudioMonitor {
     pub fn is_running(&self) -> bool {
         self.handle.as_ref().is_some_and(|h| !h.is_finished())
     }
-}
 
-/// Cast `[i16]` as `[u8]`
-fn i16_to_u8_slice(slice: &mut [i16]) -> &mut [u8] {
-    let byte_len = 2 * slice.len();
-    unsafe { std::slice::from_raw_parts_mut(slice.as_mut_ptr().cast::<u8>(), byte_len) }
+    /// Handle on the most recent level measurement
+    pub fn level_watch(&self) -> AudioLevelWatch {
+        self.level.clone()
+    }
 }
 
-/// Cast `[f32]` as `[u8]`
-fn f32_to_u8_slice(slice: &mut [f32]) -> &mut [u8] {
-    let byte_len = 4 * slice.len();
-    unsafe { std::slice::from_raw_parts_mut(slice.as_mut_ptr().cast::<u8>(), byte_len) }
-}
+fn activity_event(update: ActivityUpdate) -> Event {
+    match update {
+        ActivityUpdate::Started(level) => {
+            info!(
+                target = "audio_monitor",
+                "Audio activity started at {:.1} dBFS", level.dbfs
+            );
 
-/// Cast `[i32]` as `[u8]`
-fn i32_to_u8_slice(slice: &mut [i32]) -> &mut [u8] {
-    let byte_len = 4 * slice.len();
-    unsafe { std::slice::from_raw_parts_mut(slice.as_mut_ptr().cast::<u8>(), byte_len) }
+            Event::AudioActivityStarted(level)
+        }
+        ActivityUpdate::Ongoing(summary) => Event::AudioActivityOngoing(summary),
+        ActivityUpdate::Ended(summary) => {
+            info!(
+                target = "audio_monitor",
+                "Audio activity ended after {:.1}s, peak {:.1} dBFS",
+                summary.duration,
+                summary.peak
+            );
+
+            Event::AudioActivityEnded(summary)
+        }
+    }
 }
 
-/// Calculate samples RMS
-fn calculate_rms(samples: &[f32]) -> f32 {
-    if samples.is_empty() {
-        return 0.0;
+fn cry_event(update: CryUpdate) -> Event {
+    match update {
+        CryUpdate::Started(cry) => Event::CryStarted {
+            confidence: cry.confidence,
+        },
+        CryUpdate::Ended(cry) => Event::CryEnded {
+            confidence: cry.confidence,
+            duration: cry.duration.as_secs_f32(),
+        },
     }
-
-    // Sum the squares of all samples
-    let sum_of_squares: f32 = samples.iter().map(|sample| sample * sample).sum();
-
-    // Calculate the mean of squares
-    let mean_of_squares = sum_of_squares / samples.len() as f32;
-
-    // Return the square root of the mean
-    mean_of_squares.sqrt()
 }
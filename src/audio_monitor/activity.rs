@@ -0,0 +1,307 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio_monitor::level::{to_dbfs, AudioLevel, AUDIO_MONITOR_DEFAULT_THRESHOLD_DB};
+use crate::serde_stuff::float_precision_two;
+
+/// Drop below the start threshold tolerated while active, dB
+pub const AUDIO_ACTIVITY_DEFAULT_HYSTERESIS_DB: f32 = 3.0;
+/// Loud time before an episode starts, filters out single knocks
+pub const AUDIO_ACTIVITY_DEFAULT_DEBOUNCE: Duration = Duration::from_millis(600);
+/// Quiet time before an episode ends, bridges breaths between cries
+pub const AUDIO_ACTIVITY_DEFAULT_HANGOVER: Duration = Duration::from_secs(5);
+pub const AUDIO_ACTIVITY_DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+/// The noise floor adapts this much slower during an episode, so it doesn't swallow long ones
+pub const AUDIO_ACTIVITY_NOISE_FLOOR_SLOWDOWN: u32 = 10;
+
+#[derive(Clone, Debug)]
+pub struct ActivityConfig {
+    /// Margin above the noise floor starting an episode, dB
+    pub threshold_db: f32,
+    /// Level below which nothing starts, whatever the noise floor
+    pub min_dbfs: Option<f32>,
+    pub hysteresis_db: f32,
+    pub debounce: Duration,
+    pub hangover: Duration,
+    /// Time between two `Ongoing` updates of an episode
+    pub summary_interval: Duration,
+}
+
+impl Default for ActivityConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: AUDIO_MONITOR_DEFAULT_THRESHOLD_DB,
+            min_dbfs: None,
+            hysteresis_db: AUDIO_ACTIVITY_DEFAULT_HYSTERESIS_DB,
+            debounce: AUDIO_ACTIVITY_DEFAULT_DEBOUNCE,
+            hangover: AUDIO_ACTIVITY_DEFAULT_HANGOVER,
+            summary_interval: AUDIO_ACTIVITY_DEFAULT_SUMMARY_INTERVAL,
+        }
+    }
+}
+
+/// Summary of an episode so far
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioActivity {
+    /// Seconds from the first to the last loud window
+    #[serde(with = "float_precision_two")]
+    pub duration: f32,
+    /// Highest sample peak, dBFS
+    #[serde(with = "float_precision_two")]
+    pub peak: f32,
+    /// Energy average of the loud windows, dBFS
+    #[serde(with = "float_precision_two")]
+    pub mean: f32,
+}
+
+/// Episode state change or ongoing summary
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActivityUpdate {
+    Started(AudioLevel),
+    Ongoing(AudioActivity),
+    Ended(AudioActivity),
+}
+
+#[derive(Debug, Default)]
+struct Episode {
+    /// Since the first loud window
+    elapsed: Duration,
+    /// Up to the end of the last loud window
+    duration: Duration,
+    loud: Duration,
+    /// Sum of squared RMS weighted by seconds
+    energy: f64,
+    peak_dbfs: Option<f32>,
+    quiet_for: Duration,
+    since_summary: Duration,
+}
+
+impl Episode {
+    fn add_loud(&mut self, level: &AudioLevel, elapsed: Duration) {
+        self.loud += elapsed;
+        self.energy += (level.rms as f64).powi(2) * elapsed.as_secs_f64();
+        self.peak_dbfs = Some(
+            self.peak_dbfs
+                .map_or(level.peak_dbfs, |peak| peak.max(level.peak_dbfs)),
+        );
+        self.duration = self.elapsed;
+        self.quiet_for = Duration::ZERO;
+    }
+
+    fn summary(&self) -> AudioActivity {
+        let mean_rms = if self.loud.is_zero() {
+            0.0
+        } else {
+            (self.energy / self.loud.as_secs_f64()).sqrt() as f32
+        };
+
+        AudioActivity {
+            duration: self.duration.as_secs_f32(),
+            peak: self.peak_dbfs.unwrap_or(to_dbfs(0.0)),
+            mean: to_dbfs(mean_rms),
+        }
+    }
+}
+
+/// Turns per window levels into activity episodes, loud enough for long enough to start, quiet
+/// enough for long enough to end
+#[derive(Debug)]
+pub struct ActivityTracker {
+    config: ActivityConfig,
+    /// Loud windows waiting for the debounce
+    pending: Option<Episode>,
+    active: Option<Episode>,
+}
+
+impl ActivityTracker {
+    pub fn new(config: ActivityConfig) -> Self {
+        Self {
+            config,
+            pending: None,
+            active: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Account for a window lasting `elapsed`, `noise_floor_dbfs` being the floor before it
+    pub fn update(
+        &mut self,
+        level: &AudioLevel,
+        noise_floor_dbfs: f32,
+        elapsed: Duration,
+    ) -> Option<ActivityUpdate> {
+        let audible = self
+            .config
+            .min_dbfs
+            .is_none_or(|min_dbfs| level.dbfs >= min_dbfs);
+        let margin = level.dbfs - noise_floor_dbfs;
+
+        let Some(episode) = self.active.as_mut() else {
+            if !audible || margin < self.config.threshold_db {
+                self.pending = None;
+
+                return None;
+            }
+
+            let episode = self.pending.get_or_insert_with(Episode::default);
+            episode.elapsed += elapsed;
+            episode.add_loud(level, elapsed);
+
+            if episode.elapsed < self.config.debounce {
+                return None;
+            }
+
+            self.active = self.pending.take();
+
+            return Some(ActivityUpdate::Started(level.clone()));
+        };
+
+        episode.elapsed += elapsed;
+        episode.since_summary += elapsed;
+
+        if audible && margin >= self.config.threshold_db - self.config.hysteresis_db {
+            episode.add_loud(level, elapsed);
+        } else {
+            episode.quiet_for += elapsed;
+
+            if episode.quiet_for >= self.config.hangover {
+                let summary = episode.summary();
+                self.active = None;
+
+                return Some(ActivityUpdate::Ended(summary));
+            }
+        }
+
+        if episode.since_summary >= self.config.summary_interval {
+            episode.since_summary = Duration::ZERO;
+
+            return Some(ActivityUpdate::Ongoing(episode.summary()));
+        }
+
+        None
+    }
+
+    /// End the episode in progress, e.g. when the audio stops, a pending one never started
+    pub fn finish(&mut self) -> Option<AudioActivity> {
+        self.pending = None;
+
+        self.active.take().map(|episode| episode.summary())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monitor window length
+    const STEP: Duration = Duration::from_millis(300);
+    const FLOOR: f32 = -60.0;
+
+    fn level(dbfs: f32) -> AudioLevel {
+        AudioLevel {
+            rms: 10f32.powf(dbfs / 20.0),
+            peak: 10f32.powf((dbfs + 6.0) / 20.0),
+            dbfs,
+            peak_dbfs: dbfs + 6.0,
+            noise_floor_dbfs: FLOOR,
+            spl: None,
+        }
+    }
+
+    /// Updates of `windows` windows at `dbfs`, with default timing
+    fn feed(tracker: &mut ActivityTracker, dbfs: f32, windows: usize) -> Vec<ActivityUpdate> {
+        (0..windows)
+            .filter_map(|_| tracker.update(&level(dbfs), FLOOR, STEP))
+            .collect()
+    }
+
+    fn started(tracker: &mut ActivityTracker) {
+        let updates = feed(tracker, -40.0, 2);
+
+        assert!(
+            matches!(updates.as_slice(), [ActivityUpdate::Started(_)]),
+            "{:?}",
+            updates
+        );
+    }
+
+    #[test]
+    fn knock_is_not_activity() {
+        let mut tracker = ActivityTracker::new(ActivityConfig::default());
+
+        assert_eq!(feed(&mut tracker, -40.0, 1), vec![]);
+        assert_eq!(feed(&mut tracker, -60.0, 20), vec![]);
+        assert!(!tracker.is_active());
+    }
+
+    #[test]
+    fn start_summaries_and_end() {
+        let mut tracker = ActivityTracker::new(ActivityConfig::default());
+        started(&mut tracker);
+
+        // a summary every 10 seconds of activity
+        let updates = feed(&mut tracker, -40.0, 34);
+        assert!(
+            matches!(updates.as_slice(), [ActivityUpdate::Ongoing(summary)] if summary.duration > 10.0),
+            "{:?}",
+            updates
+        );
+
+        // over after 5 quiet seconds
+        assert_eq!(feed(&mut tracker, -60.0, 16), vec![]);
+
+        let updates = feed(&mut tracker, -60.0, 1);
+        let [ActivityUpdate::Ended(summary)] = updates.as_slice() else {
+            panic!("Unexpected updates {:?}", updates);
+        };
+
+        assert!((summary.duration - 10.8).abs() < 1e-3, "{:?}", summary);
+        assert!((summary.peak - -34.0).abs() < 1e-3, "{:?}", summary);
+        assert!((summary.mean - -40.0).abs() < 1e-3, "{:?}", summary);
+        assert!(!tracker.is_active());
+    }
+
+    #[test]
+    fn hysteresis_dip_keeps_going() {
+        let mut tracker = ActivityTracker::new(ActivityConfig::default());
+        started(&mut tracker);
+
+        // 7.5 dB over the floor, under the 10 dB start threshold but within the 3 dB hysteresis
+        assert_eq!(feed(&mut tracker, -52.5, 30), vec![]);
+        assert!(tracker.is_active());
+
+        let updates = feed(&mut tracker, -55.0, 17);
+        assert!(
+            matches!(updates.as_slice(), [.., ActivityUpdate::Ended(_)]),
+            "{:?}",
+            updates
+        );
+    }
+
+    #[test]
+    fn finish_pending() {
+        let mut tracker = ActivityTracker::new(ActivityConfig::default());
+
+        assert_eq!(feed(&mut tracker, -40.0, 1), vec![]);
+        assert_eq!(tracker.finish(), None);
+
+        // the debounce starts over
+        assert_eq!(feed(&mut tracker, -40.0, 1), vec![]);
+    }
+
+    #[test]
+    fn finish_active() {
+        let mut tracker = ActivityTracker::new(ActivityConfig::default());
+        started(&mut tracker);
+
+        let summary = tracker.finish().unwrap();
+
+        assert!((summary.duration - 0.6).abs() < 1e-3, "{:?}", summary);
+        assert!(!tracker.is_active());
+        assert_eq!(tracker.finish(), None);
+    }
+}
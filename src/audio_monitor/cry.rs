@@ -0,0 +1,592 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use tracing::{debug, info};
+
+pub const CRY_DEFAULT_SENSITIVITY: f32 = 0.5;
+pub const CRY_DEFAULT_MIN_BURSTS: u32 = 2;
+/// Fundamental frequency range of infant cries
+pub const CRY_MIN_PITCH_HZ: f32 = 250.0;
+pub const CRY_MAX_PITCH_HZ: f32 = 600.0;
+
+/// Analysis frame, long enough for a few periods of the lowest pitch
+const CRY_FRAME_MS: u32 = 50;
+/// Band holding the fundamental and the strongest harmonics of a cry
+const CRY_BAND_HZ: (f32, f32) = (CRY_MIN_PITCH_HZ, 4_000.0);
+/// Share of the frame energy expected within `CRY_BAND_HZ`
+const CRY_MIN_BAND_RATIO: f32 = 0.5;
+/// Quiet frames tolerated inside a burst
+const CRY_BURST_GAP_FRAMES: u64 = 2;
+/// Cry bursts last between a short wail and a long exhalation, barks are shorter, machines longer
+const CRY_MIN_BURST: Duration = Duration::from_millis(250);
+const CRY_MAX_BURST: Duration = Duration::from_secs(4);
+/// Pitch spread within a burst, relative to its lowest pitch, electronic beeps hold a flat tone
+const CRY_MIN_PITCH_VARIATION: f32 = 0.03;
+/// Silence ending a crying episode
+const CRY_MAX_PAUSE: Duration = Duration::from_secs(3);
+/// Bursts kept to estimate the rhythm
+const CRY_RHYTHM_BURSTS: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct CryConfig {
+    /// `0.0..=1.0`, higher accepts quieter and less tonal sounds
+    pub sensitivity: f32,
+    /// Bursts in a row before reporting a cry
+    pub min_bursts: u32,
+}
+
+impl Default for CryConfig {
+    fn default() -> Self {
+        Self {
+            sensitivity: CRY_DEFAULT_SENSITIVITY,
+            min_bursts: CRY_DEFAULT_MIN_BURSTS,
+        }
+    }
+}
+
+impl CryConfig {
+    /// Normalized autocorrelation peak needed for a voiced frame
+    fn min_harmonicity(&self) -> f32 {
+        0.8 - self.sensitivity.clamp(0.0, 1.0) * 0.35
+    }
+
+    /// Frame loudness needed for a voiced frame
+    fn min_rms(&self) -> f32 {
+        0.002 + (1.0 - self.sensitivity.clamp(0.0, 1.0)) * 0.03
+    }
+}
+
+/// Short-time features of a single frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CryFeatures {
+    pub rms: f32,
+    /// Fundamental frequency, `None` when no period was found within the cry range
+    pub pitch: Option<f32>,
+    /// Normalized autocorrelation at the pitch period, `1.0` for a pure periodic signal
+    pub harmonicity: f32,
+    /// Share of the energy within the cry band
+    pub band_ratio: f32,
+}
+
+/// Crying episode, as confirmed so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CryDetection {
+    /// `0.0..=1.0`
+    pub confidence: f32,
+    /// From the first to the last burst of the episode
+    pub duration: Duration,
+}
+
+/// Crying episode state change, sent once each way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CryUpdate {
+    /// Enough bursts in a row to call it crying
+    Started(CryDetection),
+    /// No cry burst for a while
+    Ended(CryDetection),
+}
+
+/// Voiced frames in a row, positions in frames
+#[derive(Clone, Copy, Debug)]
+struct Burst {
+    start: u64,
+    end: u64,
+    voiced: u32,
+    harmonicity: f32,
+    min_pitch: f32,
+    max_pitch: f32,
+}
+
+impl Burst {
+    fn mean_harmonicity(&self) -> f32 {
+        self.harmonicity / self.voiced.max(1) as f32
+    }
+
+    fn pitch_variation(&self) -> f32 {
+        (self.max_pitch - self.min_pitch) / self.min_pitch.max(f32::EPSILON)
+    }
+}
+
+/// Looks for the pitch, harmonicity and rhythmic bursts of a crying baby in interleaved samples
+pub struct CryDetector {
+    config: CryConfig,
+    sample_rate: u32,
+    channels: u8,
+    frame_len: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Normalized autocorrelation of the window, divided out of the frame autocorrelation
+    window_acf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    /// Mono samples short of a full frame
+    pending: Vec<f32>,
+    position: u64,
+    burst: Option<Burst>,
+    /// Too long to be a cry, ignored until it ends
+    burst_rejected: bool,
+    bursts: Vec<Burst>,
+    episode_start: Option<u64>,
+    episode_bursts: u32,
+    /// Latest state of a confirmed episode
+    crying: Option<CryDetection>,
+}
+
+impl std::fmt::Debug for CryDetector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CryDetector")
+            .field("config", &self.config)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels)
+            .field("position", &self.position)
+            .field("bursts", &self.bursts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CryDetector {
+    pub fn new(config: CryConfig, sample_rate: u32, channels: u8) -> Self {
+        let sample_rate = sample_rate.max(1);
+        let frame_len = (sample_rate as usize * CRY_FRAME_MS as usize / 1000).max(16);
+        // zero padded so the autocorrelation doesn't wrap around
+        let fft_len = (2 * frame_len).next_power_of_two();
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_len);
+        let ifft = planner.plan_fft_inverse(fft_len);
+
+        let window: Vec<f32> = (0..frame_len)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / (frame_len - 1) as f32;
+
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+
+        let mut detector = Self {
+            config,
+            sample_rate,
+            channels,
+            frame_len,
+            fft,
+            ifft,
+            window_acf: vec![1.0; fft_len],
+            window,
+            spectrum: vec![Complex::default(); fft_len],
+            pending: Vec::with_capacity(frame_len * 2),
+            position: 0,
+            burst: None,
+            burst_rejected: false,
+            bursts: Vec::new(),
+            episode_start: None,
+            episode_bursts: 0,
+            crying: None,
+        };
+
+        let ones = vec![1.0; frame_len];
+        detector.autocorrelate(&ones);
+        let r0 = detector.spectrum[0].re.max(f32::EPSILON);
+        detector.window_acf = detector
+            .spectrum
+            .iter()
+            .map(|c| (c.re / r0).max(f32::EPSILON))
+            .collect();
+
+        detector
+    }
+
+    /// Feed interleaved samples, returns episode starts and ends in order
+    pub fn process(&mut self, samples: &[f32]) -> Vec<CryUpdate> {
+        let channels = self.channels.max(1) as usize;
+
+        self.pending.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+
+        let mut updates = Vec::new();
+
+        while self.pending.len() >= self.frame_len {
+            let frame: Vec<f32> = self.pending.drain(..self.frame_len).collect();
+            let features = self.analyze(&frame);
+
+            updates.extend(self.update(&features));
+
+            self.position += 1;
+        }
+
+        updates
+    }
+
+    /// End the episode in progress, e.g. when the audio stops
+    pub fn finish(&mut self) -> Option<CryDetection> {
+        self.burst = None;
+        self.burst_rejected = false;
+
+        self.end_episode()
+    }
+
+    /// Features of a single mono frame of `frame_len` samples
+    pub fn analyze(&mut self, frame: &[f32]) -> CryFeatures {
+        let len = frame.len().min(self.frame_len);
+        let mean = frame[..len].iter().sum::<f32>() / len.max(1) as f32;
+        let centered: Vec<f32> = frame[..len].iter().map(|s| s - mean).collect();
+        let rms = (centered.iter().map(|s| s * s).sum::<f32>() / len.max(1) as f32).sqrt();
+
+        if rms < 1e-6 {
+            return CryFeatures {
+                rms,
+                ..Default::default()
+            };
+        }
+
+        let band_ratio = self.autocorrelate(&centered);
+        let r0 = self.spectrum[0].re;
+        if r0 <= 0.0 {
+            return CryFeatures {
+                rms,
+                band_ratio,
+                ..Default::default()
+            };
+        }
+
+        let acf = |lag: usize| self.spectrum[lag].re / r0 / self.window_acf[lag];
+        let rate = self.sample_rate as f32;
+        let min_lag = ((rate / CRY_MAX_PITCH_HZ).floor() as usize).max(2);
+        let max_lag = ((rate / CRY_MIN_PITCH_HZ).ceil() as usize).min(len / 2);
+
+        // highest local maximum within the cry pitch range
+        let Some((lag, peak)) = (min_lag..max_lag)
+            .filter(|&lag| acf(lag) >= acf(lag - 1) && acf(lag) >= acf(lag + 1))
+            .map(|lag| (lag, acf(lag)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            return CryFeatures {
+                rms,
+                band_ratio,
+                ..Default::default()
+            };
+        };
+
+        // a peak at half the lag means the period is really shorter, a whistle rather than a cry
+        let half = lag / 2;
+        let pitch = if half >= 2 && acf(half) > 0.9 * peak {
+            None
+        } else {
+            let (a, b, c) = (acf(lag - 1), peak, acf(lag + 1));
+            let denominator = a - 2.0 * b + c;
+            let offset = if denominator.abs() > f32::EPSILON {
+                (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            };
+
+            Some(rate / (lag as f32 + offset))
+        };
+
+        CryFeatures {
+            rms,
+            pitch,
+            harmonicity: peak.clamp(0.0, 1.0),
+            band_ratio,
+        }
+    }
+
+    /// Windowed autocorrelation of `frame` into `spectrum`, returns the cry band energy share
+    fn autocorrelate(&mut self, frame: &[f32]) -> f32 {
+        for (i, bin) in self.spectrum.iter_mut().enumerate() {
+            *bin = match (frame.get(i), self.window.get(i)) {
+                (Some(sample), Some(weight)) => Complex::new(sample * weight, 0.0),
+                _ => Complex::default(),
+            };
+        }
+
+        self.fft.process(&mut self.spectrum);
+
+        let fft_len = self.spectrum.len();
+        let bin_hz = self.sample_rate as f32 / fft_len as f32;
+        let mut total = 0.0;
+        let mut band = 0.0;
+
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+
+            if k > 0 && k <= fft_len / 2 {
+                let frequency = k as f32 * bin_hz;
+
+                total += power;
+                if (CRY_BAND_HZ.0..=CRY_BAND_HZ.1).contains(&frequency) {
+                    band += power;
+                }
+            }
+
+            *bin = Complex::new(power, 0.0);
+        }
+
+        // Wiener-Khinchin, the inverse transform of the power spectrum
+        self.ifft.process(&mut self.spectrum);
+
+        if total > 0.0 {
+            band / total
+        } else {
+            0.0
+        }
+    }
+
+    fn frames_for(&self, duration: Duration) -> u64 {
+        let frame_secs = self.frame_len as f64 / self.sample_rate as f64;
+
+        (duration.as_secs_f64() / frame_secs).round().max(1.0) as u64
+    }
+
+    fn frames_duration(&self, frames: u64) -> Duration {
+        Duration::from_secs_f64(frames as f64 * self.frame_len as f64 / self.sample_rate as f64)
+    }
+
+    fn is_voiced(&self, features: &CryFeatures) -> bool {
+        features.rms >= self.config.min_rms()
+            && features.harmonicity >= self.config.min_harmonicity()
+            && features.band_ratio >= CRY_MIN_BAND_RATIO
+            && features
+                .pitch
+                .is_some_and(|pitch| (CRY_MIN_PITCH_HZ..=CRY_MAX_PITCH_HZ).contains(&pitch))
+    }
+
+    /// Track bursts of voiced frames and the episode they form
+    fn update(&mut self, features: &CryFeatures) -> Option<CryUpdate> {
+        let position = self.position;
+
+        if self.is_voiced(features) {
+            let burst = self.burst.get_or_insert(Burst {
+                start: position,
+                end: position,
+                voiced: 0,
+                harmonicity: 0.0,
+                min_pitch: f32::MAX,
+                max_pitch: 0.0,
+            });
+            let pitch = features.pitch.unwrap_or_default();
+            burst.end = position;
+            burst.voiced += 1;
+            burst.harmonicity += features.harmonicity;
+            burst.min_pitch = burst.min_pitch.min(pitch);
+            burst.max_pitch = burst.max_pitch.max(pitch);
+
+            let length = burst.end - burst.start + 1;
+            if !self.burst_rejected && length > self.frames_for(CRY_MAX_BURST) {
+                debug!(
+                    target = "audio_monitor",
+                    "Steady tone too long for a cry, ignoring it"
+                );
+
+                self.burst_rejected = true;
+
+                return self.end_episode().map(CryUpdate::Ended);
+            }
+
+            return None;
+        }
+
+        if let Some(burst) = self.burst {
+            if position - burst.end < CRY_BURST_GAP_FRAMES {
+                return None;
+            }
+
+            self.burst = None;
+
+            let rejected = std::mem::take(&mut self.burst_rejected);
+            if rejected
+                || burst.end - burst.start + 1 < self.frames_for(CRY_MIN_BURST)
+                || burst.pitch_variation() < CRY_MIN_PITCH_VARIATION
+            {
+                return None;
+            }
+
+            return self.push_burst(burst);
+        }
+
+        if self
+            .bursts
+            .last()
+            .is_some_and(|last| position - last.end > self.frames_for(CRY_MAX_PAUSE))
+        {
+            return self.end_episode().map(CryUpdate::Ended);
+        }
+
+        None
+    }
+
+    fn push_burst(&mut self, burst: Burst) -> Option<CryUpdate> {
+        debug!(
+            target = "audio_monitor",
+            "Cry burst: {:?} harmonicity {:.2}",
+            self.frames_duration(burst.end - burst.start + 1),
+            burst.mean_harmonicity()
+        );
+
+        let episode_start = *self.episode_start.get_or_insert(burst.start);
+        self.episode_bursts += 1;
+
+        self.bursts.push(burst);
+        if self.bursts.len() > CRY_RHYTHM_BURSTS {
+            self.bursts.remove(0);
+        }
+
+        if self.episode_bursts < self.config.min_bursts.max(1) {
+            return None;
+        }
+
+        let detection = CryDetection {
+            confidence: self.confidence(),
+            duration: self.frames_duration(burst.end + 1 - episode_start),
+        };
+
+        if self.crying.replace(detection).is_some() {
+            return None;
+        }
+
+        info!(
+            target = "audio_monitor",
+            "Crying detected, confidence {:.2}", detection.confidence
+        );
+
+        Some(CryUpdate::Started(detection))
+    }
+
+    /// Tonality, steadiness of the burst rhythm and number of bursts, each within `0.0..=1.0`
+    fn confidence(&self) -> f32 {
+        let min_harmonicity = self.config.min_harmonicity();
+        let harmonicity = self.bursts.iter().map(Burst::mean_harmonicity).sum::<f32>()
+            / self.bursts.len().max(1) as f32;
+        let tonality = ((harmonicity - min_harmonicity) / (1.0 - min_harmonicity)).clamp(0.0, 1.0);
+
+        let periods: Vec<f32> = self
+            .bursts
+            .windows(2)
+            .map(|pair| (pair[1].start - pair[0].start) as f32)
+            .collect();
+        let regularity = if periods.len() < 2 {
+            1.0
+        } else {
+            let mean = periods.iter().sum::<f32>() / periods.len() as f32;
+            let variance =
+                periods.iter().map(|p| (p - mean).powi(2)).sum::<f32>() / periods.len() as f32;
+
+            1.0 - (variance.sqrt() / mean.max(f32::EPSILON)).min(1.0)
+        };
+
+        let count = (self.bursts.len() as f32 / (self.config.min_bursts + 2) as f32).min(1.0);
+
+        (0.4 * tonality + 0.3 * regularity + 0.3 * count).clamp(0.0, 1.0)
+    }
+
+    /// Returns the episode if it was confirmed crying
+    fn end_episode(&mut self) -> Option<CryDetection> {
+        self.bursts.clear();
+        self.episode_start = None;
+        self.episode_bursts = 0;
+
+        let crying = self.crying.take()?;
+
+        info!(
+            target = "audio_monitor",
+            "Crying stopped after {:.1}s",
+            crying.duration.as_secs_f32()
+        );
+
+        Some(crying)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_monitor::source::file::FileSource;
+    use crate::audio_monitor::source::{AudioSource, AudioSpec};
+    use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
+    use std::path::PathBuf;
+
+    /// Labelled recordings, `cry` ones should be detected, `other` ones should stay quiet
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/audio")
+            .join(name)
+    }
+
+    fn run(name: &str) -> Vec<CryUpdate> {
+        let mut source = FileSource::open(
+            fixture(name),
+            AudioSpec::new(FfmpegAudioSampleFormat::S16le, 16_000, 1),
+        )
+        .unwrap()
+        .with_realtime(false);
+
+        let spec = source.spec().clone();
+        let mut detector = CryDetector::new(CryConfig::default(), spec.sample_rate, spec.channels);
+        let mut samples = vec![0f32; spec.samples_for(100)];
+        let mut updates = Vec::new();
+
+        loop {
+            let read = source.read(&mut samples).unwrap();
+            if read == 0 {
+                break;
+            }
+
+            updates.extend(detector.process(&samples[..read]));
+        }
+
+        updates.extend(detector.finish().map(CryUpdate::Ended));
+
+        updates
+    }
+
+    fn assert_one_episode(name: &str) {
+        let updates = run(name);
+
+        assert_eq!(updates.len(), 2, "{}: {:?}", name, updates);
+        assert!(
+            matches!(updates[0], CryUpdate::Started(cry) if cry.confidence > 0.0),
+            "{}: {:?}",
+            name,
+            updates
+        );
+        assert!(
+            matches!(updates[1], CryUpdate::Ended(cry) if cry.duration > Duration::ZERO),
+            "{}: {:?}",
+            name,
+            updates
+        );
+    }
+
+    #[test]
+    fn detects_crying() {
+        assert_one_episode("cry/cry_16k.wav");
+        assert_one_episode("cry/cry_long_bursts.wav");
+    }
+
+    #[test]
+    fn ignores_other_sounds() {
+        for name in [
+            "other/adult_voice.wav",
+            "other/beeps.wav",
+            "other/dog_bark.wav",
+            "other/door_slam.wav",
+            "other/silence.wav",
+            "other/steady_tone.wav",
+            "other/white_noise.wav",
+        ] {
+            assert_eq!(run(name), Vec::new(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn finish_without_crying() {
+        let mut detector = CryDetector::new(CryConfig::default(), 16_000, 1);
+
+        assert!(detector.process(&[0.0; 16_000]).is_empty());
+        assert_eq!(detector.finish(), None);
+    }
+}
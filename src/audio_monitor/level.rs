@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::serde_stuff::float_precision_two;
+
+/// Reported for digital silence instead of minus infinity
+pub const AUDIO_LEVEL_MIN_DBFS: f32 = -120.0;
+/// Margin above the noise floor triggering the monitor
+pub const AUDIO_MONITOR_DEFAULT_THRESHOLD_DB: f32 = 10.0;
+/// Time constant of the noise floor when the room gets louder
+pub const AUDIO_MONITOR_DEFAULT_NOISE_FLOOR_WINDOW: Duration = Duration::from_secs(60);
+
+/// The floor drops this much faster than it rises, a quiet moment is the ambient level
+const AUDIO_NOISE_FLOOR_FALL_RATE: f32 = 10.0;
+
+/// Amplitude relative to full scale, `1.0` being 0 dBFS
+pub fn to_dbfs(amplitude: f32) -> f32 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(AUDIO_LEVEL_MIN_DBFS)
+    } else {
+        AUDIO_LEVEL_MIN_DBFS
+    }
+}
+
+/// Reference level measured with a sound level meter next to the microphone
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AudioCalibration {
+    /// Level reported by the monitor
+    pub dbfs: f32,
+    /// Sound pressure level read on the meter, dB SPL
+    pub spl: f32,
+}
+
+impl AudioCalibration {
+    pub fn new(dbfs: f32, spl: f32) -> Self {
+        Self { dbfs, spl }
+    }
+
+    /// Sound pressure level matching a dBFS level
+    pub fn spl(&self, dbfs: f32) -> f32 {
+        dbfs - self.dbfs + self.spl
+    }
+}
+
+/// Loudness of a window of samples
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AudioLevel {
+    #[serde(with = "float_precision_two")]
+    pub rms: f32,
+    #[serde(with = "float_precision_two")]
+    pub peak: f32,
+    #[serde(with = "float_precision_two")]
+    pub dbfs: f32,
+    #[serde(with = "float_precision_two")]
+    pub peak_dbfs: f32,
+    /// Ambient level the trigger margin applies to
+    #[serde(with = "float_precision_two")]
+    pub noise_floor_dbfs: f32,
+    /// Sound pressure level in dB SPL, only when calibrated
+    pub spl: Option<f32>,
+}
+
+impl AudioLevel {
+    /// RMS and peak of normalized samples, the noise floor is left to the caller
+    pub fn measure(samples: &[f32]) -> Self {
+        let rms = if samples.is_empty() {
+            0.0
+        } else {
+            (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32)
+                .sqrt()
+        };
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+
+        Self {
+            rms,
+            peak,
+            dbfs: to_dbfs(rms),
+            peak_dbfs: to_dbfs(peak),
+            noise_floor_dbfs: to_dbfs(rms),
+            spl: None,
+        }
+    }
+}
+
+/// Ambient level as an exponential moving average of dBFS levels, rising slowly and falling fast
+#[derive(Clone, Debug)]
+pub struct NoiseFloor {
+    window: Duration,
+    level: Option<f32>,
+}
+
+impl NoiseFloor {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            level: None,
+        }
+    }
+
+    /// Current floor, `None` until the first update
+    pub fn level(&self) -> Option<f32> {
+        self.level
+    }
+
+    /// Account for a level measured over `elapsed`, returns the new floor
+    pub fn update(&mut self, dbfs: f32, elapsed: Duration) -> f32 {
+        let Some(level) = self.level else {
+            self.level = Some(dbfs);
+
+            return dbfs;
+        };
+
+        let mut alpha =
+            (elapsed.as_secs_f32() / self.window.as_secs_f32().max(f32::EPSILON)).clamp(0.0, 1.0);
+        if dbfs < level {
+            alpha = (alpha * AUDIO_NOISE_FLOOR_FALL_RATE).min(1.0);
+        }
+
+        let level = level + alpha * (dbfs - level);
+        self.level = Some(level);
+
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} isn't {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn noise_floor_seeds_from_first_level() {
+        let mut floor = NoiseFloor::new(Duration::from_secs(60));
+
+        assert_eq!(floor.level(), None);
+        assert_eq!(floor.update(-45.0, Duration::from_millis(300)), -45.0);
+        assert_eq!(floor.level(), Some(-45.0));
+    }
+
+    #[test]
+    fn noise_floor_rises_slower_than_it_falls() {
+        let window = Duration::from_secs(60);
+        let elapsed = Duration::from_secs(3);
+
+        let mut rising = NoiseFloor::new(window);
+        rising.update(-60.0, elapsed);
+
+        let mut falling = NoiseFloor::new(window);
+        falling.update(-40.0, elapsed);
+
+        // 5% of the way up, 50% of the way down
+        assert_near(rising.update(-40.0, elapsed), -59.0);
+        assert_near(falling.update(-60.0, elapsed), -50.0);
+    }
+
+    #[test]
+    fn noise_floor_catches_up_past_its_window() {
+        let mut floor = NoiseFloor::new(Duration::from_secs(10));
+        floor.update(-60.0, Duration::ZERO);
+
+        assert_eq!(floor.update(-30.0, Duration::from_secs(20)), -30.0);
+        assert_eq!(floor.update(-70.0, Duration::from_secs(2)), -70.0);
+    }
+}
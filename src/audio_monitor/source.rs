@@ -0,0 +1,193 @@
+use std::io::Read;
+use std::process::{Child, ChildStdout};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::ffmpeg::audio::{FfmpegAudioDeviceType, FfmpegAudioSampleFormat};
+
+pub mod alsa;
+pub mod file;
+pub mod pipewire;
+pub mod pulse;
+
+/// Where monitored audio comes from
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioBackend {
+    /// `arecord`, use a `dsnoop` device to share the microphone with the stream
+    Alsa,
+    #[default]
+    Pulse,
+    /// `pw-record`, native PipeWire capture
+    Pipewire,
+    /// WAV or raw samples replay
+    File,
+}
+
+impl From<&FfmpegAudioDeviceType> for AudioBackend {
+    fn from(value: &FfmpegAudioDeviceType) -> Self {
+        match value {
+            FfmpegAudioDeviceType::Alsa => Self::Alsa,
+            FfmpegAudioDeviceType::Pulse => Self::Pulse,
+        }
+    }
+}
+
+/// Interleaved samples layout
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioSpec {
+    pub format: FfmpegAudioSampleFormat,
+    pub sample_rate: u32,
+    pub channels: u8,
+}
+
+impl AudioSpec {
+    pub fn new(format: FfmpegAudioSampleFormat, sample_rate: u32, channels: u8) -> Self {
+        Self {
+            format,
+            sample_rate,
+            channels,
+        }
+    }
+
+    /// Bytes per sample of a single channel
+    pub fn sample_size(&self) -> usize {
+        match self.format {
+            FfmpegAudioSampleFormat::S16le => 2,
+            FfmpegAudioSampleFormat::F32le | FfmpegAudioSampleFormat::S32le => 4,
+        }
+    }
+
+    /// Interleaved samples covering the given milliseconds
+    pub fn samples_for(&self, millis: u32) -> usize {
+        (self.sample_rate as usize * millis as usize / 1000).max(1) * self.channels.max(1) as usize
+    }
+}
+
+/// Blocking audio capture
+pub trait AudioSource {
+    /// Layout of the captured samples, might differ from the requested one for files
+    fn spec(&self) -> &AudioSpec;
+
+    /// Fill `samples` with interleaved samples normalized to `-1.0..=1.0`
+    ///
+    /// Blocks until the buffer is full, returns fewer samples only at the end of the stream.
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize>;
+}
+
+/// Convert little endian samples to normalized floats, trailing partial samples are ignored
+pub fn decode_samples(
+    format: &FfmpegAudioSampleFormat,
+    bytes: &[u8],
+    samples: &mut [f32],
+) -> usize {
+    match format {
+        FfmpegAudioSampleFormat::S16le => bytes
+            .chunks_exact(2)
+            .zip(samples.iter_mut())
+            .map(|(b, s)| *s = i16::from_le_bytes([b[0], b[1]]) as f32 / 32_768.0)
+            .count(),
+        FfmpegAudioSampleFormat::S32le => bytes
+            .chunks_exact(4)
+            .zip(samples.iter_mut())
+            .map(|(b, s)| {
+                *s = i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0
+            })
+            .count(),
+        FfmpegAudioSampleFormat::F32le => bytes
+            .chunks_exact(4)
+            .zip(samples.iter_mut())
+            .map(|(b, s)| *s = f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .count(),
+    }
+}
+
+/// Read and decode until `samples` is full or the reader is exhausted
+pub(crate) fn read_samples(
+    reader: &mut impl Read,
+    spec: &AudioSpec,
+    buffer: &mut Vec<u8>,
+    samples: &mut [f32],
+) -> Result<usize> {
+    buffer.resize(samples.len() * spec.sample_size(), 0);
+
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(decode_samples(&spec.format, &buffer[..filled], samples))
+}
+
+/// Raw samples read from the output of a capture command
+#[derive(Debug)]
+pub(crate) struct CommandSource {
+    name: &'static str,
+    child: Child,
+    stdout: ChildStdout,
+    spec: AudioSpec,
+    buffer: Vec<u8>,
+}
+
+impl CommandSource {
+    pub(crate) fn spawn(
+        name: &'static str,
+        command: &mut std::process::Command,
+        spec: AudioSpec,
+    ) -> Result<Self> {
+        debug!(target = "audio_monitor", "Spawning {}: {:?}", name, command);
+
+        let mut child = command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn child process {}: {}", name, e))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("Failed to capture child process output for {}", name))?;
+
+        Ok(Self {
+            name,
+            child,
+            stdout,
+            spec,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl AudioSource for CommandSource {
+    fn spec(&self) -> &AudioSpec {
+        &self.spec
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize> {
+        let read = read_samples(&mut self.stdout, &self.spec, &mut self.buffer, samples)?;
+
+        // capture never ends on its own, an early end means it died
+        if read < samples.len() {
+            let status = self.child.wait()?;
+
+            return Err(anyhow!("Process {} exited: {}", self.name, status));
+        }
+
+        Ok(read)
+    }
+}
+
+impl Drop for CommandSource {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,57 @@
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::audio_monitor::source::{AudioSource, AudioSpec, CommandSource};
+use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
+
+pub static ALSA_RECORD_BIN: &str = "arecord";
+pub static ALSA_DEFAULT_DEVICE: &str = "default";
+
+/// Direct ALSA capture through `arecord`
+///
+/// Hardware devices (`hw:`) can only be opened once, share the microphone with the stream
+/// through a `dsnoop` device.
+#[derive(Debug)]
+pub struct AlsaSource {
+    inner: CommandSource,
+}
+
+impl AlsaSource {
+    pub fn open(spec: AudioSpec, device: Option<&str>) -> Result<Self> {
+        let format = match spec.format {
+            FfmpegAudioSampleFormat::S16le => "S16_LE",
+            FfmpegAudioSampleFormat::S32le => "S32_LE",
+            FfmpegAudioSampleFormat::F32le => "FLOAT_LE",
+        };
+
+        let mut command = Command::new(ALSA_RECORD_BIN);
+        command.args([
+            "-q",
+            "-D",
+            device.unwrap_or(ALSA_DEFAULT_DEVICE),
+            "-t",
+            "raw",
+            "-f",
+            format,
+            "-r",
+            &spec.sample_rate.to_string(),
+            "-c",
+            &spec.channels.to_string(),
+        ]);
+
+        Ok(Self {
+            inner: CommandSource::spawn(ALSA_RECORD_BIN, &mut command, spec)?,
+        })
+    }
+}
+
+impl AudioSource for AlsaSource {
+    fn spec(&self) -> &AudioSpec {
+        self.inner.spec()
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize> {
+        self.inner.read(samples)
+    }
+}
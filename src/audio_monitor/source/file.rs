@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Take};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use crate::audio_monitor::source::{read_samples, AudioSource, AudioSpec};
+use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
+
+const WAV_FORMAT_PCM: u16 = 1;
+const WAV_FORMAT_FLOAT: u16 = 3;
+const WAV_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Replay of a recording, WAV files describe their own layout, anything else is taken as raw
+/// samples in the requested layout
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+    reader: Take<BufReader<File>>,
+    spec: AudioSpec,
+    buffer: Vec<u8>,
+    realtime: bool,
+    started_at: Option<Instant>,
+    samples_read: u64,
+}
+
+impl FileSource {
+    pub fn open(path: PathBuf, spec: AudioSpec) -> Result<Self> {
+        let mut reader = BufReader::new(
+            File::open(&path).map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?,
+        );
+
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+
+        let (spec, data_size) = if is_wav {
+            read_wav_header(&mut reader, &path)?
+        } else {
+            (spec, None)
+        };
+
+        // streamed WAV files might not know their size
+        let reader = reader.take(data_size.unwrap_or(u64::MAX));
+
+        Ok(Self {
+            path,
+            reader,
+            spec,
+            buffer: Vec::new(),
+            realtime: true,
+            started_at: None,
+            samples_read: 0,
+        })
+    }
+
+    /// Pace reads like a live capture, otherwise replay as fast as possible
+    pub fn with_realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AudioSource for FileSource {
+    fn spec(&self) -> &AudioSpec {
+        &self.spec
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize> {
+        let started_at = *self.started_at.get_or_insert_with(Instant::now);
+        let read = read_samples(&mut self.reader, &self.spec, &mut self.buffer, samples)?;
+
+        self.samples_read += read as u64;
+
+        if self.realtime {
+            let frames = self.samples_read / self.spec.channels.max(1) as u64;
+            let due = started_at
+                + Duration::from_secs_f64(frames as f64 / self.spec.sample_rate.max(1) as f64);
+
+            std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Parse the header up to the samples, returns their layout and byte size if known
+fn read_wav_header(reader: &mut impl Read, path: &Path) -> Result<(AudioSpec, Option<u64>)> {
+    let invalid = |reason: &str| anyhow!("Invalid WAV file {}: {}", path.display(), reason);
+
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+
+    let mut spec = None;
+
+    loop {
+        let mut header = [0u8; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| invalid("no data chunk"))?;
+
+        let id = &header[0..4];
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        if id == b"data" {
+            let spec = spec.ok_or_else(|| invalid("data before fmt chunk"))?;
+            let size = (size != 0 && size != u32::MAX).then_some(size as u64);
+
+            return Ok((spec, size));
+        }
+
+        // chunks are padded to an even size
+        let mut chunk = vec![0u8; size as usize + (size as usize & 1)];
+        reader.read_exact(&mut chunk)?;
+
+        if id != b"fmt " {
+            continue;
+        }
+
+        if chunk.len() < 16 {
+            return Err(invalid("truncated fmt chunk"));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([chunk[i], chunk[i + 1]]);
+        let mut tag = u16_at(0);
+        let channels = u16_at(2);
+        let sample_rate = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        let bits = u16_at(14);
+
+        // the actual format is the first two bytes of the sub format GUID
+        if tag == WAV_FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+            tag = u16_at(24);
+        }
+
+        let format = match (tag, bits) {
+            (WAV_FORMAT_PCM, 16) => FfmpegAudioSampleFormat::S16le,
+            (WAV_FORMAT_PCM, 32) => FfmpegAudioSampleFormat::S32le,
+            (WAV_FORMAT_FLOAT, 32) => FfmpegAudioSampleFormat::F32le,
+            _ => {
+                return Err(invalid(&format!(
+                    "unsupported format {} with {} bits per sample",
+                    tag, bits
+                )))
+            }
+        };
+
+        spec = Some(AudioSpec::new(format, sample_rate, channels as u8));
+    }
+}
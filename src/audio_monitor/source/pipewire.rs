@@ -0,0 +1,57 @@
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::audio_monitor::source::{AudioSource, AudioSpec, CommandSource};
+use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
+
+pub static PIPEWIRE_RECORD_BIN: &str = "pw-record";
+
+/// Native PipeWire capture through `pw-record`, without the PulseAudio compatibility layer
+#[derive(Debug)]
+pub struct PipewireSource {
+    inner: CommandSource,
+}
+
+impl PipewireSource {
+    /// `device` is a node name or serial, the default source otherwise
+    pub fn open(spec: AudioSpec, device: Option<&str>) -> Result<Self> {
+        let format = match spec.format {
+            FfmpegAudioSampleFormat::S16le => "s16",
+            FfmpegAudioSampleFormat::S32le => "s32",
+            FfmpegAudioSampleFormat::F32le => "f32",
+        };
+
+        let mut command = Command::new(PIPEWIRE_RECORD_BIN);
+        command.args([
+            "--raw",
+            "--format",
+            format,
+            "--rate",
+            &spec.sample_rate.to_string(),
+            "--channels",
+            &spec.channels.to_string(),
+        ]);
+
+        if let Some(device) = device {
+            command.args(["--target", device]);
+        }
+
+        // raw samples on stdout
+        command.arg("-");
+
+        Ok(Self {
+            inner: CommandSource::spawn(PIPEWIRE_RECORD_BIN, &mut command, spec)?,
+        })
+    }
+}
+
+impl AudioSource for PipewireSource {
+    fn spec(&self) -> &AudioSpec {
+        self.inner.spec()
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize> {
+        self.inner.read(samples)
+    }
+}
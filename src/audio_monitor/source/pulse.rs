@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+use libpulse_binding as pulse;
+use libpulse_binding::sample::Format as PulseAudioSampleFormat;
+use libpulse_simple_binding as simple;
+
+use crate::audio_monitor::source::{decode_samples, AudioSource, AudioSpec};
+use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
+
+impl From<FfmpegAudioSampleFormat> for PulseAudioSampleFormat {
+    fn from(value: FfmpegAudioSampleFormat) -> Self {
+        match value {
+            // FfmpegAudioSampleFormat::U8 => PulseAudioSampleFormat::U8,
+            // FfmpegAudioSampleFormat::ALaw => PulseAudioSampleFormat::ALaw,
+            // FfmpegAudioSampleFormat::ULaw => PulseAudioSampleFormat::ULaw,
+            FfmpegAudioSampleFormat::S16le => PulseAudioSampleFormat::S16le,
+            // FfmpegAudioSampleFormat::S16be => PulseAudioSampleFormat::S16be,
+            FfmpegAudioSampleFormat::F32le => PulseAudioSampleFormat::F32le,
+            // FfmpegAudioSampleFormat::F32be => PulseAudioSampleFormat::F32be,
+            FfmpegAudioSampleFormat::S32le => PulseAudioSampleFormat::S32le,
+            // FfmpegAudioSampleFormat::S32be => PulseAudioSampleFormat::S32be,
+            // FfmpegAudioSampleFormat::S24le => PulseAudioSampleFormat::S24le,
+            // FfmpegAudioSampleFormat::S24be => PulseAudioSampleFormat::S24be,
+        }
+    }
+}
+
+/// PulseAudio record stream, also served by PipeWire through `pipewire-pulse`
+pub struct PulseSource {
+    connection: simple::Simple,
+    spec: AudioSpec,
+    buffer: Vec<u8>,
+}
+
+impl PulseSource {
+    pub fn open(spec: AudioSpec, device: Option<&str>) -> Result<Self> {
+        let connection = simple::Simple::new(
+            None,
+            "babypi",
+            pulse::stream::Direction::Record,
+            device,
+            "audio_monitor",
+            &pulse::sample::Spec {
+                format: spec.format.clone().into(),
+                channels: spec.channels,
+                rate: spec.sample_rate,
+            },
+            None,
+            None,
+        )
+        .map_err(|e| anyhow!("Error connecting to pulseaudio: {}", e))?;
+
+        Ok(Self {
+            connection,
+            spec,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+impl AudioSource for PulseSource {
+    fn spec(&self) -> &AudioSpec {
+        &self.spec
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize> {
+        self.buffer
+            .resize(samples.len() * self.spec.sample_size(), 0);
+
+        self.connection
+            .read(&mut self.buffer)
+            .map_err(|e| anyhow!("Error reading from pulseaudio stream: {}", e))?;
+
+        Ok(decode_samples(&self.spec.format, &self.buffer, samples))
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const BACKOFF_DEFAULT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+pub const BACKOFF_DEFAULT_MAX_DELAY: Duration = Duration::from_secs(300);
+pub const BACKOFF_DEFAULT_JITTER: f32 = 0.2;
+pub const BACKOFF_DEFAULT_HEALTHY_PERIOD: Duration = Duration::from_secs(120);
+
+/// Exponential backoff restart policy
+///
+/// The retry budget is meant to be reset by the caller once the service
+/// has been up for at least `healthy_period`.
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Relative random spread applied to each delay, `0.0..=1.0`
+    pub jitter: f32,
+    /// Consecutive failures tolerated before giving up, `None` for unlimited
+    pub max_retries: Option<u32>,
+    pub healthy_period: Duration,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: BACKOFF_DEFAULT_INITIAL_DELAY,
+            max_delay: BACKOFF_DEFAULT_MAX_DELAY,
+            jitter: BACKOFF_DEFAULT_JITTER,
+            max_retries: None,
+            healthy_period: BACKOFF_DEFAULT_HEALTHY_PERIOD,
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    pub fn new(
+        initial_delay: Duration,
+        max_delay: Duration,
+        jitter: f32,
+        max_retries: Option<u32>,
+        healthy_period: Duration,
+    ) -> Self {
+        Self {
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            jitter: jitter.clamp(0.0, 1.0),
+            max_retries,
+            healthy_period,
+        }
+    }
+
+    /// Delay before the given retry attempt (1-based), jitter included
+    pub fn delay(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO;
+        }
+
+        let exponent = (attempt - 1).min(31);
+        let delay = self
+            .initial_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay);
+
+        // cheap source of randomness, good enough to spread out restarts
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let spread = (nanos % 1_000) as f32 / 1_000.0 * 2.0 - 1.0;
+
+        delay
+            .mul_f32((1.0 + self.jitter * spread).max(0.0))
+            .min(self.max_delay)
+    }
+
+    /// Did we run out of retries?
+    pub fn is_exhausted(&self, attempt: u32) -> bool {
+        self.max_retries.is_some_and(|max| attempt > max)
+    }
+}
@@ -3,8 +3,10 @@ mod toml;
 
 pub use cli::CliArgs;
 pub use toml::{
-    AccelerometerConfigV1, CameraConfigV1, IrCamConfigV1, MicrophoneConfigV1, MmWaveConfigV1,
-    TomlConfig, TomlConfigHardwareV1, TomlConfigMonitoringV1, TomlConfigNotificationsV1,
-    TomlConfigRecordingV1, TomlConfigServerV1, TomlConfigStreamV1, TomlConfigTelemetryV1,
+    AccelerometerConfigV1, CameraConfigV1, IrCamConfigV1, LensConfigV1, MicrophoneConfigV1,
+    MmWaveConfigV1, TomlConfig, TomlConfigAnalysisV1, TomlConfigCryV1, TomlConfigHardwareV1,
+    TomlConfigInferenceV1, TomlConfigMonitoringV1, TomlConfigMotionV1, TomlConfigNotificationsV1,
+    TomlConfigProcessLimitsV1, TomlConfigRecordingV1, TomlConfigSafeSleepV1, TomlConfigServerV1,
+    TomlConfigSnapshotV1, TomlConfigStreamV1, TomlConfigTelemetryV1, TomlConfigTimelapseV1,
     TomlConfigV1, TomlParity, TOML_CONFIG_DEFAULT_DIR, TOML_CONFIG_DEFAULT_FILENAME,
 };
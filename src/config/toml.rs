@@ -5,12 +5,26 @@ use rppal::uart::Parity;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    analysis::motion::{MotionPoint, MotionZone},
+    audio_monitor::{
+        level::AudioCalibration,
+        source::{alsa::ALSA_DEFAULT_DEVICE, AudioBackend},
+    },
     ffmpeg::{
-        audio::{FfmpegAudioDeviceType, FfmpegAudioFormat, FfmpegAudioSampleFormat},
+        audio::{
+            FfmpegAudioDeviceType, FfmpegAudioFormat, FfmpegAudioSampleFormat,
+            FFMPEG_DEFAULT_AUDIO_DEVICE,
+        },
         FFMPEG_DEFAULT_STREAM_DIR,
     },
     file_exists,
-    rpicam::{Rpicam, RpicamCodec, RpicamDevice, RpicamDeviceMode},
+    lens::LensModel,
+    live_stream::frame_hub::FrameDecodeMode,
+    process_control::limits::ProcessLimits,
+    rpicam::{bitrate_to_bps, Rpicam, RpicamCodec, RpicamDevice, RpicamDeviceMode},
+    snapshot::SnapshotFormat,
+    supervisor::RestartPolicy,
+    timelapse::{TimelapseWindow, TIMELAPSE_DEFAULT_END, TIMELAPSE_DEFAULT_START},
 };
 
 pub const TOML_CONFIG_DEFAULT_DIR: &str = "/etc/babypi";
@@ -27,6 +41,10 @@ pub struct TomlConfigV1 {
     pub monitoring: TomlConfigMonitoringV1,
     pub telemetry: TomlConfigTelemetryV1,
     pub notifications: TomlConfigNotificationsV1,
+    #[serde(default)]
+    pub analysis: TomlConfigAnalysisV1,
+    #[serde(default)]
+    pub snapshot: TomlConfigSnapshotV1,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -41,11 +59,51 @@ pub struct TomlConfigHardwareV1 {
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct TomlConfigStreamV1 {
     pub audio: Option<bool>,
+    pub av_sync: Option<bool>,
     pub data_dir: Option<PathBuf>,
     pub extra_args_setup: Option<String>,
     pub extra_args_video_input: Option<String>,
     pub extra_args_audio_input: Option<String>,
     pub extra_args_output: Option<String>,
+    /// Initial watchdog restart delay, in seconds
+    pub restart_delay: Option<u64>,
+    /// Upper bound for the exponential restart delay, in seconds
+    pub restart_delay_max: Option<u64>,
+    /// Consecutive failed restarts before giving up, `0` for unlimited
+    pub restart_retries: Option<u32>,
+    /// Uptime after which the retry budget is restored, in seconds
+    pub restart_healthy_period: Option<u64>,
+    /// When to restart the pipeline after it went down, defaults to `always`
+    pub restart_policy: Option<RestartPolicy>,
+    /// Restarts allowed within `restart_limit_window` before giving up, `0` for unlimited
+    pub restart_limit: Option<u32>,
+    /// Sliding window for `restart_limit`, in seconds
+    pub restart_limit_window: Option<u64>,
+    /// Restart the pipeline when no data flows for this long, in seconds, `0` to disable
+    pub stall_timeout: Option<u64>,
+    /// Restart the pipeline when a process resident memory exceeds this, in MiB, `0` to disable
+    pub memory_limit: Option<u64>,
+    /// MJPEG frame rate, decoded apart from the `analysis` frames
+    pub mjpeg_fps: Option<f32>,
+    pub mjpeg_width: Option<u32>,
+    pub mjpeg_height: Option<u32>,
+    /// MJPEG JPEG quality, `1..=100`
+    pub mjpeg_quality: Option<u8>,
+    /// Recent video kept in memory for pre-event clips, in seconds
+    pub ring_buffer_duration: Option<u64>,
+    /// Memory cap for the pre-event buffer, in MiB
+    pub ring_buffer_max_size: Option<u64>,
+    /// Exported clips directory, defaults to `clips` within the stream data dir
+    pub clips_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub limits: TomlConfigProcessLimitsV1,
+}
+
+/// Scheduling priority and resource caps of the stream processes
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigProcessLimitsV1 {
+    pub rpicam: Option<ProcessLimits>,
+    pub ffmpeg: Option<ProcessLimits>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -60,17 +118,163 @@ pub struct TomlConfigServerV1 {
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct TomlConfigRecordingV1 {
     pub enabled: bool,
+    /// Recordings directory, defaults to `recordings` within the stream data dir
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub timelapse: TomlConfigTimelapseV1,
+}
+
+/// Nightly time-lapse, one video per recording window
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigTimelapseV1 {
+    pub enabled: bool,
+    /// Seconds between two sampled frames
+    pub interval: Option<u64>,
+    /// Window start, local `HH:MM`
+    pub start: Option<String>,
+    /// Window end, local `HH:MM`, may be on the next day
+    pub end: Option<String>,
+    /// Output video frame rate
+    pub fps: Option<u32>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Time-lapses kept, `0` for unlimited
+    pub retention: Option<usize>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct TomlConfigMonitoringV1 {
     pub enabled: bool,
+    /// Linear level below which nothing triggers, superseded by `min_dbfs`
     pub rms_threshold: Option<f32>,
+    /// Margin above the ambient noise floor triggering the monitor, dB
+    pub threshold_db: Option<f32>,
+    /// Level below which nothing triggers, whatever the noise floor, dBFS
+    pub min_dbfs: Option<f32>,
+    /// Time for the noise floor to follow a louder room, in seconds
+    pub noise_floor_window_secs: Option<u64>,
+    /// Drop below `threshold_db` tolerated once activity started, dB
+    pub hysteresis_db: Option<f32>,
+    /// Loud time before activity starts, in milliseconds
+    pub activity_debounce_ms: Option<u64>,
+    /// Quiet time before activity ends, in seconds
+    pub activity_hangover_secs: Option<u64>,
+    /// Time between two ongoing activity summaries, in seconds
+    pub activity_summary_interval_secs: Option<u64>,
+    /// Reference `{ dbfs, spl }` pair to report sound pressure levels
+    pub calibration: Option<AudioCalibration>,
+    /// Capture backend, follows the microphone interface by default
+    pub source: Option<AudioBackend>,
+    /// Capture device, the microphone one by default, use a `dsnoop` one to share a `hw:` device
+    /// with the stream
+    pub device: Option<String>,
+    /// Recording replayed by the `file` backend, WAV or raw samples in the microphone format
+    pub file: Option<PathBuf>,
+    #[serde(default)]
+    pub cry: TomlConfigCryV1,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigCryV1 {
+    pub enabled: bool,
+    /// `0.0..=1.0`, higher accepts quieter and less tonal sounds
+    pub sensitivity: Option<f32>,
+    /// Crying bursts in a row before reporting a cry
+    pub min_bursts: Option<u32>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct TomlConfigTelemetryV1 {
     pub enabled: bool,
+    /// Seconds between process resource samples, `0` to disable
+    pub process_stats_interval: Option<u64>,
+    /// Publish samples as telemetry events, off by default, `/api/metrics` serves them either way
+    pub process_stats_events: Option<bool>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigAnalysisV1 {
+    /// Decoded frames per second handed to analytics
+    pub fps: Option<f32>,
+    /// Frames are scaled down to fit, `320` pixels wide when neither is set
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// `all`, `keyframes` or `{ every = N }` for keyframes plus every Nth P-frame
+    pub decode_mode: Option<FrameDecodeMode>,
+    #[serde(default)]
+    pub motion: TomlConfigMotionV1,
+    #[serde(default)]
+    pub presence: TomlConfigInferenceV1,
+    #[serde(default)]
+    pub pose: TomlConfigInferenceV1,
+    #[serde(default)]
+    pub safe_sleep: TomlConfigSafeSleepV1,
+}
+
+/// ONNX model analyzer, needs the `onnx` build feature
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigInferenceV1 {
+    pub enabled: bool,
+    pub model: Option<PathBuf>,
+    /// Square model input size, in pixels
+    pub input_size: Option<u32>,
+    /// Minimum detection or keypoint confidence
+    pub threshold: Option<f32>,
+    /// Seconds between two inferences
+    pub interval: Option<u64>,
+}
+
+/// Unsafe sleeping position alerts, needs pose analysis
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigSafeSleepV1 {
+    pub enabled: bool,
+    /// Minimum keypoint score to count it as visible
+    pub keypoint_threshold: Option<f32>,
+    /// Seconds face down before alerting
+    pub stomach_duration: Option<u64>,
+    /// Seconds without a visible face before alerting
+    pub face_hidden_duration: Option<u64>,
+    /// Seconds back in a safe position before an alert clears
+    pub clear_duration: Option<u64>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigMotionV1 {
+    pub enabled: bool,
+    /// `0.0..=1.0`, higher picks up smaller brightness changes
+    pub sensitivity: Option<f32>,
+    /// Changed share of a zone needed to count as motion
+    pub min_area: Option<f32>,
+    /// Background adaptation speed per frame
+    pub learning_rate: Option<f32>,
+    /// Consecutive frames with motion before reporting it
+    pub on_frames: Option<u32>,
+    /// Consecutive quiet frames before motion is considered over
+    pub off_frames: Option<u32>,
+    /// Analyzed frames between two motion events of an active zone
+    pub report_frames: Option<u32>,
+    /// Polygons in normalized coordinates, the whole picture when omitted
+    pub zones: Option<Vec<MotionZone>>,
+    /// Polygons ignored within every zone
+    pub masks: Option<Vec<Vec<MotionPoint>>>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct TomlConfigSnapshotV1 {
+    pub enabled: Option<bool>,
+    /// Seconds between periodic snapshots
+    pub interval: Option<u64>,
+    pub format: Option<SnapshotFormat>,
+    /// JPEG quality, `1..=100`
+    pub quality: Option<u8>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// History directory, defaults to `snapshots` within the stream data dir
+    pub dir: Option<PathBuf>,
+    /// Snapshots kept in history, `0` for unlimited
+    pub history_count: Option<usize>,
+    /// Maximum snapshot age in seconds, `0` for unlimited
+    pub history_max_age: Option<u64>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -90,12 +294,36 @@ pub struct CameraConfigV1 {
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub fps: Option<u32>,
+    /// Frames between two keyframes
+    pub intra: Option<u32>,
     pub tuning_file: Option<PathBuf>,
     pub hflip: Option<bool>,
     pub vflip: Option<bool>,
     pub extra_args: Option<String>,
     pub ircut_gpio_pin: Option<u8>,
     pub ircut_on_state: Option<bool>,
+    #[serde(default)]
+    pub lens: LensConfigV1,
+}
+
+/// Fisheye dewarping, see `examples/calibrate.rs` to compute the model
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct LensConfigV1 {
+    pub enabled: bool,
+    pub model: Option<LensModel>,
+    /// Output focal length relative to the lens one, lower keeps more of the edges
+    pub zoom: Option<f32>,
+    /// Dewarp snapshots and their history
+    pub snapshots: Option<bool>,
+    /// Dewarp frames fed to analyzers
+    pub analysis: Option<bool>,
+    /// Dewarp the MJPEG live view
+    pub mjpeg: Option<bool>,
+    /// Dewarp the HLS stream in ffmpeg, which re-encodes the video
+    pub stream: Option<bool>,
+    /// ffmpeg encoder used when dewarping the stream
+    pub stream_encoder: Option<String>,
+    pub stream_bitrate: Option<String>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -194,6 +422,36 @@ impl TomlConfigV1 {
             monitoring: TomlConfigMonitoringV1::default(),
             telemetry: TomlConfigTelemetryV1::default(),
             notifications: TomlConfigNotificationsV1::default(),
+            analysis: TomlConfigAnalysisV1::default(),
+            snapshot: TomlConfigSnapshotV1::default(),
+        }
+    }
+
+    /// Audio monitoring backend, follows the microphone interface unless set
+    pub fn monitoring_backend(&self) -> AudioBackend {
+        self.monitoring.source.clone().unwrap_or_else(|| {
+            self.hardware
+                .mic
+                .interface
+                .as_ref()
+                .map(AudioBackend::from)
+                .unwrap_or(AudioBackend::Alsa)
+        })
+    }
+
+    /// Audio monitoring device, or replayed file, `None` for the backend default
+    pub fn monitoring_device(&self) -> Option<String> {
+        match self.monitoring_backend() {
+            AudioBackend::File => self
+                .monitoring
+                .file
+                .as_ref()
+                .map(|file| file.to_string_lossy().to_string()),
+            _ => self
+                .monitoring
+                .device
+                .clone()
+                .or_else(|| self.hardware.mic.device.clone()),
         }
     }
 
@@ -234,12 +492,64 @@ impl TomlConfigV1 {
             return Err(anyhow!("Camera `{}` not found.", camera_index));
         }
 
+        if self.stream.av_sync.is_some_and(|v| v)
+            && self
+                .hardware
+                .camera
+                .codec
+                .as_ref()
+                .is_some_and(|codec| codec != &RpicamCodec::H264)
+        {
+            return Err(anyhow!(
+                "Audio/video synchronization requires the H264 camera codec."
+            ));
+        }
+
         if let Some(tuning_file) = self.hardware.camera.tuning_file.as_ref() {
             if !file_exists(tuning_file).await {
                 return Err(anyhow!("Camera tuning file is invalid."));
             }
         }
 
+        let lens = &self.hardware.camera.lens;
+        if lens.enabled {
+            match lens.model.as_ref() {
+                None => {
+                    return Err(anyhow!("Lens correction requires a lens model."));
+                }
+                Some(model)
+                    if model.width == 0
+                        || model.height == 0
+                        || model.fx <= 0.0
+                        || model.fy <= 0.0 =>
+                {
+                    return Err(anyhow!("Lens model is invalid."));
+                }
+                _ => {}
+            }
+
+            if lens.zoom.is_some_and(|zoom| zoom <= 0.0) {
+                return Err(anyhow!("Lens zoom must be positive."));
+            }
+
+            if lens.stream.is_some_and(|v| v) && self.stream.av_sync.is_some_and(|v| v) {
+                return Err(anyhow!(
+                    "Stream lens correction can't be combined with audio/video synchronization."
+                ));
+            }
+        }
+
+        for (name, limits) in [
+            ("rpicam", &self.stream.limits.rpicam),
+            ("ffmpeg", &self.stream.limits.ffmpeg),
+        ] {
+            if let Some(limits) = limits {
+                limits
+                    .validate()
+                    .map_err(|e| anyhow!("Process `{}` limits are invalid: {}", name, e))?;
+            }
+        }
+
         let data_dir = self
             .stream
             .data_dir
@@ -250,22 +560,142 @@ impl TomlConfigV1 {
             return Err(anyhow!("Stream storage directory is invalid."));
         }
 
+        if let Some(bitrate) = self.hardware.mic.output_bitrate.as_deref() {
+            bitrate_to_bps(bitrate)?;
+        }
+
         if self.monitoring.enabled {
-            if !self.hardware.mic.enabled {
+            if self.monitoring.source == Some(AudioBackend::File) {
+                match self.monitoring.file.as_ref() {
+                    Some(file) if file_exists(file).await => {}
+                    _ => return Err(anyhow!("Audio monitoring replay file is invalid.")),
+                }
+            } else if !self.hardware.mic.enabled {
                 return Err(anyhow!(
                     "Audio monitoring can't be enabled without enabled microphone config."
                 ));
-            } else if self
-                .hardware
-                .mic
-                .interface
-                .as_ref()
-                .is_none_or(|interface| interface == &FfmpegAudioDeviceType::Alsa)
+            }
+
+            // hardware devices open once, the stream would keep the monitor out
+            if self.monitoring_backend() == AudioBackend::Alsa
+                && self.stream.audio.is_some_and(|v| v)
+                && self.hardware.mic.interface.clone().unwrap_or_default()
+                    == FfmpegAudioDeviceType::Alsa
+            {
+                let stream_device = self
+                    .hardware
+                    .mic
+                    .device
+                    .as_deref()
+                    .unwrap_or(FFMPEG_DEFAULT_AUDIO_DEVICE);
+                let monitor_device = self.monitoring_device();
+                let monitor_device = monitor_device.as_deref().unwrap_or(ALSA_DEFAULT_DEVICE);
+
+                if monitor_device == stream_device
+                    && (monitor_device.starts_with("hw:") || monitor_device.starts_with("plughw:"))
+                {
+                    return Err(anyhow!(
+                        "Audio monitoring can't open `{}` while the stream captures it, set a `dsnoop` monitoring device.",
+                        monitor_device
+                    ));
+                }
+            }
+
+            if self
+                .monitoring
+                .threshold_db
+                .is_some_and(|threshold| threshold < 0.0)
+            {
+                return Err(anyhow!("Audio monitoring threshold must be positive."));
+            }
+
+            if self.monitoring.min_dbfs.is_some_and(|dbfs| dbfs > 0.0) {
+                return Err(anyhow!(
+                    "Audio monitoring minimum level can't be above 0 dBFS."
+                ));
+            }
+
+            if self
+                .monitoring
+                .hysteresis_db
+                .is_some_and(|hysteresis| hysteresis < 0.0)
+            {
+                return Err(anyhow!("Audio monitoring hysteresis must be positive."));
+            }
+
+            if self.monitoring.activity_summary_interval_secs == Some(0) {
+                return Err(anyhow!("Audio activity summary interval can't be zero."));
+            }
+
+            if self.monitoring.noise_floor_window_secs == Some(0) {
+                return Err(anyhow!(
+                    "Audio monitoring noise floor window can't be zero."
+                ));
+            }
+        }
+
+        if self.analysis.motion.enabled {
+            let motion = &self.analysis.motion;
+
+            if motion
+                .sensitivity
+                .is_some_and(|sensitivity| !(0.0..=1.0).contains(&sensitivity))
             {
+                return Err(anyhow!("Motion sensitivity must be between 0 and 1."));
+            }
+
+            if motion
+                .min_area
+                .is_some_and(|min_area| !(min_area > 0.0 && min_area <= 1.0))
+            {
+                return Err(anyhow!(
+                    "Motion minimum area must be above 0 and at most 1."
+                ));
+            }
+        }
+
+        for (name, inference) in [
+            ("presence", &self.analysis.presence),
+            ("pose", &self.analysis.pose),
+        ] {
+            if !inference.enabled {
+                continue;
+            }
+
+            if !cfg!(feature = "onnx") {
                 return Err(anyhow!(
-                    "Audio monitoring can't be enabled when using ALSA."
+                    "Analysis `{}` requires a build with the `onnx` feature.",
+                    name
                 ));
             }
+
+            match inference.model.as_ref() {
+                Some(model) if file_exists(model).await => {}
+                _ => return Err(anyhow!("Analysis `{}` model file is invalid.", name)),
+            }
+        }
+
+        if self.recording.timelapse.enabled {
+            let timelapse = &self.recording.timelapse;
+
+            TimelapseWindow::parse(
+                timelapse
+                    .start
+                    .as_deref()
+                    .unwrap_or(TIMELAPSE_DEFAULT_START),
+                timelapse.end.as_deref().unwrap_or(TIMELAPSE_DEFAULT_END),
+            )
+            .map_err(|e| anyhow!("Time-lapse window is invalid: {}", e))?;
+
+            if timelapse.interval == Some(0) || timelapse.fps == Some(0) {
+                return Err(anyhow!("Time-lapse interval and fps must be positive."));
+            }
+        }
+
+        if self.analysis.safe_sleep.enabled && !self.analysis.pose.enabled {
+            return Err(anyhow!(
+                "Safe sleep alerts need pose analysis to be enabled."
+            ));
         }
 
         Ok(())
@@ -1,4 +1,4 @@
-use std::{path::PathBuf, process::Stdio, str::FromStr};
+use std::{fmt::Display, path::PathBuf, process::Stdio, str::FromStr};
 
 use audio::FfmpegAudio;
 use audio::FFMPEG_DEFAULT_AUDIO_OUTPUT_BITRATE;
@@ -9,12 +9,19 @@ use tokio::process::{Child, Command};
 use anyhow::anyhow;
 use anyhow::Result;
 use tracing::debug;
+use tracing::warn;
+
+use crate::process_control::limits::ProcessLimits;
 
 pub static FFMPEG_BIN: &str = "ffmpeg";
 
 pub static FFMPEG_DEFAULT_STREAM_DIR: &str = "/var/run/babypi/stream";
 pub static FFMPEG_DEFAULT_STREAM_PLAYLIST_NAME: &str = "live.m3u8";
 pub static FFMPEG_DEFAULT_STREAM_SEGMENT_NAME_PATTERN: &str = "%08d.ts";
+pub static FFMPEG_DEFAULT_STREAM_SEGMENT_TIME: u64 = 4;
+/// Raspberry Pi hardware encoder, only used when the video has to be re-encoded
+pub static FFMPEG_DEFAULT_VIDEO_ENCODER: &str = "h264_v4l2m2m";
+pub static FFMPEG_DEFAULT_VIDEO_BITRATE: &str = "4M";
 
 pub mod audio;
 
@@ -26,12 +33,68 @@ pub struct FfmpegExtraArgs {
     pub output: Option<Vec<String>>,
 }
 
+/// Format of the video piped into ffmpeg
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum FfmpegInputFormat {
+    /// Raw H.264, timestamps come from the wall clock
+    #[default]
+    H264,
+    /// MPEG-TS with capture timestamps and optional muxed audio
+    MpegTs,
+}
+
+impl Display for FfmpegInputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FfmpegInputFormat::H264 => write!(f, "h264"),
+            FfmpegInputFormat::MpegTs => write!(f, "mpegts"),
+        }
+    }
+}
+
+/// Re-encode the video through a filter graph instead of copying it
+#[derive(Clone, Debug)]
+pub struct FfmpegVideoFilter {
+    pub filter: String,
+    pub encoder: String,
+    pub bitrate: String,
+}
+
+impl FfmpegVideoFilter {
+    pub fn new(filter: impl Into<String>) -> Self {
+        Self {
+            filter: filter.into(),
+            encoder: FFMPEG_DEFAULT_VIDEO_ENCODER.to_string(),
+            bitrate: FFMPEG_DEFAULT_VIDEO_BITRATE.to_string(),
+        }
+    }
+
+    pub fn with_encoder(mut self, encoder: Option<String>) -> Self {
+        if let Some(encoder) = encoder {
+            self.encoder = encoder;
+        }
+
+        self
+    }
+
+    pub fn with_bitrate(mut self, bitrate: Option<String>) -> Self {
+        if let Some(bitrate) = bitrate {
+            self.bitrate = bitrate;
+        }
+
+        self
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Ffmpeg {
     pub stream_dir: PathBuf,
     pub audio_input: Option<FfmpegAudio>,
     pub extra_args: Option<FfmpegExtraArgs>,
     pub verbose: bool,
+    pub input_format: FfmpegInputFormat,
+    pub video_filter: Option<FfmpegVideoFilter>,
+    pub limits: Option<ProcessLimits>,
 }
 
 impl Default for Ffmpeg {
@@ -42,6 +105,9 @@ impl Default for Ffmpeg {
             audio_input: None,
             extra_args: None,
             verbose: false,
+            input_format: FfmpegInputFormat::default(),
+            video_filter: None,
+            limits: None,
         }
     }
 }
@@ -58,9 +124,38 @@ impl Ffmpeg {
             audio_input,
             extra_args,
             verbose,
+            input_format: FfmpegInputFormat::default(),
+            video_filter: None,
+            limits: None,
         }
     }
 
+    /// Set piped video input format
+    pub fn with_input_format(mut self, input_format: FfmpegInputFormat) -> Self {
+        self.input_format = input_format;
+
+        self
+    }
+
+    /// Filter and re-encode the video, costly on the CPU unless the encoder is hardware backed
+    pub fn with_video_filter(mut self, video_filter: Option<FfmpegVideoFilter>) -> Self {
+        self.video_filter = video_filter;
+
+        self
+    }
+
+    /// Scheduling priority and resource caps of the encoder process
+    pub fn with_limits(mut self, limits: Option<ProcessLimits>) -> Self {
+        self.limits = limits;
+
+        self
+    }
+
+    /// HLS playlist location
+    pub fn playlist_path(&self) -> PathBuf {
+        self.stream_dir.join(FFMPEG_DEFAULT_STREAM_PLAYLIST_NAME)
+    }
+
     fn build_ffmpeg_cmd_args(&self) -> Vec<String> {
         let mut args = Vec::new();
 
@@ -91,11 +186,16 @@ impl Ffmpeg {
         args.push("-thread_queue_size".to_string());
         args.push("256".to_string());
 
-        // come up with video timestamps
-        args.push("-use_wallclock_as_timestamps".to_string());
-        args.push("1".to_string());
+        if self.input_format == FfmpegInputFormat::H264 {
+            // come up with video timestamps
+            args.push("-use_wallclock_as_timestamps".to_string());
+            args.push("1".to_string());
+        }
+
+        // we will be piping the h264 or mpegts input
+        args.push("-f".to_string());
+        args.push(self.input_format.to_string());
 
-        // we will be piping the h264 input
         args.push("-i".to_string());
         args.push("pipe:".to_string());
 
@@ -170,9 +270,20 @@ impl Ffmpeg {
             }
         }
 
-        // avoid transcoding at all costs
-        args.push("-c:v".to_string());
-        args.push("copy".to_string());
+        if let Some(video_filter) = self.video_filter.as_ref() {
+            args.push("-vf".to_string());
+            args.push(video_filter.filter.clone());
+
+            args.push("-c:v".to_string());
+            args.push(video_filter.encoder.clone());
+
+            args.push("-b:v".to_string());
+            args.push(video_filter.bitrate.clone());
+        } else {
+            // avoid transcoding at all costs
+            args.push("-c:v".to_string());
+            args.push("copy".to_string());
+        }
 
         if let Some(audio_input) = self.audio_input.as_ref() {
             // this is the most resource costly thing in the whole app...
@@ -199,6 +310,13 @@ impl Ffmpeg {
             args.push("0:0".to_string());
             args.push("-map".to_string());
             args.push("1:0".to_string());
+        } else if self.input_format == FfmpegInputFormat::MpegTs {
+            // audio, if any, is already encoded and muxed on the capture clock
+            args.push("-c:a".to_string());
+            args.push("copy".to_string());
+
+            args.push("-map".to_string());
+            args.push("0".to_string());
         }
 
         // HLS live stream parameters
@@ -219,7 +337,7 @@ impl Ffmpeg {
 
         // 4 seconds per segment
         args.push("-segment_time".to_string());
-        args.push("4".to_string());
+        args.push(FFMPEG_DEFAULT_STREAM_SEGMENT_TIME.to_string());
 
         // 8 segments per playlist
         args.push("-segment_list_size".to_string());
@@ -229,14 +347,11 @@ impl Ffmpeg {
         args.push("-segment_wrap".to_string());
         args.push("10".to_string());
 
-        let stream_playlist = {
-            let mut stream_dir = self.stream_dir.clone();
-            stream_dir.push(FFMPEG_DEFAULT_STREAM_PLAYLIST_NAME);
-            stream_dir
-                .to_str()
-                .expect("Failed to build stream playlist path")
-                .to_string()
-        };
+        let stream_playlist = self
+            .playlist_path()
+            .to_str()
+            .expect("Failed to build stream playlist path")
+            .to_string();
 
         // playlist location
         args.push("-segment_list".to_string());
@@ -265,15 +380,31 @@ impl Ffmpeg {
             "Spawning {} with arguments: {:?}", FFMPEG_BIN, args
         );
 
-        let ffmpeg = Command::new(FFMPEG_BIN)
+        let mut command = Command::new(FFMPEG_BIN);
+        command
             .args(&args)
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
             .stderr(Stdio::piped())
-            .kill_on_drop(true)
+            .kill_on_drop(true);
+
+        let limit_errors = self
+            .limits
+            .as_ref()
+            .and_then(|limits| limits.configure(&mut command));
+
+        let ffmpeg = command
             .spawn()
             .map_err(|e| anyhow!("Failed to spawn child process {}: {}", FFMPEG_BIN, e))?;
 
+        if let (Some(limits), Some(pid)) = (self.limits.as_ref(), ffmpeg.id()) {
+            limits.verify(FFMPEG_BIN, pid, limit_errors);
+
+            if let Err(e) = limits.attach(FFMPEG_BIN, pid) {
+                warn!(target = "ffmpeg", "{}", e);
+            }
+        }
+
         Ok(ffmpeg)
     }
 }
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use image::{GrayImage, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::live_stream::frame_hub::{Frame, FrameFormat};
+
+pub mod calibration;
+
+/// Output focal length relative to the lens one, keeps the picture center at its native scale
+pub const LENS_DEFAULT_ZOOM: f32 = 1.0;
+
+/// Fisheye lens model, OpenCV `fisheye` conventions
+///
+/// Intrinsics are in pixels at the calibration resolution and scale with the picture,
+/// which assumes the same sensor crop at every resolution.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LensModel {
+    /// Calibration picture size
+    pub width: u32,
+    pub height: u32,
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    /// Distortion `θd = θ (1 + k1 θ² + k2 θ⁴ + k3 θ⁶ + k4 θ⁸)`
+    pub k: [f32; 4],
+}
+
+impl LensModel {
+    /// Intrinsics for a picture of the given size
+    fn scaled(&self, width: u32, height: u32) -> (f32, f32, f32, f32) {
+        let sx = width as f32 / self.width as f32;
+        let sy = height as f32 / self.height as f32;
+
+        (self.fx * sx, self.fy * sy, self.cx * sx, self.cy * sy)
+    }
+
+    /// Distorted radius on the normalized plane for an incidence angle
+    pub fn distort_angle(&self, theta: f32) -> f32 {
+        let t2 = theta * theta;
+        let [k1, k2, k3, k4] = self.k;
+
+        theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))))
+    }
+
+    /// Project a point on the undistorted normalized plane to distorted pixels
+    pub fn project(&self, a: f32, b: f32, width: u32, height: u32) -> (f32, f32) {
+        let (fx, fy, cx, cy) = self.scaled(width, height);
+        let r = (a * a + b * b).sqrt();
+
+        let scale = if r > f32::EPSILON {
+            self.distort_angle(r.atan()) / r
+        } else {
+            1.0
+        };
+
+        (fx * a * scale + cx, fy * b * scale + cy)
+    }
+
+    /// Distorted pixels back to the undistorted normalized plane, `None` past 90°
+    pub fn unproject(&self, u: f32, v: f32, width: u32, height: u32) -> Option<(f32, f32)> {
+        let (fx, fy, cx, cy) = self.scaled(width, height);
+        let (x, y) = ((u - cx) / fx, (v - cy) / fy);
+        let theta_d = (x * x + y * y).sqrt();
+
+        if theta_d < f32::EPSILON {
+            return Some((x, y));
+        }
+
+        // Newton on θd(θ), converges in a few steps for sane coefficients
+        let mut theta = theta_d;
+        for _ in 0..10 {
+            let t2 = theta * theta;
+            let [k1, k2, k3, k4] = self.k;
+            let derivative =
+                1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+            theta -= (self.distort_angle(theta) - theta_d) / derivative;
+        }
+
+        if !(0.0..std::f32::consts::FRAC_PI_2).contains(&theta) {
+            return None;
+        }
+
+        let scale = theta.tan() / theta_d;
+        Some((x * scale, y * scale))
+    }
+
+    /// `v360` filter dewarping the stream in ffmpeg
+    ///
+    /// `lenscorrection` only knows polynomial radial models, `v360` handles equidistant
+    /// fisheye lenses. It approximates this model: the principal point is taken as the
+    /// picture center and the distortion coefficients are ignored.
+    pub fn ffmpeg_filter(&self, width: u32, height: u32, zoom: f32) -> String {
+        let (fx, fy, _, _) = self.scaled(width, height);
+        let zoom = zoom.max(0.1);
+
+        // equidistant fisheye: r = f θ
+        let ih_fov = (width as f32 / fx).to_degrees();
+        let iv_fov = (height as f32 / fy).to_degrees();
+        let h_fov = (2.0 * (width as f32 / (2.0 * fx * zoom)).atan()).to_degrees();
+        let v_fov = (2.0 * (height as f32 / (2.0 * fy * zoom)).atan()).to_degrees();
+
+        format!(
+            "v360=input=fisheye:output=flat:ih_fov={:.1}:iv_fov={:.1}:h_fov={:.1}:v_fov={:.1}:w={}:h={}",
+            ih_fov, iv_fov, h_fov, v_fov, width, height
+        )
+    }
+}
+
+/// Precomputed source position of every output pixel, `None` outside of the source picture
+#[derive(Debug)]
+pub struct LensRemap {
+    width: u32,
+    height: u32,
+    map: Vec<Option<(f32, f32)>>,
+}
+
+impl LensRemap {
+    /// Rectilinear output of the same size, `zoom` scales the output focal length
+    pub fn new(model: &LensModel, width: u32, height: u32, zoom: f32) -> Self {
+        let (fx, fy, cx, cy) = model.scaled(width, height);
+        let (fx_out, fy_out) = (fx * zoom, fy * zoom);
+        let (max_x, max_y) = (width as f32 - 1.0, height as f32 - 1.0);
+
+        let map = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let a = (x as f32 - cx) / fx_out;
+                let b = (y as f32 - cy) / fy_out;
+                let (u, v) = model.project(a, b, width, height);
+
+                (u >= 0.0 && v >= 0.0 && u <= max_x && v <= max_y).then_some((u, v))
+            })
+            .collect();
+
+        Self { width, height, map }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Bilinear resampling of packed 8 bit pixels
+    pub fn apply(&self, data: &[u8], channels: usize) -> Vec<u8> {
+        let width = self.width as usize;
+        let mut out = vec![0; self.map.len() * channels];
+
+        for (i, source) in self.map.iter().enumerate() {
+            let Some((u, v)) = *source else {
+                continue;
+            };
+
+            let (x0, y0) = (u as usize, v as usize);
+            let (x1, y1) = (
+                (x0 + 1).min(width - 1),
+                (y0 + 1).min(self.height as usize - 1),
+            );
+            let (fx, fy) = (u - x0 as f32, v - y0 as f32);
+
+            for c in 0..channels {
+                let p = |x: usize, y: usize| data[(y * width + x) * channels + c] as f32;
+                let top = p(x0, y0) * (1.0 - fx) + p(x1, y0) * fx;
+                let bottom = p(x0, y1) * (1.0 - fx) + p(x1, y1) * fx;
+
+                out[i * channels + c] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+            }
+        }
+
+        out
+    }
+}
+
+/// Remaps by picture size
+type LensRemapCache = HashMap<(u32, u32), Arc<LensRemap>>;
+
+/// Lens model with remaps built on first use for each picture size
+#[derive(Clone, Debug)]
+pub struct LensCorrection {
+    model: LensModel,
+    zoom: f32,
+    remaps: Arc<Mutex<LensRemapCache>>,
+}
+
+impl LensCorrection {
+    pub fn new(model: LensModel, zoom: f32) -> Self {
+        Self {
+            model,
+            zoom,
+            remaps: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn model(&self) -> &LensModel {
+        &self.model
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Remap for the given size, CPU heavy the first time
+    pub fn remap(&self, width: u32, height: u32) -> Arc<LensRemap> {
+        let mut remaps = self.remaps.lock().unwrap_or_else(|e| e.into_inner());
+
+        remaps
+            .entry((width, height))
+            .or_insert_with(|| Arc::new(LensRemap::new(&self.model, width, height, self.zoom)))
+            .clone()
+    }
+
+    pub fn apply_rgb(&self, image: &RgbImage) -> RgbImage {
+        let (width, height) = image.dimensions();
+        let data = self.remap(width, height).apply(image.as_raw(), 3);
+
+        RgbImage::from_raw(width, height, data).unwrap_or_else(|| image.clone())
+    }
+
+    pub fn apply_gray(&self, image: &GrayImage) -> GrayImage {
+        let (width, height) = image.dimensions();
+        let data = self.remap(width, height).apply(image.as_raw(), 1);
+
+        GrayImage::from_raw(width, height, data).unwrap_or_else(|| image.clone())
+    }
+
+    pub fn apply_frame(&self, frame: Frame) -> Frame {
+        let channels = match frame.format {
+            FrameFormat::Rgb => 3,
+            FrameFormat::Luma => 1,
+        };
+        let data = self
+            .remap(frame.width, frame.height)
+            .apply(&frame.data, channels);
+
+        Frame { data, ..frame }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wide angle lens at 1280x720
+    fn model() -> LensModel {
+        LensModel {
+            width: 1280,
+            height: 720,
+            fx: 420.0,
+            fy: 425.0,
+            cx: 645.0,
+            cy: 355.0,
+            k: [-0.03, 0.01, -0.002, 0.0005],
+        }
+    }
+
+    #[test]
+    fn unproject_reverts_project() {
+        let model = model();
+
+        for (width, height) in [(1280, 720), (640, 360)] {
+            for a in [-2.0, -0.7, 0.0, 0.3, 1.5] {
+                for b in [-1.2, 0.0, 0.05, 0.9] {
+                    let (u, v) = model.project(a, b, width, height);
+                    let (x, y) = model.unproject(u, v, width, height).unwrap();
+
+                    assert!(
+                        (x - a).abs() < 1e-3 && (y - b).abs() < 1e-3,
+                        "({}, {}) came back as ({}, {}) at {}x{}",
+                        a,
+                        b,
+                        x,
+                        y,
+                        width,
+                        height
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn principal_point_stays_put() {
+        let model = model();
+
+        assert_eq!(model.project(0.0, 0.0, 1280, 720), (645.0, 355.0));
+        assert_eq!(model.unproject(645.0, 355.0, 1280, 720), Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn unproject_past_90_degrees() {
+        let model = model();
+
+        // further out than the lens maps anything
+        assert_eq!(model.unproject(645.0 + 420.0 * 2.0, 355.0, 1280, 720), None);
+    }
+
+    #[test]
+    fn remap_samples_projected_positions() {
+        let model = model();
+        let (width, height) = (64, 36);
+        let remap = LensRemap::new(&model, width, height, LENS_DEFAULT_ZOOM);
+
+        // horizontal ramp, bilinear sampling of it is exact
+        let data: Vec<u8> = (0..height)
+            .flat_map(|_| (0..width).map(|x| (x * 4) as u8))
+            .collect();
+        let output = remap.apply(&data, 1);
+
+        let (fx, fy, cx, cy) = model.scaled(width, height);
+        let y = cy.round() as u32;
+        for x in 0..width {
+            let a = (x as f32 - cx) / fx;
+            let b = (y as f32 - cy) / fy;
+            let (u, _) = model.project(a, b, width, height);
+
+            if (0.0..=(width - 1) as f32).contains(&u) {
+                assert_eq!(
+                    output[(y * width + x) as usize],
+                    (u * 4.0).round() as u8,
+                    "column {}",
+                    x
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,915 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use image::imageops::blur;
+use image::GrayImage;
+use tracing::debug;
+
+use crate::lens::LensModel;
+
+/// Views needed for a usable fit
+pub const CALIBRATION_MIN_IMAGES: usize = 3;
+
+const CALIBRATION_MAX_ITERATIONS: usize = 200;
+/// Corner response kept relative to the strongest one
+const CORNER_RESPONSE_THRESHOLD: f32 = 0.1;
+/// Samples on the circle around a candidate corner
+const CORNER_RING_SAMPLES: usize = 24;
+/// Snap distance relative to the local grid step
+const CORNER_SNAP_TOLERANCE: f32 = 0.35;
+/// Initial horizontal field of view guesses, degrees
+const FOV_GUESSES: [f64; 6] = [60.0, 90.0, 120.0, 150.0, 180.0, 210.0];
+
+type Point = (f32, f32);
+type Pose = [f64; 6];
+
+/// Printed calibration target
+#[derive(Clone, Copy, Debug)]
+pub struct Checkerboard {
+    /// Inner corners along a row
+    pub cols: usize,
+    /// Inner corners along a column
+    pub rows: usize,
+    /// Square side, any unit
+    pub square_size: f32,
+}
+
+impl Checkerboard {
+    pub fn new(cols: usize, rows: usize, square_size: f32) -> Self {
+        Self {
+            cols,
+            rows,
+            square_size,
+        }
+    }
+
+    /// Inner corners on the board plane, row major
+    pub fn object_points(&self) -> Vec<[f64; 3]> {
+        (0..self.rows)
+            .flat_map(|r| (0..self.cols).map(move |c| (c, r)))
+            .map(|(c, r)| {
+                [
+                    c as f64 * self.square_size as f64,
+                    r as f64 * self.square_size as f64,
+                    0.0,
+                ]
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    pub model: LensModel,
+    /// Root mean square reprojection error, pixels
+    pub rms_error: f32,
+    /// Pictures the board was found in
+    pub images_used: usize,
+}
+
+/// Checkerboard inner corners in pixels, row major, `None` when the board isn't found
+pub fn find_corners(image: &GrayImage, board: &Checkerboard) -> Option<Vec<Point>> {
+    let candidates = corner_candidates(image);
+    let expected = board.cols * board.rows;
+
+    if candidates.len() < expected {
+        return None;
+    }
+
+    // strongest responses are the likeliest board corners
+    candidates
+        .iter()
+        .take(expected)
+        .enumerate()
+        .find_map(|(origin, _)| {
+            let grid = grow_grid(&candidates, origin)?;
+            order_grid(&candidates, &grid, board)
+        })
+}
+
+/// Saddle points of the smoothed picture, strongest first
+fn corner_candidates(image: &GrayImage) -> Vec<Point> {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let scale = (width.max(height) as f32 / 640.0).max(1.0);
+    let smooth = blur(image, 1.5 * scale);
+    let step = (2.0 * scale).round() as i64;
+
+    let at = |x: i64, y: i64| smooth.get_pixel(x as u32, y as u32)[0] as f32;
+
+    let mut response = vec![0.0f32; (width * height) as usize];
+    for y in step..height - step {
+        for x in step..width - step {
+            let center = at(x, y);
+            let ixx = at(x + step, y) - 2.0 * center + at(x - step, y);
+            let iyy = at(x, y + step) - 2.0 * center + at(x, y - step);
+            let ixy = (at(x + step, y + step) - at(x + step, y - step) - at(x - step, y + step)
+                + at(x - step, y - step))
+                / 4.0;
+
+            // saddles have a strongly negative Hessian determinant
+            response[(y * width + x) as usize] = (ixy * ixy - ixx * iyy).max(0.0);
+        }
+    }
+
+    let max = response.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Vec::new();
+    }
+
+    let radius = 2 * step;
+    let r = |x: i64, y: i64| response[(y * width + x) as usize];
+    let mut peaks = Vec::new();
+
+    for y in radius..height - radius {
+        for x in radius..width - radius {
+            let value = r(x, y);
+            if value < max * CORNER_RESPONSE_THRESHOLD {
+                continue;
+            }
+
+            let is_peak = (-radius..=radius).all(|dy| {
+                (-radius..=radius).all(|dx| {
+                    (dx == 0 && dy == 0)
+                        || r(x + dx, y + dy) < value
+                        || (r(x + dx, y + dy) == value && (dy, dx) > (0, 0))
+                })
+            });
+            if !is_peak || !alternates(&at, x, y, 3 * step, width, height) {
+                continue;
+            }
+
+            // quadratic sub pixel refinement
+            let offset = |minus: f32, plus: f32| {
+                let denominator = minus - 2.0 * value + plus;
+                if denominator.abs() > f32::EPSILON {
+                    (0.5 * (minus - plus) / denominator).clamp(-0.5, 0.5)
+                } else {
+                    0.0
+                }
+            };
+
+            peaks.push((
+                value,
+                (
+                    x as f32 + offset(r(x - 1, y), r(x + 1, y)),
+                    y as f32 + offset(r(x, y - 1), r(x, y + 1)),
+                ),
+            ));
+        }
+    }
+
+    peaks.sort_by(|a, b| b.0.total_cmp(&a.0));
+    peaks.into_iter().map(|(_, point)| point).collect()
+}
+
+/// Checkerboard corners go dark, light, dark, light around a circle, unlike the outer
+/// corners of the edge squares
+fn alternates(
+    at: &impl Fn(i64, i64) -> f32,
+    x: i64,
+    y: i64,
+    radius: i64,
+    width: i64,
+    height: i64,
+) -> bool {
+    if x < radius || y < radius || x + radius >= width || y + radius >= height {
+        return false;
+    }
+
+    let ring: Vec<f32> = (0..CORNER_RING_SAMPLES)
+        .map(|i| {
+            let angle = i as f32 * std::f32::consts::TAU / CORNER_RING_SAMPLES as f32;
+            let (sin, cos) = angle.sin_cos();
+            at(
+                x + (cos * radius as f32).round() as i64,
+                y + (sin * radius as f32).round() as i64,
+            )
+        })
+        .collect();
+
+    let mean = ring.iter().sum::<f32>() / ring.len() as f32;
+    let transitions = (0..ring.len())
+        .filter(|&i| (ring[i] > mean) != (ring[(i + 1) % ring.len()] > mean))
+        .count();
+
+    transitions == 4
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// Label candidates with grid coordinates, walking from `origin` to its neighbours
+fn grow_grid(candidates: &[Point], origin: usize) -> Option<HashMap<(i32, i32), usize>> {
+    let o = candidates[origin];
+
+    let mut by_distance: Vec<usize> = (0..candidates.len()).filter(|&i| i != origin).collect();
+    by_distance.sort_by(|&a, &b| distance(o, candidates[a]).total_cmp(&distance(o, candidates[b])));
+
+    // two nearest neighbours at roughly a right angle span the grid axes
+    let first = *by_distance.first()?;
+    let u = (candidates[first].0 - o.0, candidates[first].1 - o.1);
+    let second = by_distance.iter().skip(1).find(|&&i| {
+        let v = (candidates[i].0 - o.0, candidates[i].1 - o.1);
+        let cos =
+            (u.0 * v.0 + u.1 * v.1) / (distance(o, candidates[first]) * distance(o, candidates[i]));
+        cos.abs() < 0.5
+    })?;
+
+    let mut grid = HashMap::from([((0, 0), origin), ((1, 0), first), ((0, 1), *second)]);
+    let mut used: Vec<bool> = vec![false; candidates.len()];
+    grid.values().for_each(|&i| used[i] = true);
+
+    loop {
+        let mut added = false;
+        let known: Vec<((i32, i32), usize)> = grid.iter().map(|(k, v)| (*k, *v)).collect();
+
+        for ((i, j), index) in known {
+            for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let target = (i + di, j + dj);
+                if grid.contains_key(&target) {
+                    continue;
+                }
+
+                // continue the line through the previous corner, or follow a neighbouring line
+                let step = [(0, 0), (dj, di), (-dj, -di)].iter().find_map(|(oi, oj)| {
+                    let (from, to) = if (*oi, *oj) == (0, 0) {
+                        (grid.get(&(i - di, j - dj))?, &index)
+                    } else {
+                        (
+                            grid.get(&(i + oi, j + oj))?,
+                            grid.get(&(i + oi + di, j + oj + dj))?,
+                        )
+                    };
+
+                    let (a, b) = (candidates[*from], candidates[*to]);
+                    Some((b.0 - a.0, b.1 - a.1))
+                });
+                let Some(step) = step else {
+                    continue;
+                };
+
+                let p = candidates[index];
+                let predicted = (p.0 + step.0, p.1 + step.1);
+                let tolerance = CORNER_SNAP_TOLERANCE * (step.0.powi(2) + step.1.powi(2)).sqrt();
+
+                let nearest = (0..candidates.len())
+                    .filter(|&c| !used[c])
+                    .min_by(|&a, &b| {
+                        distance(predicted, candidates[a])
+                            .total_cmp(&distance(predicted, candidates[b]))
+                    });
+
+                if let Some(c) = nearest.filter(|&c| distance(predicted, candidates[c]) < tolerance)
+                {
+                    grid.insert(target, c);
+                    used[c] = true;
+                    added = true;
+                }
+            }
+        }
+
+        if !added {
+            break;
+        }
+    }
+
+    Some(grid)
+}
+
+/// Row major corners when the labelled grid is exactly the board
+fn order_grid(
+    candidates: &[Point],
+    grid: &HashMap<(i32, i32), usize>,
+    board: &Checkerboard,
+) -> Option<Vec<Point>> {
+    let min_i = grid.keys().map(|k| k.0).min()?;
+    let max_i = grid.keys().map(|k| k.0).max()?;
+    let min_j = grid.keys().map(|k| k.1).min()?;
+    let max_j = grid.keys().map(|k| k.1).max()?;
+    let (cols, rows) = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+
+    if grid.len() != board.cols * board.rows {
+        return None;
+    }
+
+    let at = |i: i32, j: i32| grid.get(&(min_i + i, min_j + j)).map(|&c| candidates[c]);
+
+    if (cols, rows) == (board.cols, board.rows) {
+        (0..rows as i32)
+            .flat_map(|j| (0..cols as i32).map(move |i| (i, j)))
+            .map(|(i, j)| at(i, j))
+            .collect()
+    } else if (cols, rows) == (board.rows, board.cols) {
+        (0..cols as i32)
+            .flat_map(|i| (0..rows as i32).map(move |j| (i, j)))
+            .map(|(i, j)| at(i, j))
+            .collect()
+    } else {
+        None
+    }
+}
+
+/// Fit a fisheye model to checkerboard pictures, all of the same size
+pub fn calibrate(images: &[GrayImage], board: &Checkerboard) -> Result<Calibration> {
+    let (width, height) = images
+        .first()
+        .map(|image| image.dimensions())
+        .ok_or_else(|| anyhow!("No calibration pictures"))?;
+
+    if images
+        .iter()
+        .any(|image| image.dimensions() != (width, height))
+    {
+        return Err(anyhow!("Calibration pictures must all have the same size"));
+    }
+
+    let views: Vec<Vec<Point>> = images
+        .iter()
+        .enumerate()
+        .filter_map(|(i, image)| {
+            let corners = find_corners(image, board);
+            if corners.is_none() {
+                debug!(
+                    target = "lens::calibration",
+                    "Board not found in picture {}", i
+                );
+            }
+            corners
+        })
+        .collect();
+
+    calibrate_corners(&views, board, width, height)
+}
+
+/// Fit a fisheye model to already detected corners
+pub fn calibrate_corners(
+    views: &[Vec<Point>],
+    board: &Checkerboard,
+    width: u32,
+    height: u32,
+) -> Result<Calibration> {
+    if views.len() < CALIBRATION_MIN_IMAGES {
+        return Err(anyhow!(
+            "Board found in {} pictures, at least {} are needed",
+            views.len(),
+            CALIBRATION_MIN_IMAGES
+        ));
+    }
+
+    let problem = Problem {
+        object: board.object_points(),
+        views: views
+            .iter()
+            .map(|view| view.iter().map(|&(u, v)| [u as f64, v as f64]).collect())
+            .collect(),
+    };
+
+    // focal length guess with the best initial fit
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let mut params = FOV_GUESSES
+        .iter()
+        .map(|fov| {
+            let f = width as f64 / fov.to_radians();
+            let intrinsics = [f, f, cx, cy, 0.0, 0.0, 0.0, 0.0];
+            let mut params = intrinsics.to_vec();
+            for view in problem.views.iter() {
+                params.extend(initial_pose(&problem.object, view, &intrinsics));
+            }
+            params
+        })
+        .min_by(|a, b| problem.cost(a).total_cmp(&problem.cost(b)))
+        .ok_or_else(|| anyhow!("No initial guess"))?;
+
+    levenberg_marquardt(&problem, &mut params);
+
+    let points = problem.views.iter().map(|v| v.len()).sum::<usize>();
+    let rms_error = (problem.cost(&params) / points as f64).sqrt() as f32;
+
+    Ok(Calibration {
+        model: LensModel {
+            width,
+            height,
+            fx: params[0] as f32,
+            fy: params[1] as f32,
+            cx: params[2] as f32,
+            cy: params[3] as f32,
+            k: [
+                params[4] as f32,
+                params[5] as f32,
+                params[6] as f32,
+                params[7] as f32,
+            ],
+        },
+        rms_error,
+        images_used: views.len(),
+    })
+}
+
+/// Reprojection problem, parameters are the 8 intrinsics then 6 pose values per view
+struct Problem {
+    object: Vec<[f64; 3]>,
+    views: Vec<Vec<[f64; 2]>>,
+}
+
+impl Problem {
+    fn residuals(&self, params: &[f64]) -> Vec<f64> {
+        let intrinsics = &params[..8];
+
+        self.views
+            .iter()
+            .enumerate()
+            .flat_map(|(v, observed)| {
+                let pose = &params[8 + v * 6..14 + v * 6];
+                self.object
+                    .iter()
+                    .zip(observed.iter())
+                    .flat_map(move |(point, observed)| {
+                        let [u, w] = project(intrinsics, pose, point);
+                        [u - observed[0], w - observed[1]]
+                    })
+            })
+            .collect()
+    }
+
+    fn cost(&self, params: &[f64]) -> f64 {
+        self.residuals(params).iter().map(|r| r * r).sum()
+    }
+}
+
+fn levenberg_marquardt(problem: &Problem, params: &mut Vec<f64>) {
+    let n = params.len();
+    let mut lambda = 1e-3;
+    let mut residuals = problem.residuals(params);
+    let mut cost: f64 = residuals.iter().map(|r| r * r).sum();
+
+    for _ in 0..CALIBRATION_MAX_ITERATIONS {
+        // forward difference jacobian, column per parameter
+        let jacobian: Vec<Vec<f64>> = (0..n)
+            .map(|p| {
+                let h = 1e-6 * params[p].abs().max(1.0);
+                let mut shifted = params.clone();
+                shifted[p] += h;
+                problem
+                    .residuals(&shifted)
+                    .iter()
+                    .zip(residuals.iter())
+                    .map(|(a, b)| (a - b) / h)
+                    .collect()
+            })
+            .collect();
+
+        let mut jtj = vec![0.0; n * n];
+        let mut jtr = vec![0.0; n];
+        for a in 0..n {
+            jtr[a] = jacobian[a]
+                .iter()
+                .zip(residuals.iter())
+                .map(|(j, r)| j * r)
+                .sum();
+            for b in a..n {
+                let value: f64 = jacobian[a]
+                    .iter()
+                    .zip(jacobian[b].iter())
+                    .map(|(x, y)| x * y)
+                    .sum();
+                jtj[a * n + b] = value;
+                jtj[b * n + a] = value;
+            }
+        }
+
+        let improved = loop {
+            let mut damped = jtj.clone();
+            for d in 0..n {
+                damped[d * n + d] += lambda * jtj[d * n + d].max(1e-9);
+            }
+
+            let step = solve(damped, jtr.iter().map(|g| -g).collect(), n);
+            let candidate: Vec<f64> = match step {
+                Some(step) => params.iter().zip(step.iter()).map(|(p, s)| p + s).collect(),
+                None => params.clone(),
+            };
+            let candidate_residuals = problem.residuals(&candidate);
+            let candidate_cost: f64 = candidate_residuals.iter().map(|r| r * r).sum();
+
+            if candidate_cost < cost {
+                let gain = (cost - candidate_cost) / cost.max(f64::EPSILON);
+                *params = candidate;
+                residuals = candidate_residuals;
+                cost = candidate_cost;
+                lambda = (lambda / 10.0).max(1e-12);
+                break gain > 1e-10;
+            }
+
+            lambda *= 10.0;
+            if lambda > 1e12 {
+                break false;
+            }
+        };
+
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Gaussian elimination with partial pivoting on a row major `n x n` system
+fn solve(mut a: Vec<f64>, mut b: Vec<f64>, n: usize) -> Option<Vec<f64>> {
+    for col in 0..n {
+        let pivot =
+            (col..n).max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs()))?;
+        if a[pivot * n + col].abs() < 1e-15 {
+            return None;
+        }
+
+        if pivot != col {
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+            }
+            b.swap(col, pivot);
+        }
+
+        for row in col + 1..n {
+            let factor = a[row * n + col] / a[col * n + col];
+            if factor == 0.0 {
+                continue;
+            }
+            for k in col..n {
+                a[row * n + k] -= factor * a[col * n + k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row * n + k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row * n + row];
+    }
+
+    Some(x)
+}
+
+/// Board point to distorted pixels
+fn project(intrinsics: &[f64], pose: &[f64], point: &[f64; 3]) -> [f64; 2] {
+    let [fx, fy, cx, cy, k1, k2, k3, k4] = [
+        intrinsics[0],
+        intrinsics[1],
+        intrinsics[2],
+        intrinsics[3],
+        intrinsics[4],
+        intrinsics[5],
+        intrinsics[6],
+        intrinsics[7],
+    ];
+    let r = rotation(&pose[..3]);
+    let camera: Vec<f64> = (0..3)
+        .map(|i| r[i][0] * point[0] + r[i][1] * point[1] + r[i][2] * point[2] + pose[3 + i])
+        .collect();
+
+    if camera[2] <= 1e-9 {
+        // behind the camera, keep the cost high but finite
+        return [1e6, 1e6];
+    }
+
+    let (a, b) = (camera[0] / camera[2], camera[1] / camera[2]);
+    let radius = (a * a + b * b).sqrt();
+    let theta = radius.atan();
+    let t2 = theta * theta;
+    let theta_d = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))));
+    let scale = if radius > 1e-12 {
+        theta_d / radius
+    } else {
+        1.0
+    };
+
+    [fx * a * scale + cx, fy * b * scale + cy]
+}
+
+/// Rodrigues vector to rotation matrix
+fn rotation(rvec: &[f64]) -> [[f64; 3]; 3] {
+    let theta = (rvec[0] * rvec[0] + rvec[1] * rvec[1] + rvec[2] * rvec[2]).sqrt();
+    if theta < 1e-12 {
+        return [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    }
+
+    let (x, y, z) = (rvec[0] / theta, rvec[1] / theta, rvec[2] / theta);
+    let (s, c) = theta.sin_cos();
+    let t = 1.0 - c;
+
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+/// Rotation matrix to Rodrigues vector
+fn rodrigues(r: &[[f64; 3]; 3]) -> [f64; 3] {
+    let cos = ((r[0][0] + r[1][1] + r[2][2] - 1.0) / 2.0).clamp(-1.0, 1.0);
+    let theta = cos.acos();
+
+    if theta < 1e-9 {
+        return [0.0; 3];
+    }
+
+    let axis = [r[2][1] - r[1][2], r[0][2] - r[2][0], r[1][0] - r[0][1]];
+    let norm = (axis[0] * axis[0] + axis[1] * axis[1] + axis[2] * axis[2]).sqrt();
+
+    if norm < 1e-9 {
+        // half turn, axis from the symmetric part
+        let x = ((r[0][0] + 1.0) / 2.0).max(0.0).sqrt();
+        let y = ((r[1][1] + 1.0) / 2.0).max(0.0).sqrt().copysign(r[0][1]);
+        let z = ((r[2][2] + 1.0) / 2.0).max(0.0).sqrt().copysign(r[0][2]);
+        return [x * theta, y * theta, z * theta];
+    }
+
+    [
+        axis[0] / norm * theta,
+        axis[1] / norm * theta,
+        axis[2] / norm * theta,
+    ]
+}
+
+/// Board pose from a homography onto the undistorted normalized plane
+fn initial_pose(object: &[[f64; 3]], observed: &[[f64; 2]], intrinsics: &[f64; 8]) -> Pose {
+    let [fx, fy, cx, cy, ..] = *intrinsics;
+
+    // equidistant guess, θ = r / f
+    let normalized: Vec<[f64; 2]> = observed
+        .iter()
+        .map(|[u, v]| {
+            let (x, y) = ((u - cx) / fx, (v - cy) / fy);
+            let theta = (x * x + y * y).sqrt().min(1.5);
+            let scale = if theta > 1e-12 {
+                theta.tan() / theta
+            } else {
+                1.0
+            };
+            [x * scale, y * scale]
+        })
+        .collect();
+
+    // DLT with h33 = 1
+    let mut ata = vec![0.0; 64];
+    let mut atb = vec![0.0; 8];
+    for (point, [a, b]) in object.iter().zip(normalized.iter()) {
+        let (x, y) = (point[0], point[1]);
+        for (row, rhs) in [
+            ([x, y, 1.0, 0.0, 0.0, 0.0, -a * x, -a * y], *a),
+            ([0.0, 0.0, 0.0, x, y, 1.0, -b * x, -b * y], *b),
+        ] {
+            for i in 0..8 {
+                atb[i] += row[i] * rhs;
+                for j in 0..8 {
+                    ata[i * 8 + j] += row[i] * row[j];
+                }
+            }
+        }
+    }
+
+    let Some(h) = solve(ata, atb, 8) else {
+        return [0.0, 0.0, 0.0, 0.0, 0.0, 1.0];
+    };
+
+    let h1 = [h[0], h[3], h[6]];
+    let h2 = [h[1], h[4], h[7]];
+    let h3 = [h[2], h[5], 1.0];
+    let norm = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+
+    let mut lambda = 2.0 / (norm(h1) + norm(h2));
+    if h3[2] * lambda < 0.0 {
+        lambda = -lambda;
+    }
+
+    // orthonormalize the first two columns
+    let r1 = h1.map(|v| v * lambda);
+    let r1 = r1.map(|v| v / norm(r1));
+    let r2 = h2.map(|v| v * lambda);
+    let dot = r1[0] * r2[0] + r1[1] * r2[1] + r1[2] * r2[2];
+    let r2 = [
+        r2[0] - dot * r1[0],
+        r2[1] - dot * r1[1],
+        r2[2] - dot * r1[2],
+    ];
+    let r2 = r2.map(|v| v / norm(r2));
+    let r3 = [
+        r1[1] * r2[2] - r1[2] * r2[1],
+        r1[2] * r2[0] - r1[0] * r2[2],
+        r1[0] * r2[1] - r1[1] * r2[0],
+    ];
+
+    let rvec = rodrigues(&[
+        [r1[0], r2[0], r3[0]],
+        [r1[1], r2[1], r3[1]],
+        [r1[2], r2[2], r3[2]],
+    ]);
+    let t = h3.map(|v| v * lambda);
+
+    [rvec[0], rvec[1], rvec[2], t[0], t[1], t[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOARD: Checkerboard = Checkerboard {
+        cols: 9,
+        rows: 6,
+        square_size: 0.03,
+    };
+
+    /// Wide angle lens at 640x360
+    fn lens() -> LensModel {
+        LensModel {
+            width: 640,
+            height: 360,
+            fx: 210.0,
+            fy: 212.0,
+            cx: 322.0,
+            cy: 178.0,
+            k: [-0.03, 0.01, -0.002, 0.0005],
+        }
+    }
+
+    /// Board poses around the picture, Rodrigues vector then translation in meters
+    fn poses() -> Vec<Pose> {
+        vec![
+            [0.0, 0.0, 0.0, -0.12, -0.075, 0.35],
+            [0.3, -0.2, 0.1, -0.25, -0.1, 0.3],
+            [-0.25, 0.35, -0.05, 0.02, -0.05, 0.3],
+            [0.1, 0.1, 0.3, -0.3, 0.0, 0.28],
+            [-0.4, -0.1, 0.0, -0.05, -0.15, 0.32],
+            [0.2, 0.4, -0.2, -0.1, 0.05, 0.27],
+        ]
+    }
+
+    /// Board point in camera coordinates
+    fn camera_point(pose: &Pose, point: &[f64; 3]) -> [f64; 3] {
+        let r = rotation(&pose[..3]);
+
+        [0, 1, 2]
+            .map(|i| r[i][0] * point[0] + r[i][1] * point[1] + r[i][2] * point[2] + pose[3 + i])
+    }
+
+    /// Inner corners seen through the lens model
+    fn view(model: &LensModel, pose: &Pose) -> Vec<Point> {
+        BOARD
+            .object_points()
+            .iter()
+            .map(|point| {
+                let [x, y, z] = camera_point(pose, point);
+                model.project((x / z) as f32, (y / z) as f32, model.width, model.height)
+            })
+            .collect()
+    }
+
+    /// Checkerboard picture through the lens model, white around the board
+    fn render(model: &LensModel, pose: &Pose) -> GrayImage {
+        let r = rotation(&pose[..3]);
+        let size = BOARD.square_size as f64;
+
+        GrayImage::from_fn(model.width, model.height, |u, v| {
+            let Some((a, b)) = model.unproject(u as f32, v as f32, model.width, model.height)
+            else {
+                return image::Luma([255]);
+            };
+
+            // ray (a, b, 1) against the board plane, Cramer's rule on [r0 r1 -ray] [x y s] = -t
+            let ray = [a as f64, b as f64, 1.0];
+            let m = [0, 1, 2].map(|i| [r[i][0], r[i][1], -ray[i]]);
+            let rhs = [-pose[3], -pose[4], -pose[5]];
+            let det = |m: &[[f64; 3]; 3]| {
+                m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                    - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                    + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+            };
+            let with_column = |c: usize| {
+                let mut m = m;
+                (0..3).for_each(|i| m[i][c] = rhs[i]);
+                det(&m)
+            };
+            let d = det(&m);
+            let (x, y) = (with_column(0) / d, with_column(1) / d);
+
+            // squares around the inner corners, one more on each side
+            let (col, row) = ((x / size).floor() + 1.0, (y / size).floor() + 1.0);
+            let on_board = (0.0..=BOARD.cols as f64).contains(&col)
+                && (0.0..=BOARD.rows as f64).contains(&row);
+
+            if on_board && (col + row) as i64 % 2 == 0 {
+                image::Luma([0])
+            } else {
+                image::Luma([255])
+            }
+        })
+    }
+
+    fn assert_recovers(calibration: &Calibration, expected: &LensModel, tolerance: f32) {
+        let model = &calibration.model;
+
+        for (name, actual, expected) in [
+            ("fx", model.fx, expected.fx),
+            ("fy", model.fy, expected.fy),
+            ("cx", model.cx, expected.cx),
+            ("cy", model.cy, expected.cy),
+        ] {
+            assert!(
+                (actual - expected).abs() < tolerance,
+                "{} is {}, expected {}",
+                name,
+                actual,
+                expected
+            );
+        }
+
+        // coefficients trade off against each other, the curve they describe doesn't
+        for i in 0..=10 {
+            let theta = i as f32 * 0.1;
+
+            assert!(
+                (model.distort_angle(theta) - expected.distort_angle(theta)).abs()
+                    < tolerance / expected.fx,
+                "θd({}) is {}, expected {}",
+                theta,
+                model.distort_angle(theta),
+                expected.distort_angle(theta)
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_round_trip() {
+        for rvec in [
+            [0.0, 0.0, 0.0],
+            [0.3, -0.2, 0.1],
+            [0.0, 0.0, 3.0],
+            [-1.2, 0.4, 0.9],
+        ] {
+            let back = rodrigues(&rotation(&rvec));
+
+            assert!(
+                rvec.iter()
+                    .zip(back.iter())
+                    .all(|(a, b)| (a - b).abs() < 1e-9),
+                "{:?} came back as {:?}",
+                rvec,
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn calibrate_synthetic_corners() {
+        let lens = lens();
+        let views: Vec<Vec<Point>> = poses().iter().map(|pose| view(&lens, pose)).collect();
+
+        let calibration = calibrate_corners(&views, &BOARD, lens.width, lens.height).unwrap();
+
+        assert_eq!(calibration.images_used, views.len());
+        assert!(calibration.rms_error < 0.01, "{:?}", calibration);
+        assert_recovers(&calibration, &lens, 0.5);
+    }
+
+    #[test]
+    fn calibrate_needs_enough_views() {
+        let lens = lens();
+        let views: Vec<Vec<Point>> = poses()[..2].iter().map(|pose| view(&lens, pose)).collect();
+
+        assert!(calibrate_corners(&views, &BOARD, lens.width, lens.height).is_err());
+    }
+
+    #[test]
+    fn find_corners_in_rendered_board() {
+        let lens = lens();
+
+        for pose in poses().iter() {
+            let corners = find_corners(&render(&lens, pose), &BOARD).expect("board not found");
+            let expected = view(&lens, pose);
+
+            assert_eq!(corners.len(), expected.len());
+
+            for (u, v) in expected {
+                assert!(
+                    corners.iter().any(|&(x, y)| distance((x, y), (u, v)) < 1.0),
+                    "no corner found near ({}, {}) for {:?}",
+                    u,
+                    v,
+                    pose
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn calibrate_rendered_boards() {
+        let lens = lens();
+        let images: Vec<GrayImage> = poses().iter().map(|pose| render(&lens, pose)).collect();
+
+        let calibration = calibrate(&images, &BOARD).unwrap();
+
+        assert_eq!(calibration.images_used, images.len());
+        assert!(calibration.rms_error < 0.5, "{:?}", calibration);
+        assert_recovers(&calibration, &lens, 3.0);
+    }
+}
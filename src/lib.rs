@@ -1,5 +1,5 @@
-use std::fs::OpenOptions;
 use std::path::Path;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -23,31 +23,136 @@ use ffmpeg::audio::FfmpegAudio;
 use ffmpeg::audio::FFMPEG_DEFAULT_AUDIO_DEVICE;
 use ffmpeg::Ffmpeg;
 use ffmpeg::FfmpegExtraArgs;
+use ffmpeg::FfmpegInputFormat;
+use ffmpeg::FfmpegVideoFilter;
 use ffmpeg::FFMPEG_DEFAULT_STREAM_DIR;
-use image::codecs::webp::WebPEncoder;
-use image::ExtendedColorType;
 use live_stream::LiveStream;
 use rpicam::Rpicam;
 use rpicam::RpicamDeviceMode;
+use rpicam::RpicamLibav;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use tracing::debug;
 use tracing::error;
+use tracing::warn;
 
+use crate::analysis::motion::MotionConfig;
+use crate::analysis::motion::MotionDetector;
+use crate::analysis::motion::MOTION_DEFAULT_LEARNING_RATE;
+use crate::analysis::motion::MOTION_DEFAULT_MIN_AREA;
+use crate::analysis::motion::MOTION_DEFAULT_OFF_FRAMES;
+use crate::analysis::motion::MOTION_DEFAULT_ON_FRAMES;
+use crate::analysis::motion::MOTION_DEFAULT_REPORT_FRAMES;
+use crate::analysis::motion::MOTION_DEFAULT_SENSITIVITY;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::OnnxPoseEstimator;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::OnnxPresenceDetector;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::POSE_DEFAULT_INPUT_SIZE;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::POSE_DEFAULT_INTERVAL;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::PRESENCE_DEFAULT_INPUT_SIZE;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::PRESENCE_DEFAULT_INTERVAL;
+#[cfg(feature = "onnx")]
+use crate::analysis::onnx::PRESENCE_DEFAULT_THRESHOLD;
+use crate::analysis::pose::POSE_DEFAULT_KEYPOINT_THRESHOLD;
+use crate::analysis::safe_sleep::SafeSleepConfig;
+use crate::analysis::safe_sleep::SafeSleepMonitor;
+use crate::analysis::safe_sleep::SafeSleepUpdate;
+use crate::analysis::safe_sleep::SAFE_SLEEP_DEFAULT_CLEAR_DURATION;
+use crate::analysis::safe_sleep::SAFE_SLEEP_DEFAULT_FACE_HIDDEN_DURATION;
+use crate::analysis::safe_sleep::SAFE_SLEEP_DEFAULT_STOMACH_DURATION;
+use crate::analysis::AnalyzerRunner;
+use crate::audio_monitor::activity::AUDIO_ACTIVITY_DEFAULT_DEBOUNCE;
+use crate::audio_monitor::activity::AUDIO_ACTIVITY_DEFAULT_HANGOVER;
+use crate::audio_monitor::activity::AUDIO_ACTIVITY_DEFAULT_HYSTERESIS_DB;
+use crate::audio_monitor::activity::AUDIO_ACTIVITY_DEFAULT_SUMMARY_INTERVAL;
+use crate::audio_monitor::cry::CryConfig;
+use crate::audio_monitor::cry::CRY_DEFAULT_MIN_BURSTS;
+use crate::audio_monitor::cry::CRY_DEFAULT_SENSITIVITY;
+use crate::audio_monitor::level::to_dbfs;
+use crate::audio_monitor::level::AUDIO_MONITOR_DEFAULT_NOISE_FLOOR_WINDOW;
+use crate::audio_monitor::level::AUDIO_MONITOR_DEFAULT_THRESHOLD_DB;
 use crate::audio_monitor::AudioMonitor;
 use crate::audio_monitor::AudioMonitorContext;
+use crate::backoff::ExponentialBackoff;
+use crate::backoff::BACKOFF_DEFAULT_HEALTHY_PERIOD;
+use crate::backoff::BACKOFF_DEFAULT_INITIAL_DELAY;
+use crate::backoff::BACKOFF_DEFAULT_JITTER;
+use crate::backoff::BACKOFF_DEFAULT_MAX_DELAY;
 use crate::ffmpeg::audio::FfmpegAudioSampleFormat;
 use crate::ffmpeg::audio::FFMPEG_DEFAULT_AUDIO_SAMPLE_FORMAT;
 use crate::ffmpeg::audio::FFMPEG_DEFAULT_AUDIO_SAMPLE_RATE;
+use crate::lens::LensCorrection;
+use crate::lens::LENS_DEFAULT_ZOOM;
+use crate::live_stream::clip::ClipExporter;
+use crate::live_stream::clip::CLIP_DEFAULT_DIR_NAME;
+use crate::live_stream::frame_hub::FrameHubConfig;
+use crate::live_stream::frame_hub::FRAME_HUB_DEFAULT_FPS;
+use crate::live_stream::frame_hub::FRAME_HUB_DEFAULT_MAX_WIDTH;
+use crate::live_stream::ring_buffer::RING_BUFFER_DEFAULT_DURATION;
+use crate::live_stream::ring_buffer::RING_BUFFER_DEFAULT_MAX_BYTES;
+use crate::live_stream::LIVE_STREAM_BOOTSTRAP_RETRY;
+use crate::live_stream::LIVE_STREAM_DEFAULT_STALL_TIMEOUT;
+use crate::process_control::resources::ResourceSampler;
+use crate::process_control::resources::ResourceWatch;
+use crate::process_control::resources::PROCESS_DEFAULT_SAMPLE_INTERVAL;
+use crate::server::api::api_handler_clip_export;
+use crate::server::api::api_handler_metrics;
+use crate::server::api::api_handler_mjpeg;
+use crate::server::api::api_handler_snapshot;
+use crate::server::api::api_handler_snapshot_index;
+use crate::server::api::api_handler_stream_reset;
+use crate::server::api::api_handler_stream_status;
+use crate::server::api::api_handler_timelapse_index;
 use crate::server::middleware::auth::AuthMiddleware;
 use crate::server::middleware::headers::HlsHeadersMiddleware;
+use crate::server::mjpeg::MjpegOptions;
+use crate::server::mjpeg::MjpegStream;
+use crate::server::mjpeg::MJPEG_DEFAULT_FPS;
 use crate::server::websocket::ws_handler_telemetry;
 use crate::server::DEFAULT_MICRO_UI;
+use crate::snapshot::encode;
+use crate::snapshot::history::write_atomic;
+use crate::snapshot::history::SnapshotEntry;
+use crate::snapshot::history::SnapshotHistory;
+use crate::snapshot::history::SNAPSHOT_ALERT_PREFIX;
+use crate::snapshot::SnapshotCache;
+use crate::snapshot::SnapshotOptions;
+use crate::snapshot::SNAPSHOT_DEFAULT_DIR_NAME;
+use crate::snapshot::SNAPSHOT_DEFAULT_FORMAT;
+use crate::snapshot::SNAPSHOT_DEFAULT_HISTORY_COUNT;
+use crate::snapshot::SNAPSHOT_DEFAULT_HISTORY_MAX_AGE;
+use crate::snapshot::SNAPSHOT_DEFAULT_INTERVAL;
+use crate::supervisor::RestartLimit;
+use crate::supervisor::RestartPolicy;
+use crate::supervisor::SUPERVISOR_DEFAULT_RESTART_WINDOW;
+use crate::telemetry::events::Event;
 use crate::telemetry::events::EventDispatcher;
+use crate::telemetry::events::Severity;
+use crate::timelapse::TimelapseLibrary;
+use crate::timelapse::TimelapseRecorder;
+use crate::timelapse::TimelapseWindow;
+use crate::timelapse::RECORDING_DEFAULT_DIR_NAME;
+use crate::timelapse::TIMELAPSE_DEFAULT_END;
+use crate::timelapse::TIMELAPSE_DEFAULT_FPS;
+use crate::timelapse::TIMELAPSE_DEFAULT_INTERVAL;
+use crate::timelapse::TIMELAPSE_DEFAULT_MAX_WIDTH;
+use crate::timelapse::TIMELAPSE_DEFAULT_RETENTION;
+use crate::timelapse::TIMELAPSE_DEFAULT_START;
+use crate::timelapse::TIMELAPSE_FINISH_TIMEOUT;
 
+pub mod analysis;
 pub mod audio_monitor;
+pub mod backoff;
 pub mod config;
 pub mod ffmpeg;
 pub mod gpio;
+pub mod lens;
 pub mod live_stream;
 pub mod mlx90640;
 pub mod mmwave;
@@ -55,13 +160,33 @@ pub mod process_control;
 pub mod rpicam;
 pub mod serde_stuff;
 pub mod server;
+pub mod snapshot;
+pub mod supervisor;
 pub mod telemetry;
+pub mod timelapse;
 
 /// Check if file exists
 pub async fn file_exists(file: impl AsRef<Path>) -> bool {
     tokio::fs::try_exists(file).await.is_ok_and(|res| res)
 }
 
+/// Create a directory served by the web server, a failure only disables serving it
+fn prepare_served_dir(dir: &Path) -> bool {
+    match std::fs::create_dir_all(dir) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(
+                target = "web_server",
+                "Not serving {}, failed to create it: {}",
+                dir.display(),
+                e
+            );
+
+            false
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BabyPi {
     config: TomlConfig,
@@ -72,6 +197,10 @@ pub struct BabyPi {
     web_server: Option<ServerHandle>,
     audio_monitor: Option<AudioMonitor>,
     snapshot_pipeline: Option<JoinHandle<()>>,
+    analyzers: Option<AnalyzerRunner>,
+    safe_sleep_monitor: Option<JoinHandle<()>>,
+    timelapse: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+    resources: Option<ResourceWatch>,
 }
 
 impl BabyPi {
@@ -84,18 +213,47 @@ impl BabyPi {
             web_server: None,
             audio_monitor: None,
             snapshot_pipeline: None,
+            analyzers: None,
+            safe_sleep_monitor: None,
+            timelapse: None,
+            resources: None,
         }
     }
 
     pub async fn run(&mut self) -> Result<()> {
+        self.resources = self.sample_interval().map(|interval| {
+            ResourceWatch::spawn(
+                ResourceSampler::new("babypi", std::process::id()),
+                interval,
+                self.config
+                    .telemetry
+                    .process_stats_events
+                    .unwrap_or(false)
+                    .then(|| self.events.clone()),
+            )
+        });
+
         self.live_stream = Some(self.run_live_stream().await?);
-        self.web_server = Some(self.run_web_server().await?);
 
         if self.config.monitoring.enabled {
             self.audio_monitor = Some(self.run_audio_monitor().await?);
         }
 
-        self.snapshot_pipeline = Some(self.run_snapshot_pipeline().await?);
+        self.web_server = Some(self.run_web_server().await?);
+
+        self.analyzers = self.run_analyzers()?;
+
+        if self.config.analysis.safe_sleep.enabled {
+            self.safe_sleep_monitor = self.run_safe_sleep_monitor();
+        }
+
+        if self.config.recording.timelapse.enabled {
+            self.timelapse = self.run_timelapse()?;
+        }
+
+        if self.config.snapshot.enabled.unwrap_or(true) {
+            self.snapshot_pipeline = Some(self.run_snapshot_pipeline().await?);
+        }
 
         Ok(())
     }
@@ -109,6 +267,10 @@ impl BabyPi {
             live_stream.stop().await;
         }
 
+        if let Some(resources) = self.resources.take() {
+            resources.stop();
+        }
+
         if let Some(mut audio_monitor) = self.audio_monitor.take() {
             audio_monitor.stop();
         }
@@ -117,20 +279,46 @@ impl BabyPi {
             snapshot_pipeline.abort();
         }
 
+        if let Some(mut analyzers) = self.analyzers.take() {
+            analyzers.stop();
+        }
+
+        if let Some(safe_sleep_monitor) = self.safe_sleep_monitor.take() {
+            safe_sleep_monitor.abort();
+        }
+
+        if let Some((stop, mut timelapse)) = self.timelapse.take() {
+            let _ = stop.send(());
+
+            // let ffmpeg write out the night so far
+            if tokio::time::timeout(TIMELAPSE_FINISH_TIMEOUT, &mut timelapse)
+                .await
+                .is_err()
+            {
+                error!(target = "timelapse", "Time-lapse did not finish in time");
+                timelapse.abort();
+            }
+        }
+
         Ok(())
     }
 
+    /// Process resource sampling period, `None` when disabled
+    fn sample_interval(&self) -> Option<Duration> {
+        match self.config.telemetry.process_stats_interval {
+            Some(0) => None,
+            Some(interval) => Some(Duration::from_secs(interval)),
+            None => Some(PROCESS_DEFAULT_SAMPLE_INTERVAL),
+        }
+    }
+
     async fn run_live_stream(&self) -> Result<LiveStream> {
-        let mode = if self.config.hardware.camera.width.is_some()
-            && self.config.hardware.camera.height.is_some()
-            && self.config.hardware.camera.fps.is_some()
-        {
-            Some(RpicamDeviceMode::new(
-                "selected",
-                self.config.hardware.camera.width.unwrap(),
-                self.config.hardware.camera.height.unwrap(),
-                self.config.hardware.camera.fps.unwrap(),
-            ))
+        let mode = if let (Some(width), Some(height), Some(fps)) = (
+            self.config.hardware.camera.width,
+            self.config.hardware.camera.height,
+            self.config.hardware.camera.fps,
+        ) {
+            Some(RpicamDeviceMode::new("selected", width, height, fps))
         } else {
             None
         };
@@ -148,7 +336,9 @@ impl BabyPi {
                     .map(str::to_string)
                     .collect::<Vec<String>>()
             }),
-        );
+        )
+        .with_intra(self.config.hardware.camera.intra)
+        .with_limits(self.config.stream.limits.rpicam.clone());
 
         let ffmpeg_audio =
             if self.config.stream.audio.is_some_and(|v| v) && self.config.hardware.mic.enabled {
@@ -220,6 +410,47 @@ impl BabyPi {
             None
         };
 
+        // let rpicam capture and mux audio on the camera clock
+        let (cam, ffmpeg_audio, input_format) = if self.config.stream.av_sync.is_some_and(|v| v) {
+            (
+                cam.with_libav(Some(RpicamLibav::new(None, ffmpeg_audio))),
+                None,
+                FfmpegInputFormat::MpegTs,
+            )
+        } else {
+            (cam, ffmpeg_audio, FfmpegInputFormat::H264)
+        };
+
+        let lens_config = &self.config.hardware.camera.lens;
+        let lens = lens_config
+            .model
+            .clone()
+            .filter(|_| lens_config.enabled)
+            .map(|model| LensCorrection::new(model, lens_config.zoom.unwrap_or(LENS_DEFAULT_ZOOM)));
+
+        let video_filter = lens
+            .as_ref()
+            .filter(|_| lens_config.stream.unwrap_or(false))
+            .map(|lens| {
+                let filter = lens.model().ffmpeg_filter(
+                    self.config
+                        .hardware
+                        .camera
+                        .width
+                        .unwrap_or(lens.model().width),
+                    self.config
+                        .hardware
+                        .camera
+                        .height
+                        .unwrap_or(lens.model().height),
+                    lens.zoom(),
+                );
+
+                FfmpegVideoFilter::new(filter)
+                    .with_encoder(lens_config.stream_encoder.clone())
+                    .with_bitrate(lens_config.stream_bitrate.clone())
+            });
+
         let ffmpeg = Ffmpeg::new(
             self.config
                 .stream
@@ -229,9 +460,117 @@ impl BabyPi {
             ffmpeg_audio,
             extra_args,
             self.verbose,
+        )
+        .with_input_format(input_format)
+        .with_video_filter(video_filter)
+        .with_limits(self.config.stream.limits.ffmpeg.clone());
+
+        let backoff = ExponentialBackoff::new(
+            self.config
+                .stream
+                .restart_delay
+                .map(Duration::from_secs)
+                .unwrap_or(BACKOFF_DEFAULT_INITIAL_DELAY),
+            self.config
+                .stream
+                .restart_delay_max
+                .map(Duration::from_secs)
+                .unwrap_or(BACKOFF_DEFAULT_MAX_DELAY),
+            BACKOFF_DEFAULT_JITTER,
+            match self.config.stream.restart_retries {
+                Some(0) => None,
+                Some(retries) => Some(retries),
+                None => Some(LIVE_STREAM_BOOTSTRAP_RETRY),
+            },
+            self.config
+                .stream
+                .restart_healthy_period
+                .map(Duration::from_secs)
+                .unwrap_or(BACKOFF_DEFAULT_HEALTHY_PERIOD),
         );
 
-        let live_stream = LiveStream::new(cam, ffmpeg, self.events.clone());
+        let stall_timeout = match self.config.stream.stall_timeout {
+            Some(0) => None,
+            Some(timeout) => Some(Duration::from_secs(timeout)),
+            None => Some(LIVE_STREAM_DEFAULT_STALL_TIMEOUT),
+        };
+
+        let restart_limit = match self.config.stream.restart_limit {
+            None | Some(0) => None,
+            Some(max_restarts) => Some(RestartLimit::new(
+                max_restarts,
+                self.config
+                    .stream
+                    .restart_limit_window
+                    .map(Duration::from_secs)
+                    .unwrap_or(SUPERVISOR_DEFAULT_RESTART_WINDOW),
+            )),
+        };
+
+        // analytics get small frames unless a size is configured
+        let (analysis_width, analysis_height) = match (
+            self.config.analysis.max_width,
+            self.config.analysis.max_height,
+        ) {
+            (None, None) => (Some(FRAME_HUB_DEFAULT_MAX_WIDTH), None),
+            size => size,
+        };
+
+        let live_stream = LiveStream::new(cam, ffmpeg, self.events.clone())
+            .with_backoff(backoff)
+            .with_restart_policy(
+                self.config
+                    .stream
+                    .restart_policy
+                    .unwrap_or(RestartPolicy::Always),
+            )
+            .with_restart_limit(restart_limit)
+            .with_sample_interval(self.sample_interval())
+            .with_stats_events(self.config.telemetry.process_stats_events.unwrap_or(false))
+            .with_memory_limit(
+                self.config
+                    .stream
+                    .memory_limit
+                    .filter(|mib| *mib > 0)
+                    .map(|mib| mib * 1024 * 1024),
+            )
+            .with_stall_timeout(stall_timeout)
+            .with_lens(
+                lens.clone()
+                    .filter(|_| lens_config.snapshots.unwrap_or(true)),
+            )
+            .with_ring_buffer(
+                self.config
+                    .stream
+                    .ring_buffer_duration
+                    .map(Duration::from_secs)
+                    .unwrap_or(RING_BUFFER_DEFAULT_DURATION),
+                self.config
+                    .stream
+                    .ring_buffer_max_size
+                    .map(|mib| (mib * 1024 * 1024) as usize)
+                    .unwrap_or(RING_BUFFER_DEFAULT_MAX_BYTES),
+            )
+            .with_frame_hub_config(
+                FrameHubConfig::new(
+                    self.config.analysis.fps.unwrap_or(FRAME_HUB_DEFAULT_FPS),
+                    analysis_width,
+                    analysis_height,
+                )
+                .with_decode_mode(self.config.analysis.decode_mode.unwrap_or_default())
+                .with_lens(
+                    lens.clone()
+                        .filter(|_| lens_config.analysis.unwrap_or(true)),
+                ),
+            )
+            .with_mjpeg_hub_config(
+                FrameHubConfig::new(
+                    self.config.stream.mjpeg_fps.unwrap_or(MJPEG_DEFAULT_FPS),
+                    self.config.stream.mjpeg_width,
+                    self.config.stream.mjpeg_height,
+                )
+                .with_lens(lens.filter(|_| lens_config.mjpeg.unwrap_or(true))),
+            );
 
         live_stream.start().await;
 
@@ -257,6 +596,42 @@ impl BabyPi {
         let telemetry_config = self.config.telemetry.clone();
 
         let events = self.events.clone();
+        let live_stream = self.live_stream.clone();
+        let resources = self.resources.clone();
+        let audio_levels = self.audio_monitor.as_ref().map(AudioMonitor::level_watch);
+        let snapshot_cache = SnapshotCache::default();
+        let snapshot_history = self.snapshot_history();
+        let timelapse_library = self.timelapse_library();
+        let clip_exporter = self.live_stream.as_ref().map(|live_stream| {
+            ClipExporter::new(
+                live_stream.ring_buffer(),
+                self.config.stream.clips_dir.clone().unwrap_or_else(|| {
+                    self.config
+                        .stream
+                        .data_dir
+                        .clone()
+                        .unwrap_or(FFMPEG_DEFAULT_STREAM_DIR.into())
+                        .join(CLIP_DEFAULT_DIR_NAME)
+                }),
+                self.config.hardware.camera.fps,
+            )
+        });
+        let serve_clips = clip_exporter
+            .as_ref()
+            .is_some_and(|clip_exporter| prepare_served_dir(clip_exporter.dir()));
+        let serve_snapshots = prepare_served_dir(snapshot_history.dir());
+        let serve_timelapses = prepare_served_dir(timelapse_library.dir());
+        let mjpeg = self.live_stream.as_ref().map(|live_stream| {
+            MjpegStream::new(
+                live_stream.mjpeg_hub(),
+                MjpegOptions::new(
+                    self.config.stream.mjpeg_fps.unwrap_or(MJPEG_DEFAULT_FPS),
+                    self.config.stream.mjpeg_width,
+                    self.config.stream.mjpeg_height,
+                    self.config.stream.mjpeg_quality,
+                ),
+            )
+        });
 
         let server = HttpServer::new(move || {
             let cors = Cors::default()
@@ -274,10 +649,70 @@ impl BabyPi {
 
             app = app.service(Files::new("/stream", stream_dir.clone()).use_etag(false));
 
+            app = app
+                .app_data(web::Data::new(snapshot_history.clone()))
+                .route("/api/snapshots", web::get().to(api_handler_snapshot_index));
+
+            // a missing directory would make `Files` serve the working directory
+            if serve_snapshots {
+                app = app.service(Files::new("/snapshots", snapshot_history.dir()));
+            }
+
+            app = app
+                .app_data(web::Data::new(timelapse_library.clone()))
+                .route(
+                    "/api/timelapses",
+                    web::get().to(api_handler_timelapse_index),
+                );
+
+            if serve_timelapses {
+                app = app.service(Files::new("/recordings", timelapse_library.dir()));
+            }
+
             if telemetry_config.enabled {
                 app = app.route("/telemetry", web::get().to(ws_handler_telemetry));
             }
 
+            if let Some(resources) = resources.clone() {
+                app = app.app_data(web::Data::new(resources));
+            }
+
+            if let Some(audio_levels) = audio_levels.clone() {
+                app = app.app_data(web::Data::new(audio_levels));
+            }
+
+            if let Some(live_stream) = live_stream.clone() {
+                app = app
+                    .app_data(web::Data::new(live_stream))
+                    .route(
+                        "/api/stream/status",
+                        web::get().to(api_handler_stream_status),
+                    )
+                    .route(
+                        "/api/stream/reset",
+                        web::post().to(api_handler_stream_reset),
+                    )
+                    .route("/api/metrics", web::get().to(api_handler_metrics))
+                    .app_data(web::Data::new(snapshot_cache.clone()))
+                    .route("/snapshot", web::get().to(api_handler_snapshot));
+            }
+
+            if let Some(clip_exporter) = clip_exporter.clone() {
+                if serve_clips {
+                    app = app.service(Files::new("/clips", clip_exporter.dir()));
+                }
+
+                app = app
+                    .route("/api/clips", web::post().to(api_handler_clip_export))
+                    .app_data(web::Data::new(clip_exporter));
+            }
+
+            if let Some(mjpeg) = mjpeg.clone() {
+                app = app
+                    .app_data(web::Data::new(mjpeg))
+                    .route("/mjpeg", web::get().to(api_handler_mjpeg));
+            }
+
             if let Some(static_dir) = static_dir.clone() {
                 app = app.service(Files::new("/", static_dir).index_file("index.html"));
             } else {
@@ -308,26 +743,71 @@ impl BabyPi {
     }
 
     async fn run_audio_monitor(&mut self) -> Result<AudioMonitor> {
+        let backend = self.config.monitoring_backend();
+        let device = self.config.monitoring_device();
+
+        let cry = &self.config.monitoring.cry;
+
         let mut monitor = AudioMonitor::new(
             AudioMonitorContext::new(
-                self.config
-                    .hardware
-                    .mic
-                    .sample_format
-                    .clone()
-                    .unwrap_or(FfmpegAudioSampleFormat::from_str(
-                        FFMPEG_DEFAULT_AUDIO_SAMPLE_FORMAT,
-                    )?)
-                    .into(),
+                self.config.hardware.mic.sample_format.clone().unwrap_or(
+                    FfmpegAudioSampleFormat::from_str(FFMPEG_DEFAULT_AUDIO_SAMPLE_FORMAT)?,
+                ),
                 self.config
                     .hardware
                     .mic
                     .sample_rate
                     .unwrap_or(FFMPEG_DEFAULT_AUDIO_SAMPLE_RATE),
                 self.config.hardware.mic.channels.unwrap_or(1),
-                self.config.hardware.mic.device.clone(),
-                self.config.monitoring.rms_threshold,
-            ),
+                device,
+                self.config.monitoring.min_dbfs.or(self
+                    .config
+                    .monitoring
+                    .rms_threshold
+                    .map(to_dbfs)),
+            )
+            .with_backend(backend)
+            .with_threshold_db(
+                self.config
+                    .monitoring
+                    .threshold_db
+                    .unwrap_or(AUDIO_MONITOR_DEFAULT_THRESHOLD_DB),
+            )
+            .with_hysteresis_db(
+                self.config
+                    .monitoring
+                    .hysteresis_db
+                    .unwrap_or(AUDIO_ACTIVITY_DEFAULT_HYSTERESIS_DB),
+            )
+            .with_activity_timing(
+                self.config
+                    .monitoring
+                    .activity_debounce_ms
+                    .map(Duration::from_millis)
+                    .unwrap_or(AUDIO_ACTIVITY_DEFAULT_DEBOUNCE),
+                self.config
+                    .monitoring
+                    .activity_hangover_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(AUDIO_ACTIVITY_DEFAULT_HANGOVER),
+                self.config
+                    .monitoring
+                    .activity_summary_interval_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(AUDIO_ACTIVITY_DEFAULT_SUMMARY_INTERVAL),
+            )
+            .with_noise_floor_window(
+                self.config
+                    .monitoring
+                    .noise_floor_window_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(AUDIO_MONITOR_DEFAULT_NOISE_FLOOR_WINDOW),
+            )
+            .with_calibration(self.config.monitoring.calibration)
+            .with_cry_detection(cry.enabled.then(|| CryConfig {
+                sensitivity: cry.sensitivity.unwrap_or(CRY_DEFAULT_SENSITIVITY),
+                min_bursts: cry.min_bursts.unwrap_or(CRY_DEFAULT_MIN_BURSTS),
+            })),
             Some(self.events.get_sender()),
         );
 
@@ -336,18 +816,270 @@ impl BabyPi {
         Ok(monitor)
     }
 
+    fn run_analyzers(&self) -> Result<Option<AnalyzerRunner>> {
+        let Some(live_stream) = self.live_stream.as_ref() else {
+            return Ok(None);
+        };
+        let analysis = &self.config.analysis;
+
+        let mut runner =
+            AnalyzerRunner::new(live_stream.frame_hub(), Some(self.events.get_sender()));
+
+        if analysis.motion.enabled {
+            let motion = &analysis.motion;
+
+            runner.start(Box::new(MotionDetector::new(MotionConfig {
+                sensitivity: motion.sensitivity.unwrap_or(MOTION_DEFAULT_SENSITIVITY),
+                min_area: motion.min_area.unwrap_or(MOTION_DEFAULT_MIN_AREA),
+                learning_rate: motion.learning_rate.unwrap_or(MOTION_DEFAULT_LEARNING_RATE),
+                on_frames: motion.on_frames.unwrap_or(MOTION_DEFAULT_ON_FRAMES),
+                off_frames: motion.off_frames.unwrap_or(MOTION_DEFAULT_OFF_FRAMES),
+                report_frames: motion.report_frames.unwrap_or(MOTION_DEFAULT_REPORT_FRAMES),
+                zones: motion.zones.clone().unwrap_or_default(),
+                masks: motion.masks.clone().unwrap_or_default(),
+            })));
+        }
+
+        #[cfg(feature = "onnx")]
+        if let (true, Some(model)) = (analysis.presence.enabled, analysis.presence.model.as_ref()) {
+            let presence = &analysis.presence;
+
+            runner.start(Box::new(OnnxPresenceDetector::new(
+                model,
+                presence.input_size.unwrap_or(PRESENCE_DEFAULT_INPUT_SIZE),
+                presence.threshold.unwrap_or(PRESENCE_DEFAULT_THRESHOLD),
+                presence
+                    .interval
+                    .map(Duration::from_secs)
+                    .unwrap_or(PRESENCE_DEFAULT_INTERVAL),
+            )?));
+        }
+
+        #[cfg(feature = "onnx")]
+        if let (true, Some(model)) = (analysis.pose.enabled, analysis.pose.model.as_ref()) {
+            let pose = &analysis.pose;
+
+            runner.start(Box::new(OnnxPoseEstimator::new(
+                model,
+                pose.input_size.unwrap_or(POSE_DEFAULT_INPUT_SIZE),
+                pose.threshold.unwrap_or(POSE_DEFAULT_KEYPOINT_THRESHOLD),
+                pose.interval
+                    .map(Duration::from_secs)
+                    .unwrap_or(POSE_DEFAULT_INTERVAL),
+            )?));
+        }
+
+        Ok(Some(runner))
+    }
+
+    fn run_safe_sleep_monitor(&self) -> Option<JoinHandle<()>> {
+        let live_stream = self.live_stream.clone()?;
+        let events = self.events.clone();
+        // evidence outlives the periodic snapshots pruning
+        let history = SnapshotHistory::new(self.snapshot_history().dir().to_path_buf(), None, None)
+            .with_prefix(SNAPSHOT_ALERT_PREFIX);
+        let options = self.snapshot_options();
+        let safe_sleep = &self.config.analysis.safe_sleep;
+
+        let mut monitor = SafeSleepMonitor::new(SafeSleepConfig {
+            keypoint_threshold: safe_sleep
+                .keypoint_threshold
+                .unwrap_or(POSE_DEFAULT_KEYPOINT_THRESHOLD),
+            stomach_duration: safe_sleep
+                .stomach_duration
+                .map(Duration::from_secs)
+                .unwrap_or(SAFE_SLEEP_DEFAULT_STOMACH_DURATION),
+            face_hidden_duration: safe_sleep
+                .face_hidden_duration
+                .map(Duration::from_secs)
+                .unwrap_or(SAFE_SLEEP_DEFAULT_FACE_HIDDEN_DURATION),
+            clear_duration: safe_sleep
+                .clear_duration
+                .map(Duration::from_secs)
+                .unwrap_or(SAFE_SLEEP_DEFAULT_CLEAR_DURATION),
+        });
+
+        Some(tokio::spawn(async move {
+            let mut rx = events.get_receiver();
+
+            loop {
+                let updates = match rx.recv().await {
+                    Ok(Event::Presence { present, .. }) => {
+                        monitor.set_presence(present);
+                        continue;
+                    }
+                    Ok(Event::Pose { keypoints, .. }) => {
+                        monitor.update(&keypoints, std::time::Instant::now())
+                    }
+                    Err(RecvError::Closed) => break,
+                    _ => continue,
+                };
+
+                for update in updates {
+                    match update {
+                        SafeSleepUpdate::Alert { reason, duration } => {
+                            error!(
+                                target = "babypi::safe_sleep",
+                                "Unsafe sleep: {:?} for {} s",
+                                reason,
+                                duration.as_secs()
+                            );
+
+                            // snapshots may take a while, keep following pose results meanwhile
+                            let live_stream = live_stream.clone();
+                            let history = history.clone();
+                            let options = options.clone();
+                            let events = events.clone();
+
+                            tokio::spawn(async move {
+                                let snapshot = match live_stream.snapshot().await {
+                                    Some(image) => tokio::task::spawn_blocking(move || {
+                                        history.save(&encode(&image, &options)?, options.format)
+                                    })
+                                    .await
+                                    .map_err(anyhow::Error::from)
+                                    .and_then(|r| r)
+                                    .inspect_err(|e| {
+                                        error!(
+                                            target = "babypi::safe_sleep",
+                                            "Failed to save alert snapshot: {}", e
+                                        )
+                                    })
+                                    .ok()
+                                    .map(|entry| entry.filename),
+                                    None => None,
+                                };
+
+                                events.send(Event::SleepAlert {
+                                    reason,
+                                    severity: Severity::High,
+                                    duration_secs: duration.as_secs(),
+                                    snapshot,
+                                });
+                            });
+                        }
+                        SafeSleepUpdate::Cleared { reason } => {
+                            debug!(target = "babypi::safe_sleep", "Cleared {:?}", reason);
+
+                            events.send(Event::SleepAlertCleared { reason });
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    fn run_timelapse(&self) -> Result<Option<(oneshot::Sender<()>, JoinHandle<()>)>> {
+        let Some(live_stream) = self.live_stream.clone() else {
+            return Ok(None);
+        };
+        let timelapse = &self.config.recording.timelapse;
+
+        let recorder = TimelapseRecorder::new(
+            live_stream,
+            self.timelapse_library(),
+            TimelapseWindow::parse(
+                timelapse
+                    .start
+                    .as_deref()
+                    .unwrap_or(TIMELAPSE_DEFAULT_START),
+                timelapse.end.as_deref().unwrap_or(TIMELAPSE_DEFAULT_END),
+            )?,
+            timelapse
+                .interval
+                .map(Duration::from_secs)
+                .unwrap_or(TIMELAPSE_DEFAULT_INTERVAL),
+            timelapse.fps.unwrap_or(TIMELAPSE_DEFAULT_FPS),
+        )
+        .with_max_dimensions(
+            timelapse.max_width.or(Some(TIMELAPSE_DEFAULT_MAX_WIDTH)),
+            timelapse.max_height,
+        );
+
+        let (stop, stopped) = oneshot::channel();
+
+        Ok(Some((
+            stop,
+            tokio::spawn(recorder.run(self.events.clone(), stopped)),
+        )))
+    }
+
+    fn timelapse_library(&self) -> TimelapseLibrary {
+        let recording = &self.config.recording;
+
+        TimelapseLibrary::new(
+            recording.dir.clone().unwrap_or_else(|| {
+                self.config
+                    .stream
+                    .data_dir
+                    .clone()
+                    .unwrap_or(FFMPEG_DEFAULT_STREAM_DIR.into())
+                    .join(RECORDING_DEFAULT_DIR_NAME)
+            }),
+            match recording.timelapse.retention {
+                Some(0) => None,
+                Some(count) => Some(count),
+                None => Some(TIMELAPSE_DEFAULT_RETENTION),
+            },
+        )
+    }
+
+    fn snapshot_options(&self) -> SnapshotOptions {
+        let snapshot = &self.config.snapshot;
+
+        SnapshotOptions::new(
+            snapshot.format.unwrap_or(SNAPSHOT_DEFAULT_FORMAT),
+            snapshot.max_width,
+            snapshot.max_height,
+            snapshot.quality,
+        )
+    }
+
+    fn snapshot_history(&self) -> SnapshotHistory {
+        let snapshot = &self.config.snapshot;
+
+        SnapshotHistory::new(
+            snapshot.dir.clone().unwrap_or_else(|| {
+                self.config
+                    .stream
+                    .data_dir
+                    .clone()
+                    .unwrap_or(FFMPEG_DEFAULT_STREAM_DIR.into())
+                    .join(SNAPSHOT_DEFAULT_DIR_NAME)
+            }),
+            match snapshot.history_count {
+                Some(0) => None,
+                Some(count) => Some(count),
+                None => Some(SNAPSHOT_DEFAULT_HISTORY_COUNT),
+            },
+            match snapshot.history_max_age {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => Some(SNAPSHOT_DEFAULT_HISTORY_MAX_AGE),
+            },
+        )
+    }
+
     async fn run_snapshot_pipeline(&mut self) -> Result<JoinHandle<()>> {
         let events = self.events.clone();
-        let snapshot_path = self
+        let history = self.snapshot_history();
+        let data_dir: PathBuf = self
             .config
             .stream
             .data_dir
-            .as_ref()
-            .and_then(|p| p.to_str().map(str::to_string))
-            .unwrap_or(FFMPEG_DEFAULT_STREAM_DIR.to_string());
+            .clone()
+            .unwrap_or(FFMPEG_DEFAULT_STREAM_DIR.into());
+        let interval = self
+            .config
+            .snapshot
+            .interval
+            .map(Duration::from_secs)
+            .unwrap_or(SNAPSHOT_DEFAULT_INTERVAL)
+            .max(Duration::from_secs(1));
+        let options = self.snapshot_options();
 
         Ok(tokio::spawn(async move {
-            let mut timer = tokio::time::interval(Duration::from_secs(60));
+            let mut timer = tokio::time::interval(interval);
             let mut rx = events.get_receiver();
 
             loop {
@@ -357,26 +1089,62 @@ impl BabyPi {
                         debug!(target = "babypi::snapshot_pipeline", "Sent snapshot request");
                     }
                     event = rx.recv() => {
-                        if let Ok(telemetry::events::Event::SnapshotData { data }) = event {
-                            debug!(target = "babypi::snapshot_pipeline", "Received snapshot data");
-
-                            let mut file = OpenOptions::new()
-                                        .write(true)
-                                        .create(true)
-                                        .truncate(true)
-                                        .open(format!("{}/snapshot.webp", snapshot_path))
-                                        .expect("Failed to open file snapshot.webp");
-
-                            let encoder = WebPEncoder::new_lossless(&mut file);
-                            match encoder.encode(&data, data.width(), data.height(), ExtendedColorType::Rgb8) {
-                                Ok(_) => {
-                                    debug!(target = "babypi::snapshot_pipeline", "Saved snapshot.webp");
-
-                                    events.send(telemetry::events::Event::SnapshotUpdated { filesize: file.metadata().map(|m| m.len()).unwrap_or_default(), width: data.width(), height: data.height() });
-                                }
-                                Err(e) => {
-                                    debug!(target = "babypi::snapshot_pipeline", "Failed to encode webp image: {}", e);
-                                }
+                        let data = match event {
+                            Ok(telemetry::events::Event::SnapshotData { data }) => data,
+                            Err(RecvError::Closed) => break,
+                            _ => continue,
+                        };
+
+                        debug!(target = "babypi::snapshot_pipeline", "Received snapshot data");
+
+                        let (width, height) = options.dimensions(data.width(), data.height());
+                        let history = history.clone();
+                        let options = options.clone();
+                        let latest =
+                            data_dir.join(format!("snapshot.{}", options.format.extension()));
+
+                        let saved = tokio::task::spawn_blocking(move || -> Result<SnapshotEntry> {
+                            let encoded = encode(&data, &options)?;
+                            let entry = history.save(&encoded, options.format)?;
+                            write_atomic(&latest, &encoded)?;
+
+                            let removed = history.prune()?;
+                            if removed > 0 {
+                                debug!(
+                                    target = "babypi::snapshot_pipeline",
+                                    "Pruned {} old snapshots", removed
+                                );
+                            }
+
+                            Ok(entry)
+                        })
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(|r| r);
+
+                        match saved {
+                            Ok(entry) => {
+                                debug!(
+                                    target = "babypi::snapshot_pipeline",
+                                    "Saved {}", entry.filename
+                                );
+
+                                events.send(telemetry::events::Event::SnapshotUpdated {
+                                    filename: entry.filename,
+                                    filesize: entry.filesize,
+                                    width,
+                                    height,
+                                });
+                            }
+                            Err(e) => {
+                                error!(
+                                    target = "babypi::snapshot_pipeline",
+                                    "Failed to save snapshot: {}", e
+                                );
+
+                                events.send(telemetry::events::Event::SnapshotError {
+                                    message: e.to_string(),
+                                });
                             }
                         }
                     }
@@ -1,136 +1,250 @@
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
 use std::time::Duration;
+use std::time::Instant;
 
+use crate::backoff::ExponentialBackoff;
 use crate::ffmpeg::FFMPEG_BIN;
+use crate::ffmpeg::FFMPEG_DEFAULT_STREAM_SEGMENT_TIME;
+use crate::lens::LensCorrection;
+use crate::live_stream::av_sync::AvSyncTracker;
+use crate::live_stream::frame_hub::FrameHub;
+use crate::live_stream::frame_hub::FrameHubConfig;
+use crate::live_stream::gop_cache::GopCache;
+use crate::live_stream::h264::is_first_slice;
+use crate::live_stream::h264::nal_type;
+use crate::live_stream::h264::NalSplitter;
+use crate::live_stream::h264::H264_NAL_SPS;
+use crate::live_stream::mpegts::MpegTsDemuxer;
+use crate::live_stream::ring_buffer::GopRingBuffer;
+use crate::live_stream::stats::PipeMetrics;
+use crate::live_stream::stats::PipeStats;
+use crate::live_stream::stats::PipeTap;
+use crate::process_control::resources::ProcessStats;
+use crate::process_control::resources::PROCESS_DEFAULT_SAMPLE_INTERVAL;
+use crate::process_control::PROCESS_DEFAULT_SHUTDOWN_TIMEOUT;
 use crate::rpicam::RPICAM_BIN;
+use crate::supervisor::RestartLimit;
+use crate::supervisor::RestartPolicy;
+use crate::supervisor::SupervisedGroup;
+use crate::supervisor::Supervisor;
+use crate::supervisor::SupervisorStatus;
+use crate::telemetry::events::Event;
 use crate::telemetry::events::EventDispatcher;
+use crate::telemetry::events::Service;
 use crate::{ffmpeg::Ffmpeg, process_control::ProcessControl, rpicam::Rpicam};
 use anyhow::anyhow;
 use anyhow::Result;
+use bytes::{Bytes, BytesMut};
 use image::RgbImage;
-use openh264::decoder::Decoder;
-use openh264::formats::YUVSource;
-use openh264::nal_units;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::{ChildStdin, ChildStdout};
 use tokio::sync::broadcast::error::RecvError;
-use tokio::sync::{broadcast, RwLock};
-use tokio::task::JoinHandle;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::{AbortHandle, JoinHandle};
 use tracing::{debug, error, info, warn};
 
-pub const LIVE_STREAM_BOOTSTRAP_RETRY: u8 = 10;
-
-#[derive(Debug, Default)]
-struct LiveStreamState {
-    rpicam_process: Option<ProcessControl>,
-    ffmpeg_process: Option<ProcessControl>,
+pub mod av_sync;
+pub mod clip;
+pub mod frame_hub;
+pub mod gop_cache;
+pub mod h264;
+pub mod mpegts;
+pub mod ring_buffer;
+pub mod stats;
+
+pub const LIVE_STREAM_BOOTSTRAP_RETRY: u32 = 10;
+pub const LIVE_STREAM_AV_OFFSET_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+pub const LIVE_STREAM_DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+pub const LIVE_STREAM_STALL_STARTUP_GRACE: Duration = Duration::from_secs(30);
+pub const LIVE_STREAM_SEGMENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+pub const LIVE_STREAM_SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const LIVE_STREAM_READ_BUFFER_SIZE: usize = 64 * 1024;
+/// Chunks buffered towards ffmpeg before the camera reader waits
+pub const LIVE_STREAM_PIPE_CAPACITY: usize = 32;
+/// Chunks buffered for lossy taps before they start dropping
+pub const LIVE_STREAM_TAP_CAPACITY: usize = 32;
+pub const LIVE_STREAM_SNAPSHOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Camera piped into the encoder, restarted as a whole by the supervisor
+#[derive(Debug)]
+struct LiveStreamPipeline {
+    rpicam: Arc<Rpicam>,
+    ffmpeg: Arc<Ffmpeg>,
+    frame_hub: FrameHub,
+    mjpeg_hub: FrameHub,
+    ring_buffer: Arc<Mutex<GopRingBuffer>>,
+    lens: Option<LensCorrection>,
+    events: EventDispatcher,
+    stall_timeout: Option<Duration>,
 
     handle_pipe: Option<JoinHandle<()>>,
-    handle_watch: Option<JoinHandle<()>>,
+    handle_segments: Option<JoinHandle<()>>,
 
-    running: bool,
-    retry_count: u8,
+    stats: Option<Arc<PipeStats>>,
+    gop_cache: Option<Arc<Mutex<GopCache>>>,
 }
 
-impl LiveStreamState {
-    pub async fn start(
-        &mut self,
-        rpicam: &Rpicam,
-        ffmpeg: &Ffmpeg,
-        events: EventDispatcher,
-    ) -> Result<()> {
-        let mut rpicam_child = rpicam.spawn()?;
+/// Live stream status snapshot for the API
+pub type LiveStreamStatus = SupervisorStatus;
+
+impl LiveStreamPipeline {
+    async fn spawn(&mut self) -> Result<Vec<ProcessControl>> {
+        let mut rpicam_child = self.rpicam.spawn()?;
         let rpicam_stdout = rpicam_child.stdout.take().ok_or_else(|| {
             anyhow!(
                 "Failed to capture child process output for `{}`",
                 RPICAM_BIN
             )
         })?;
-        let rpicam_process = ProcessControl::new(RPICAM_BIN, rpicam_child)?;
+        let mut rpicam_process = ProcessControl::new(RPICAM_BIN, rpicam_child)?;
 
         info!(
             target = "live_stream",
             "Bootstrapped `{}` for live streaming", RPICAM_BIN
         );
 
-        let mut ffmpeg_child = ffmpeg.spawn()?;
-        let ffmpeg_stdin = ffmpeg_child
-            .stdin
-            .take()
-            .ok_or_else(|| anyhow!("Failed to open child process input for `{}`", FFMPEG_BIN))?;
-        let ffmpeg_process = ProcessControl::new(FFMPEG_BIN, ffmpeg_child)?;
+        let (ffmpeg_stdin, ffmpeg_process) = match self.spawn_ffmpeg() {
+            Ok(ffmpeg) => ffmpeg,
+            Err(e) => {
+                // the camera stays busy until rpicam is gone, the retry would fail on it
+                rpicam_process
+                    .shutdown(PROCESS_DEFAULT_SHUTDOWN_TIMEOUT)
+                    .await;
+
+                return Err(e);
+            }
+        };
 
         info!(
             target = "live_stream",
             "Bootstrapped `{}` for live streaming", FFMPEG_BIN
         );
 
-        let handle_pipe = tapped_io_pipe(rpicam_stdout, ffmpeg_stdin, events);
+        let stats = Arc::new(PipeStats::new());
+        let gop_cache = Arc::new(Mutex::new(GopCache::default()));
+
+        let handle_pipe = tapped_io_pipe(
+            rpicam_stdout,
+            ffmpeg_stdin,
+            self.rpicam.is_timestamped(),
+            stats.clone(),
+            PipeTaps {
+                frame_hub: self.frame_hub.clone(),
+                mjpeg_hub: self.mjpeg_hub.clone(),
+                gop_cache: gop_cache.clone(),
+                ring_buffer: self.ring_buffer.clone(),
+            },
+            self.lens.clone(),
+            self.events.clone(),
+        );
 
         info!(target = "live_stream", "Connected IO pipe");
 
-        self.rpicam_process = Some(rpicam_process);
-        self.ffmpeg_process = Some(ffmpeg_process);
+        let handle_segments = segment_watch(self.ffmpeg.playlist_path(), stats.clone());
+
         self.handle_pipe = Some(handle_pipe);
+        self.handle_segments = Some(handle_segments);
+        self.stats = Some(stats);
+        self.gop_cache = Some(gop_cache);
+
+        Ok(vec![rpicam_process, ffmpeg_process])
+    }
 
-        self.running = true;
+    fn spawn_ffmpeg(&self) -> Result<(ChildStdin, ProcessControl)> {
+        let mut ffmpeg_child = self.ffmpeg.spawn()?;
+        let ffmpeg_stdin = ffmpeg_child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to open child process input for `{}`", FFMPEG_BIN))?;
 
-        Ok(())
+        Ok((ffmpeg_stdin, ProcessControl::new(FFMPEG_BIN, ffmpeg_child)?))
     }
 
-    pub async fn reset(&mut self) {
-        self.stop().await;
-        self.retry_count = 0;
+    /// Check pipe activity, returns the reason if the pipeline looks frozen
+    fn detect_stall(&self, timeout: Duration) -> Option<String> {
+        let stats = self.stats.as_ref()?;
+
+        let byte_stall = match stats.last_byte_age() {
+            Some(age) => (age >= timeout).then_some(age),
+            None => (stats.uptime() >= timeout + LIVE_STREAM_STALL_STARTUP_GRACE)
+                .then(|| stats.uptime()),
+        };
+
+        if let Some(age) = byte_stall {
+            return Some(format!(
+                "No data from `{}` for {}s",
+                RPICAM_BIN,
+                age.as_secs()
+            ));
+        }
+
+        let segment_timeout =
+            timeout.max(Duration::from_secs(2 * FFMPEG_DEFAULT_STREAM_SEGMENT_TIME));
+
+        let segment_stall = match stats.last_segment_age() {
+            Some(age) => (age >= segment_timeout).then_some(age),
+            None => (stats.uptime() >= segment_timeout + LIVE_STREAM_STALL_STARTUP_GRACE)
+                .then(|| stats.uptime()),
+        };
+
+        segment_stall.map(|age| format!("No segments from `{}` for {}s", FFMPEG_BIN, age.as_secs()))
     }
+}
 
-    pub async fn stop(&mut self) {
-        self.running = false;
+impl SupervisedGroup for LiveStreamPipeline {
+    fn start(&mut self) -> impl Future<Output = Result<Vec<ProcessControl>>> + Send {
+        self.spawn()
+    }
 
-        if let Some(handle_watch) = self.handle_watch.take() {
-            handle_watch.abort();
+    fn check(&mut self) -> Option<String> {
+        if let Some(stats) = self.stats.as_ref() {
+            stats.sample_rates();
         }
 
-        if let Some(mut rpicam_process) = self.rpicam_process.take() {
-            if let Err(e) = rpicam_process.stop() {
-                error!(
-                    target = "live_stream",
-                    "Error while stopping `{}`: {}", RPICAM_BIN, e
-                );
-            }
-        }
+        let reason = self
+            .stall_timeout
+            .and_then(|timeout| self.detect_stall(timeout))?;
 
-        if let Some(mut ffmpeg_process) = self.ffmpeg_process.take() {
-            if let Err(e) = ffmpeg_process.stop() {
-                error!(
-                    target = "live_stream",
-                    "Error while stopping `{}`: {}", FFMPEG_BIN, e
-                );
-            }
-        }
+        warn!(target = "live_stream", "Live stream stalled: {}", reason);
+
+        Some(reason)
+    }
 
+    async fn stop(&mut self) {
         if let Some(handle_pipe) = self.handle_pipe.take() {
             handle_pipe.abort();
         }
-    }
-
-    pub fn retry_increment(&mut self) -> u8 {
-        self.retry_count += 1;
 
-        self.retry_count
-    }
+        if let Some(handle_segments) = self.handle_segments.take() {
+            handle_segments.abort();
+        }
 
-    pub fn is_running(&self) -> bool {
-        self.running
+        self.stats = None;
+        self.gop_cache = None;
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LiveStream {
     rpicam: Arc<Rpicam>,
     ffmpeg: Arc<Ffmpeg>,
-    state: Arc<RwLock<LiveStreamState>>,
-    watchdog: Arc<RwLock<Option<JoinHandle<()>>>>,
     events: EventDispatcher,
+    policy: RestartPolicy,
+    backoff: ExponentialBackoff,
+    restart_limit: Option<RestartLimit>,
+    stall_timeout: Option<Duration>,
+    sample_interval: Option<Duration>,
+    stats_events: bool,
+    memory_limit: Option<u64>,
+    frame_hub: FrameHub,
+    mjpeg_hub: FrameHub,
+    ring_buffer: Arc<Mutex<GopRingBuffer>>,
+    lens: Option<LensCorrection>,
+    supervisor: Arc<OnceLock<Supervisor<LiveStreamPipeline>>>,
 }
 
 impl LiveStream {
@@ -138,140 +252,226 @@ impl LiveStream {
         Self {
             rpicam: Arc::new(rpicam),
             ffmpeg: Arc::new(ffmpeg),
-            state: Arc::new(RwLock::new(LiveStreamState::default())),
-            watchdog: Arc::new(RwLock::new(None)),
             events,
+            policy: RestartPolicy::Always,
+            backoff: ExponentialBackoff {
+                max_retries: Some(LIVE_STREAM_BOOTSTRAP_RETRY),
+                ..Default::default()
+            },
+            restart_limit: None,
+            stall_timeout: Some(LIVE_STREAM_DEFAULT_STALL_TIMEOUT),
+            sample_interval: Some(PROCESS_DEFAULT_SAMPLE_INTERVAL),
+            stats_events: false,
+            memory_limit: None,
+            frame_hub: FrameHub::default(),
+            mjpeg_hub: FrameHub::default(),
+            ring_buffer: Arc::new(Mutex::new(GopRingBuffer::default())),
+            lens: None,
+            supervisor: Arc::new(OnceLock::new()),
         }
     }
 
-    /// Start streaming
-    pub async fn start(&self) {
-        let state_ref = self.state.clone();
-        let rpicam_ref = self.rpicam.clone();
-        let ffmpeg_ref = self.ffmpeg.clone();
-        let events = self.events.clone();
+    /// Keep the given span of recent video in memory, within a memory cap
+    pub fn with_ring_buffer(mut self, duration: Duration, max_bytes: usize) -> Self {
+        self.ring_buffer = Arc::new(Mutex::new(GopRingBuffer::new(duration, max_bytes)));
 
-        let watchdog = tokio::spawn(async move {
-            loop {
-                let state_lock = state_ref.read().await;
-                let is_running = state_lock.running;
-                let retry_count = state_lock.retry_count;
-                drop(state_lock);
-
-                if !is_running {
-                    if retry_count < LIVE_STREAM_BOOTSTRAP_RETRY {
-                        let mut state_lock = state_ref.write().await;
-                        state_lock.retry_increment();
-
-                        if let Err(e) = state_lock
-                            .start(&rpicam_ref, &ffmpeg_ref, events.clone())
-                            .await
-                        {
-                            error!(
-                                target = "live_stream",
-                                "Error while starting live stream: {}", e
-                            );
-                        } else {
-                            // set up watch task
-
-                            let mut watch_rpicam = if let Some(watch_rpicam) =
-                                state_lock.rpicam_process.as_mut().and_then(|p| p.exit_rx())
-                            {
-                                watch_rpicam
-                            } else {
-                                error!(
-                                    target = "live_stream",
-                                    "Failed to get watch receiver for `{}`", RPICAM_BIN
-                                );
-
-                                drop(state_lock);
-
-                                continue;
-                            };
-
-                            let mut watch_ffmpeg = if let Some(watch_ffmpeg) =
-                                state_lock.ffmpeg_process.as_mut().and_then(|p| p.exit_rx())
-                            {
-                                watch_ffmpeg
-                            } else {
-                                error!(
-                                    target = "live_stream",
-                                    "Failed to get watch receiver for `{}`", FFMPEG_BIN
-                                );
-
-                                drop(state_lock);
-
-                                continue;
-                            };
-
-                            let state_ref_watch = state_ref.clone();
-
-                            let handle_watch = tokio::spawn(async move {
-                                tokio::select! {
-                                    r = &mut watch_rpicam => {
-                                        match r {
-                                            Ok(exit_code) => {
-                                                warn!(target = "live_stream", "Process `{}` exit: {}", RPICAM_BIN, exit_code);
-                                            }
-                                            Err(e) => {
-                                                error!(target = "live_stream", "Process `{}` watch error: {}", RPICAM_BIN, e);
-                                            }
-                                        }
-                                    }
-                                    r = &mut watch_ffmpeg => {
-                                        match r {
-                                            Ok(exit_code) => {
-                                                warn!(target = "live_stream", "Process `{}` exit: {}", FFMPEG_BIN, exit_code);
-                                            }
-                                            Err(e) => {
-                                                error!(target = "live_stream", "Process `{}` watch error: {}", FFMPEG_BIN, e);
-                                            }
-                                        }
-                                    }
-                                    else => {
-                                        error!(target = "live_stream", "Both `{}` and `{}` seem to have exited prematurely...", RPICAM_BIN, FFMPEG_BIN);
-                                    }
-                                }
-
-                                state_ref_watch.write().await.stop().await;
-                            });
-
-                            state_lock.handle_watch = Some(handle_watch);
-                        }
+        self
+    }
 
-                        drop(state_lock);
-                    } else {
-                        error!(target = "live_stream", "Too many retries: {}", retry_count);
+    /// Pre-event video buffer, survives pipeline restarts
+    pub fn ring_buffer(&self) -> Arc<Mutex<GopRingBuffer>> {
+        self.ring_buffer.clone()
+    }
 
-                        break;
-                    }
-                }
+    /// Set decoded frames rate and resolution for analytics consumers
+    pub fn with_frame_hub_config(mut self, config: FrameHubConfig) -> Self {
+        self.frame_hub = FrameHub::new(config);
 
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-        });
+        self
+    }
+
+    /// Decoded frames source, survives pipeline restarts
+    pub fn frame_hub(&self) -> FrameHub {
+        self.frame_hub.clone()
+    }
+
+    /// Set decoded frames rate and resolution for MJPEG viewers, apart from analytics
+    pub fn with_mjpeg_hub_config(mut self, config: FrameHubConfig) -> Self {
+        self.mjpeg_hub = FrameHub::new(config);
+
+        self
+    }
 
-        let mut watchdog_lock = self.watchdog.write().await;
-        *watchdog_lock = Some(watchdog);
-        drop(watchdog_lock);
+    /// Decoded frames source for MJPEG viewers, only decoding while someone watches
+    pub fn mjpeg_hub(&self) -> FrameHub {
+        self.mjpeg_hub.clone()
+    }
+
+    /// Dewarp snapshots, frame hub frames are set through its own config
+    pub fn with_lens(mut self, lens: Option<LensCorrection>) -> Self {
+        self.lens = lens;
+
+        self
+    }
+
+    /// Restart the pipeline when no data flows for the given time, `None` disables
+    pub fn with_stall_timeout(mut self, stall_timeout: Option<Duration>) -> Self {
+        self.stall_timeout = stall_timeout;
+
+        self
+    }
+
+    /// Set watchdog restart delays and retry budget
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+
+        self
+    }
+
+    /// Set when the pipeline comes back after going down, `always` by default
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+
+        self
+    }
+
+    /// Give up after too many restarts within a time window
+    pub fn with_restart_limit(mut self, limit: Option<RestartLimit>) -> Self {
+        self.restart_limit = limit;
+
+        self
+    }
+
+    /// Sample `rpicam` and `ffmpeg` resource usage, `None` disables
+    pub fn with_sample_interval(mut self, interval: Option<Duration>) -> Self {
+        self.sample_interval = interval;
+
+        self
+    }
+
+    /// Publish the samples as `Event::ProcessStats`
+    pub fn with_stats_events(mut self, stats_events: bool) -> Self {
+        self.stats_events = stats_events;
+
+        self
+    }
+
+    /// Restart the pipeline when a process resident memory exceeds the limit, in bytes
+    pub fn with_memory_limit(mut self, limit: Option<u64>) -> Self {
+        self.memory_limit = limit;
+
+        self
+    }
+
+    /// Pipeline supervisor, built from the settings on first use
+    fn supervisor(&self) -> &Supervisor<LiveStreamPipeline> {
+        self.supervisor.get_or_init(|| {
+            let pipeline = LiveStreamPipeline {
+                rpicam: self.rpicam.clone(),
+                ffmpeg: self.ffmpeg.clone(),
+                frame_hub: self.frame_hub.clone(),
+                mjpeg_hub: self.mjpeg_hub.clone(),
+                ring_buffer: self.ring_buffer.clone(),
+                lens: self.lens.clone(),
+                events: self.events.clone(),
+                stall_timeout: self.stall_timeout,
+                handle_pipe: None,
+                handle_segments: None,
+                stats: None,
+                gop_cache: None,
+            };
+
+            Supervisor::new(Service::VideoStream, pipeline, self.events.clone())
+                .with_policy(self.policy)
+                .with_backoff(self.backoff.clone())
+                .with_restart_limit(self.restart_limit)
+                .with_sample_interval(self.sample_interval)
+                .with_stats_events(self.stats_events)
+                .with_memory_limit(self.memory_limit)
+        })
+    }
+
+    /// Start streaming
+    pub async fn start(&self) {
+        self.supervisor().start().await;
     }
 
     /// Stop streaming and reset state
     pub async fn stop(&self) {
-        let mut watchdog_lock = self.watchdog.write().await;
-        if let Some(watchdog) = watchdog_lock.take() {
-            info!(target = "live_stream", "Stopping stream watchdog");
-            watchdog.abort();
-        }
-        drop(watchdog_lock);
+        info!(target = "live_stream", "Stopping live stream");
+
+        self.supervisor().stop().await;
+    }
 
-        self.state.write().await.reset().await;
+    /// Restart the pipeline with a fresh retry budget
+    pub async fn reset(&self) {
+        info!(target = "live_stream", "Manual live stream reset");
+
+        self.supervisor().reset().await;
     }
 
     /// Are we live?
     pub async fn is_running(&self) -> bool {
-        self.state.read().await.is_running()
+        self.supervisor().is_running().await
     }
+
+    /// Current pipe throughput metrics, if streaming
+    pub async fn metrics(&self) -> Option<PipeMetrics> {
+        self.supervisor()
+            .group()
+            .read()
+            .await
+            .stats
+            .as_ref()
+            .map(|stats| stats.metrics())
+    }
+
+    /// Latest `rpicam` and `ffmpeg` resource usage, if streaming
+    pub async fn process_stats(&self) -> Vec<ProcessStats> {
+        self.supervisor().resources().await
+    }
+
+    /// Decode the most recent picture, `None` when not streaming or no keyframe showed up in time
+    pub async fn snapshot(&self) -> Option<RgbImage> {
+        let (gop_cache, stats) = {
+            let group = self.supervisor().group();
+            let pipeline = group.read().await;
+            (pipeline.gop_cache.clone()?, pipeline.stats.clone()?)
+        };
+
+        snapshot(&gop_cache, &stats, self.lens.clone()).await
+    }
+
+    /// Current watchdog view of the stream
+    pub async fn status(&self) -> LiveStreamStatus {
+        self.supervisor().status().await
+    }
+}
+
+/// Count playlist updates as produced segments
+fn segment_watch(playlist: PathBuf, stats: Arc<PipeStats>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(LIVE_STREAM_SEGMENT_POLL_INTERVAL);
+        let mut last_modified = None;
+
+        loop {
+            timer.tick().await;
+
+            let Ok(modified) = tokio::fs::metadata(&playlist)
+                .await
+                .and_then(|m| m.modified())
+            else {
+                continue;
+            };
+
+            // first observation might be a leftover from a previous run
+            if last_modified.is_some_and(|last| last != modified) {
+                stats.add_segment();
+            }
+
+            last_modified = Some(modified);
+        }
+    })
 }
 
 #[allow(dead_code)]
@@ -285,29 +485,133 @@ fn simple_io_pipe(mut rpicam_stdout: ChildStdout, mut ffmpeg_stdin: ChildStdin)
     })
 }
 
+/// Consumers fed from the camera stream
+struct PipeTaps {
+    frame_hub: FrameHub,
+    mjpeg_hub: FrameHub,
+    gop_cache: Arc<Mutex<GopCache>>,
+    ring_buffer: Arc<Mutex<GopRingBuffer>>,
+}
+
+/// Decode the latest picture from the GOP cache, waiting for the first keyframe if needed
+async fn snapshot(
+    gop_cache: &Mutex<GopCache>,
+    stats: &PipeStats,
+    lens: Option<LensCorrection>,
+) -> Option<RgbImage> {
+    let requested_at = Instant::now();
+    let deadline = requested_at + LIVE_STREAM_SNAPSHOT_TIMEOUT;
+
+    let stream = loop {
+        if let Some(stream) = gop_cache.lock().ok().and_then(|cache| cache.stream()) {
+            break Some(stream);
+        }
+
+        if Instant::now() >= deadline {
+            debug!(target = "live_stream", "No keyframe cached for snapshot");
+            break None;
+        }
+
+        tokio::time::sleep(LIVE_STREAM_SNAPSHOT_POLL_INTERVAL).await;
+    };
+
+    let image = match stream {
+        Some(stream) => tokio::task::spawn_blocking(move || {
+            let image = stream.decode_last()?;
+
+            Some(match lens {
+                Some(lens) => lens.apply_rgb(&image),
+                None => image,
+            })
+        })
+        .await
+        .ok()
+        .flatten(),
+        None => None,
+    };
+
+    match image {
+        Some(_) => {
+            let latency = requested_at.elapsed();
+            stats.add_snapshot(Some(latency));
+
+            debug!(
+                target = "live_stream",
+                "Snapshot decoded in {} ms",
+                latency.as_millis()
+            );
+        }
+        None => {
+            stats.add_snapshot(None);
+
+            warn!(target = "live_stream", "Unable to produce a snapshot");
+        }
+    }
+
+    image
+}
+
 #[allow(dead_code)]
 fn tapped_io_pipe(
     mut rpicam_stdout: ChildStdout,
     mut ffmpeg_stdin: ChildStdin,
+    timestamped: bool,
+    stats: Arc<PipeStats>,
+    taps: PipeTaps,
+    lens: Option<LensCorrection>,
     events: EventDispatcher,
 ) -> JoinHandle<()> {
+    let PipeTaps {
+        frame_hub,
+        mjpeg_hub,
+        gop_cache,
+        ring_buffer,
+    } = taps;
+
     let events_tx = events.get_sender();
     let events_rx = events.get_receiver();
 
     tokio::spawn(async move {
-        let (tx, mut rx_pipe) = broadcast::channel(10);
-        let rx_tap = tx.subscribe();
+        // ffmpeg gets every byte, a slow consumer holds back the camera instead of losing data
+        let (tx_pipe, mut rx_pipe) = mpsc::channel::<Bytes>(LIVE_STREAM_PIPE_CAPACITY);
+        // taps may fall behind, they skip ahead and resync on the next keyframe
+        let (tx_tap, mut rx_demux) = broadcast::channel::<Bytes>(LIVE_STREAM_TAP_CAPACITY);
+
+        // H.264 elementary stream, regardless of the camera output container
+        let (tx_video, rx_frames) = broadcast::channel::<Bytes>(LIVE_STREAM_TAP_CAPACITY);
 
+        let mjpeg_handle = mjpeg_hub.attach(tx_video.subscribe(), stats.clone());
+        let frames_handle = frame_hub.attach(rx_frames, stats.clone());
+
+        let gop_cache_demux = gop_cache.clone();
+        let stats_tap = stats.clone();
+        let stats_snapshot = stats.clone();
+
+        let stats_reader = stats.clone();
         let reader_handle = tokio::spawn(async move {
-            let mut buffer = [0u8; 8192 * 8];
+            let mut buffer = BytesMut::with_capacity(LIVE_STREAM_READ_BUFFER_SIZE);
             loop {
-                match rpicam_stdout.read(&mut buffer).await {
+                buffer.reserve(LIVE_STREAM_READ_BUFFER_SIZE);
+
+                match rpicam_stdout.read_buf(&mut buffer).await {
                     Ok(0) => break,
                     Ok(n) => {
-                        let data = buffer[..n].to_vec();
-                        if tx.send(data).is_err() {
+                        stats_reader.add_bytes(n);
+
+                        let data = buffer.split().freeze();
+                        let _ = tx_tap.send(data.clone());
+
+                        let data = match tx_pipe.try_send(data) {
+                            Ok(()) => continue,
+                            Err(mpsc::error::TrySendError::Full(data)) => data,
+                            Err(mpsc::error::TrySendError::Closed(_)) => break,
+                        };
+
+                        let waiting_since = Instant::now();
+                        if tx_pipe.send(data).await.is_err() {
                             break;
                         }
+                        stats_reader.add_backpressure(waiting_since.elapsed());
                     }
                     Err(_) => break,
                 }
@@ -317,113 +621,163 @@ fn tapped_io_pipe(
         });
 
         let pipe_handle = tokio::spawn(async move {
-            while let Ok(data) = rx_pipe.recv().await {
+            while let Some(data) = rx_pipe.recv().await {
                 if ffmpeg_stdin.write_all(&data).await.is_err() {
                     break;
                 }
             }
         });
 
-        // let tap_handle = tokio::spawn(async move {
-        //     let mut timer = tokio::time::interval(Duration::from_secs(60)); // TODO
-        //     let mut buffer = Vec::new();
-        //     let buffer_target = (128 * 1024) as usize; // TODO
-
-        //     'outer_loop: loop {
-        //         timer.tick().await;
-
-        //         loop {
-        //             match rx_tap.recv().await {
-        //                 Ok(data) => {
-        //                     buffer.extend_from_slice(&data);
-
-        //                     if buffer.len() >= buffer_target {
-        //                         let _ =
-        //                             events_tx.send(crate::telemetry::events::Event::RawFrameData {
-        //                                 data: buffer.clone(),
-        //                             });
-
-        //                         debug!("Sending {} bytes raw frame data event", buffer.len());
-
-        //                         buffer.clear();
-        //                         break;
-        //                     }
-        //                 }
-        //                 Err(RecvError::Lagged(_)) => {}
-        //                 Err(RecvError::Closed) => break 'outer_loop,
-        //             }
-        //         }
-        //     }
-        // });
+        let events_tx_demux = events_tx.clone();
+        let demux_handle = tokio::spawn(async move {
+            let mut demuxer = MpegTsDemuxer::new();
+            let mut splitter = NalSplitter::new();
+            let mut av_sync = AvSyncTracker::new();
+            let mut last_report = Instant::now();
+            let mut resync = false;
 
-        let tap_handle = tokio::spawn(async move {
-            let mut events_rx = events_rx.resubscribe();
+            loop {
+                let data = match rx_demux.recv().await {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) => {
+                        debug!(
+                            target = "live_stream",
+                            "Pipe demux lagged, {} chunks dropped", n
+                        );
+                        stats_tap.add_dropped(PipeTap::Demux, n);
+
+                        // references are broken, wait for the next keyframe
+                        if let Ok(mut cache) = gop_cache_demux.lock() {
+                            cache.clear();
+                        }
+                        if let Ok(mut ring_buffer) = ring_buffer.lock() {
+                            ring_buffer.discard_partial();
+                        }
+                        demuxer.discard_partial();
+                        splitter = NalSplitter::new();
+                        resync = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let demuxed_video;
+                let video: &[u8] = if timestamped {
+                    let arrival = Instant::now();
+                    let demuxed = demuxer.demux(&data);
+
+                    demuxed
+                        .video_pts
+                        .iter()
+                        .for_each(|pts| av_sync.video_pts(*pts, arrival));
+                    demuxed
+                        .audio_pts
+                        .iter()
+                        .for_each(|pts| av_sync.audio_pts(*pts, arrival));
+
+                    demuxed_video = demuxed.video;
+                    &demuxed_video
+                } else {
+                    &data
+                };
+
+                if video.is_empty() {
+                    continue;
+                }
+
+                let mut units = splitter.push(video);
 
-            'outer_loop: while let Ok(event) = events_rx.recv().await {
-                if let crate::telemetry::events::Event::SnapshotRequest = event {
-                    let mut buffer = Vec::new();
-                    let mut decoder = Decoder::new().expect("Unable to open h264 decoder");
-
-                    debug!("Received snapshot request");
-
-                    let mut rx = rx_tap.resubscribe();
-
-                    loop {
-                        match rx.recv().await {
-                            Ok(data) => {
-                                buffer.extend_from_slice(&data);
-
-                                debug!("Collecting raw frames data");
-
-                                let mut img_data = Vec::new();
-                                let mut w: u32 = 0;
-                                let mut h: u32 = 0;
-
-                                for packet in nal_units(&buffer) {
-                                    if let Ok(Some(frame)) = decoder.decode(packet) {
-                                        img_data =
-                                            vec![
-                                                0;
-                                                frame.dimensions().0 * frame.dimensions().1 * 3
-                                            ];
-                                        w = frame.dimensions().0 as u32;
-                                        h = frame.dimensions().1 as u32;
-                                        frame.write_rgb8(&mut img_data);
-                                        debug!("Parsed a valid frame");
-                                        break;
-                                    }
-                                }
-
-                                if !img_data.is_empty() && w > 0 && h > 0 {
-                                    if let Some(img) = RgbImage::from_raw(w, h, img_data) {
-                                        let _ = events_tx.send(
-                                            crate::telemetry::events::Event::SnapshotData {
-                                                data: img,
-                                            },
-                                        );
-
-                                        debug!("Sending snapshot data");
-
-                                        buffer.clear();
-                                        break;
-                                    }
-                                } else if buffer.len() > (2 * 1024 * 1024) {
-                                    debug!("Buffer exceeded 2MB, quitting...");
-
-                                    buffer.clear();
-                                    break;
-                                }
-                            }
-                            Err(RecvError::Lagged(_)) => {
-                                debug!("Pipe tap is lagging");
-                            }
-                            Err(RecvError::Closed) => break 'outer_loop,
+                if resync {
+                    match units
+                        .iter()
+                        .position(|unit| nal_type(unit) == Some(H264_NAL_SPS))
+                    {
+                        Some(sps) => {
+                            debug!(target = "live_stream", "Pipe demux resynced on keyframe");
+
+                            units.drain(..sps);
+                            resync = false;
                         }
+                        None => continue,
                     }
                 }
+
+                let frames = units.iter().filter(|unit| is_first_slice(unit)).count();
+
+                if let Ok(mut cache) = gop_cache_demux.lock() {
+                    units.iter().for_each(|unit| cache.push(unit));
+                }
+
+                if let Ok(mut ring_buffer) = ring_buffer.lock() {
+                    units.iter().for_each(|unit| ring_buffer.push(unit));
+                }
+
+                if frames > 0 {
+                    stats.add_frames(frames);
+                }
+
+                if !units.is_empty() {
+                    let _ = tx_video.send(Bytes::from(units.concat()));
+                }
+
+                if last_report.elapsed() >= LIVE_STREAM_AV_OFFSET_REPORT_INTERVAL {
+                    last_report = Instant::now();
+
+                    if let Some(offset_ms) = av_sync.offset_ms() {
+                        debug!(target = "live_stream", "A/V offset: {} ms", offset_ms);
+
+                        let _ = events_tx_demux.send(Event::AvOffset { offset_ms });
+                    }
+                }
+            }
+        });
+
+        let tap_handle = tokio::spawn(async move {
+            let mut events_rx = events_rx.resubscribe();
+
+            loop {
+                match events_rx.recv().await {
+                    Ok(Event::SnapshotRequest) => {}
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                }
+
+                debug!(target = "live_stream", "Received snapshot request");
+
+                if let Some(img) = snapshot(&gop_cache, &stats_snapshot, lens.clone()).await {
+                    debug!(target = "live_stream", "Sending snapshot data");
+
+                    let _ = events_tx.send(Event::SnapshotData { data: img });
+                }
             }
         });
 
-        let _ = tokio::join!(reader_handle, pipe_handle, tap_handle);
+        // aborting the pipe, or the pipe running dry, takes every tap down with it
+        let _tasks = PipeTasks(vec![
+            reader_handle.abort_handle(),
+            pipe_handle.abort_handle(),
+            demux_handle.abort_handle(),
+            frames_handle.abort_handle(),
+            mjpeg_handle.abort_handle(),
+            tap_handle.abort_handle(),
+        ]);
+
+        let _ = tokio::join!(
+            reader_handle,
+            pipe_handle,
+            demux_handle,
+            frames_handle,
+            mjpeg_handle
+        );
     })
 }
+
+/// Aborts the pipe tasks when dropped, the snapshot tap would otherwise outlive the pipe
+struct PipeTasks(Vec<AbortHandle>);
+
+impl Drop for PipeTasks {
+    fn drop(&mut self) {
+        self.0.iter().for_each(|task| task.abort());
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::Instant;
+
+use crate::live_stream::mpegts::{MPEGTS_PTS_CLOCK, MPEGTS_PTS_WRAP};
+
+/// Smoothing factor for the drift moving average
+const AV_SYNC_DRIFT_ALPHA: f64 = 0.05;
+
+/// Tracks a single stream clock against the local monotonic clock
+#[derive(Debug)]
+struct StreamClock {
+    first_arrival: Instant,
+    last_pts: u64,
+    elapsed_ticks: u64,
+    drift_ms: Option<f64>,
+}
+
+impl StreamClock {
+    fn new(pts: u64, arrival: Instant) -> Self {
+        Self {
+            first_arrival: arrival,
+            last_pts: pts,
+            elapsed_ticks: 0,
+            drift_ms: None,
+        }
+    }
+
+    fn update(&mut self, pts: u64, arrival: Instant) {
+        // 33 bit PTS wraps roughly every 26.5 hours
+        let delta = (pts + MPEGTS_PTS_WRAP - self.last_pts) % MPEGTS_PTS_WRAP;
+
+        // ignore out of order timestamps (B-frames, retransmits)
+        if delta > MPEGTS_PTS_WRAP / 2 {
+            return;
+        }
+
+        self.last_pts = pts;
+        self.elapsed_ticks += delta;
+
+        let pts_elapsed_ms = self.elapsed_ticks as f64 * 1000.0 / MPEGTS_PTS_CLOCK as f64;
+        let wall_elapsed_ms = arrival.duration_since(self.first_arrival).as_secs_f64() * 1000.0;
+        let drift_ms = pts_elapsed_ms - wall_elapsed_ms;
+
+        self.drift_ms = Some(match self.drift_ms {
+            Some(prev) => prev + AV_SYNC_DRIFT_ALPHA * (drift_ms - prev),
+            None => drift_ms,
+        });
+    }
+}
+
+/// Estimates audio/video offset from the timestamps carried by the muxed camera stream.
+///
+/// Each stream clock is compared against the arrival time of its packets.
+/// The difference between how far the video and audio clocks drifted from
+/// real time is the A/V offset a player would experience.
+#[derive(Debug, Default)]
+pub struct AvSyncTracker {
+    video: Option<StreamClock>,
+    audio: Option<StreamClock>,
+}
+
+impl AvSyncTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn video_pts(&mut self, pts: u64, arrival: Instant) {
+        match self.video.as_mut() {
+            Some(clock) => clock.update(pts, arrival),
+            None => self.video = Some(StreamClock::new(pts, arrival)),
+        }
+    }
+
+    pub fn audio_pts(&mut self, pts: u64, arrival: Instant) {
+        match self.audio.as_mut() {
+            Some(clock) => clock.update(pts, arrival),
+            None => self.audio = Some(StreamClock::new(pts, arrival)),
+        }
+    }
+
+    /// Video ahead of audio in milliseconds (negative when audio leads)
+    pub fn offset_ms(&self) -> Option<i64> {
+        let video = self.video.as_ref()?.drift_ms?;
+        let audio = self.audio.as_ref()?.drift_ms?;
+
+        Some((video - audio).round() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::live_stream::mpegts::MpegTsDemuxer;
+    use std::time::Duration;
+
+    /// 20 frames at 30 fps with an audio stream, video timestamps wrapping after the 10th frame
+    const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/av.ts");
+
+    /// Arrival of a timestamp at its pace from `first`, plus `late`
+    fn arrival(start: Instant, first: u64, pts: u64, late: Duration) -> Instant {
+        let ticks = (pts + MPEGTS_PTS_WRAP - first) % MPEGTS_PTS_WRAP;
+
+        start + Duration::from_micros(ticks * 1_000_000 / MPEGTS_PTS_CLOCK) + late
+    }
+
+    /// Feed the fixture timestamps, the audio ones arriving `audio_late` behind from the second
+    fn offset(audio_late: Duration) -> Option<i64> {
+        let demuxed = MpegTsDemuxer::new().demux(FIXTURE);
+        let start = Instant::now();
+        let mut tracker = AvSyncTracker::new();
+
+        let first_video = demuxed.video_pts[0];
+        for pts in demuxed.video_pts.iter() {
+            tracker.video_pts(*pts, arrival(start, first_video, *pts, Duration::ZERO));
+        }
+
+        let first_audio = demuxed.audio_pts[0];
+        for (i, pts) in demuxed.audio_pts.iter().enumerate() {
+            let late = if i == 0 { Duration::ZERO } else { audio_late };
+
+            tracker.audio_pts(*pts, arrival(start, first_audio, *pts, late));
+        }
+
+        tracker.offset_ms()
+    }
+
+    #[test]
+    fn in_sync_across_the_wrap() {
+        assert_eq!(offset(Duration::ZERO), Some(0));
+    }
+
+    #[test]
+    fn late_audio_puts_video_ahead() {
+        assert_eq!(offset(Duration::from_millis(50)), Some(50));
+    }
+
+    #[test]
+    fn needs_both_streams() {
+        let mut tracker = AvSyncTracker::new();
+        let start = Instant::now();
+
+        tracker.video_pts(0, start);
+        tracker.video_pts(3_000, start + Duration::from_millis(33));
+
+        assert_eq!(tracker.offset_ms(), None);
+    }
+
+    #[test]
+    fn out_of_order_timestamps_are_ignored() {
+        let start = Instant::now();
+        let mut clock = StreamClock::new(MPEGTS_PTS_WRAP - 3_000, start);
+
+        clock.update(3_000, start + Duration::from_micros(66_667));
+        clock.update(0, start + Duration::from_micros(66_667));
+
+        assert_eq!(clock.last_pts, 3_000);
+        assert_eq!(clock.elapsed_ticks, 6_000);
+        assert_eq!(clock.drift_ms.map(|drift| drift.round()), Some(0.0));
+    }
+}
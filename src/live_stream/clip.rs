@@ -0,0 +1,209 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::ffmpeg::FFMPEG_BIN;
+use crate::live_stream::ring_buffer::GopRingBuffer;
+
+pub const CLIP_DEFAULT_POST_DURATION: Duration = Duration::from_secs(10);
+pub const CLIP_MAX_POST_DURATION: Duration = Duration::from_secs(300);
+pub const CLIP_DEFAULT_DIR_NAME: &str = "clips";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    #[default]
+    Mp4,
+    Ts,
+}
+
+impl ClipFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "ts",
+        }
+    }
+
+    fn muxer(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Ts => "mpegts",
+        }
+    }
+}
+
+impl fmt::Display for ClipFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// Exported clip on disk
+#[derive(Clone, Debug, Serialize)]
+pub struct ClipInfo {
+    pub filename: String,
+    pub format: ClipFormat,
+    pub filesize: u64,
+}
+
+/// Releases the ring buffer pin even if the export is abandoned midway
+struct ClipPin {
+    ring_buffer: Arc<Mutex<GopRingBuffer>>,
+    pin: Option<u64>,
+}
+
+impl ClipPin {
+    fn take(mut self) -> Option<Vec<u8>> {
+        let pin = self.pin.take()?;
+        self.ring_buffer.lock().ok()?.release(pin)
+    }
+}
+
+impl Drop for ClipPin {
+    fn drop(&mut self) {
+        if let Some(pin) = self.pin.take() {
+            if let Ok(mut ring_buffer) = self.ring_buffer.lock() {
+                ring_buffer.release(pin);
+            }
+        }
+    }
+}
+
+/// Turns the pre-event ring buffer into standalone video files
+#[derive(Clone, Debug)]
+pub struct ClipExporter {
+    ring_buffer: Arc<Mutex<GopRingBuffer>>,
+    dir: PathBuf,
+    /// Raw H.264 carries no timestamps, muxing needs the camera frame rate
+    fps: Option<u32>,
+}
+
+impl ClipExporter {
+    pub fn new(ring_buffer: Arc<Mutex<GopRingBuffer>>, dir: PathBuf, fps: Option<u32>) -> Self {
+        Self {
+            ring_buffer,
+            dir,
+            fps,
+        }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    /// Export up to `pre` of buffered video plus the following `post`, whole buffer when `pre` is `None`
+    pub async fn export(
+        &self,
+        pre: Option<Duration>,
+        post: Duration,
+        format: ClipFormat,
+    ) -> Result<ClipInfo> {
+        let pin = {
+            let mut ring_buffer = self
+                .ring_buffer
+                .lock()
+                .map_err(|_| anyhow!("Ring buffer lock poisoned"))?;
+            let pre = pre.unwrap_or(ring_buffer.duration());
+
+            ClipPin {
+                pin: Some(ring_buffer.pin(pre)),
+                ring_buffer: self.ring_buffer.clone(),
+            }
+        };
+
+        tokio::time::sleep(post.min(CLIP_MAX_POST_DURATION)).await;
+
+        let data = pin
+            .take()
+            .ok_or_else(|| anyhow!("No buffered video to export"))?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let filename = format!("clip-{}.{}", timestamp, format.extension());
+        let path = self.dir.join(&filename);
+        let tmp = path.with_extension("tmp");
+
+        self.mux(&data, &tmp, format).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+
+        let filesize = tokio::fs::metadata(&path).await?.len();
+
+        debug!(
+            target = "live_stream::clip",
+            "Exported {} ({} bytes of video)",
+            filename,
+            data.len()
+        );
+
+        Ok(ClipInfo {
+            filename,
+            format,
+            filesize,
+        })
+    }
+
+    async fn mux(&self, data: &[u8], path: &PathBuf, format: ClipFormat) -> Result<()> {
+        let mut args = vec![
+            "-hide_banner".to_string(),
+            "-loglevel".to_string(),
+            "error".to_string(),
+            "-y".to_string(),
+            "-f".to_string(),
+            "h264".to_string(),
+        ];
+
+        if let Some(fps) = self.fps {
+            args.push("-framerate".to_string());
+            args.push(fps.to_string());
+        }
+
+        args.extend(["-i", "pipe:", "-c", "copy"].map(str::to_string));
+
+        if format == ClipFormat::Mp4 {
+            args.push("-movflags".to_string());
+            args.push("+faststart".to_string());
+        }
+
+        args.extend(["-f".to_string(), format.muxer().to_string()]);
+        args.push(path.to_string_lossy().to_string());
+
+        let mut child = Command::new(FFMPEG_BIN)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn child process {}: {}", FFMPEG_BIN, e))?;
+
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to open child process input for `{}`", FFMPEG_BIN))?;
+        stdin.write_all(data).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            let _ = tokio::fs::remove_file(path).await;
+
+            return Err(anyhow!(
+                "{} failed to mux clip: {}",
+                FFMPEG_BIN,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use image::imageops::{resize, FilterType};
+use image::{GrayImage, RgbImage};
+use openh264::decoder::{DecodedYUV, Decoder};
+use openh264::formats::YUVSource;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+use crate::lens::LensCorrection;
+use crate::live_stream::h264::{
+    is_first_slice, nal_type, NalSplitter, H264_NAL_IDR, H264_NAL_SLICE, H264_NAL_SPS,
+};
+use crate::live_stream::stats::{PipeStats, PipeTap};
+
+pub const FRAME_HUB_DEFAULT_FPS: f32 = 2.0;
+/// Plenty for motion and the detection models, decoding full frames for them is wasted CPU
+pub const FRAME_HUB_DEFAULT_MAX_WIDTH: u32 = 320;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameFormat {
+    /// Packed 8 bit RGB
+    Rgb,
+    /// 8 bit luma plane only, cheapest to produce
+    Luma,
+}
+
+/// How much of the stream gets decoded
+///
+/// P-frames reference the picture before them, so only keyframe mode saves decoding work.
+/// Sampling every Nth P-frame still decodes the ones in between, it saves the conversions
+/// and keeps frames evenly spread over each GOP. Pair keyframe mode with the camera `intra`
+/// period to pick the keyframe rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameDecodeMode {
+    /// Every picture
+    #[default]
+    All,
+    /// IDR pictures only, P-frames are never decoded
+    Keyframes,
+    /// IDR pictures plus every Nth P-frame after them
+    Every(u32),
+}
+
+impl fmt::Display for FrameDecodeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => f.write_str("all"),
+            Self::Keyframes => f.write_str("keyframes"),
+            Self::Every(n) => write!(f, "every_{}", n),
+        }
+    }
+}
+
+/// Picks the units fed to the decoder and the pictures handed out, per decode mode
+#[derive(Debug, Default)]
+pub struct FrameSampler {
+    mode: FrameDecodeMode,
+    /// P-frames since the last IDR
+    since_keyframe: u64,
+}
+
+impl FrameSampler {
+    pub fn new(mode: FrameDecodeMode) -> Self {
+        Self {
+            mode,
+            since_keyframe: 0,
+        }
+    }
+
+    /// Should this unit reach the decoder?
+    pub fn decode(&mut self, unit: &[u8]) -> bool {
+        match nal_type(unit) {
+            Some(H264_NAL_IDR) => {
+                if is_first_slice(unit) {
+                    self.since_keyframe = 0;
+                }
+                true
+            }
+            Some(H264_NAL_SLICE) => {
+                if is_first_slice(unit) {
+                    self.since_keyframe += 1;
+                }
+                self.mode != FrameDecodeMode::Keyframes
+            }
+            // parameter sets and friends
+            _ => true,
+        }
+    }
+
+    /// Should the picture just decoded be handed out?
+    pub fn publish(&self) -> bool {
+        match self.mode {
+            FrameDecodeMode::All | FrameDecodeMode::Keyframes => true,
+            FrameDecodeMode::Every(n) => self.since_keyframe.is_multiple_of(n.max(1) as u64),
+        }
+    }
+}
+
+/// Decoded video frame shared between analytics consumers
+#[derive(Debug)]
+pub struct Frame {
+    pub sequence: u64,
+    pub decoded_at: Instant,
+    pub width: u32,
+    pub height: u32,
+    pub format: FrameFormat,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn to_rgb_image(&self) -> Option<RgbImage> {
+        if self.format != FrameFormat::Rgb {
+            return None;
+        }
+
+        RgbImage::from_raw(self.width, self.height, self.data.clone())
+    }
+
+    pub fn to_gray_image(&self) -> Option<GrayImage> {
+        if self.format != FrameFormat::Luma {
+            return None;
+        }
+
+        GrayImage::from_raw(self.width, self.height, self.data.clone())
+    }
+}
+
+pub type FrameReceiver = watch::Receiver<Option<Arc<Frame>>>;
+
+#[derive(Clone, Debug)]
+pub struct FrameHubConfig {
+    /// Maximum frames per second handed out to subscribers
+    pub fps: f32,
+    /// Frames are scaled down to fit within these dimensions, aspect ratio preserved
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub decode_mode: FrameDecodeMode,
+    /// Fisheye dewarping applied after scaling
+    pub lens: Option<LensCorrection>,
+}
+
+impl Default for FrameHubConfig {
+    fn default() -> Self {
+        Self {
+            fps: FRAME_HUB_DEFAULT_FPS,
+            max_width: Some(FRAME_HUB_DEFAULT_MAX_WIDTH),
+            max_height: None,
+            decode_mode: FrameDecodeMode::default(),
+            lens: None,
+        }
+    }
+}
+
+impl FrameHubConfig {
+    pub fn new(fps: f32, max_width: Option<u32>, max_height: Option<u32>) -> Self {
+        Self {
+            fps,
+            max_width,
+            max_height,
+            decode_mode: FrameDecodeMode::default(),
+            lens: None,
+        }
+    }
+
+    pub fn with_decode_mode(mut self, decode_mode: FrameDecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+
+        self
+    }
+
+    pub fn with_lens(mut self, lens: Option<LensCorrection>) -> Self {
+        self.lens = lens;
+
+        self
+    }
+
+    fn interval(&self) -> Duration {
+        if self.fps > 0.0 {
+            Duration::from_secs_f32(1.0 / self.fps)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn target_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale_w = self.max_width.map(|w| w as f32 / width as f32);
+        let scale_h = self.max_height.map(|h| h as f32 / height as f32);
+
+        let scale = match (scale_w, scale_h) {
+            (Some(w), Some(h)) => w.min(h),
+            (Some(s), None) | (None, Some(s)) => s,
+            (None, None) => 1.0,
+        }
+        .min(1.0);
+
+        (
+            ((width as f32 * scale).round() as u32).max(1),
+            ((height as f32 * scale).round() as u32).max(1),
+        )
+    }
+}
+
+/// Single H.264 decode shared by any number of frame subscribers.
+///
+/// Decoding only happens while someone is subscribed.
+#[derive(Clone, Debug)]
+pub struct FrameHub {
+    config: FrameHubConfig,
+    rgb: Arc<watch::Sender<Option<Arc<Frame>>>>,
+    luma: Arc<watch::Sender<Option<Arc<Frame>>>>,
+}
+
+impl Default for FrameHub {
+    fn default() -> Self {
+        Self::new(FrameHubConfig::default())
+    }
+}
+
+impl FrameHub {
+    pub fn new(config: FrameHubConfig) -> Self {
+        Self {
+            config,
+            rgb: Arc::new(watch::Sender::new(None)),
+            luma: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    pub fn config(&self) -> &FrameHubConfig {
+        &self.config
+    }
+
+    /// Subscribe to the latest decoded frame in the given format
+    pub fn subscribe(&self, format: FrameFormat) -> FrameReceiver {
+        match format {
+            FrameFormat::Rgb => self.rgb.subscribe(),
+            FrameFormat::Luma => self.luma.subscribe(),
+        }
+    }
+
+    /// Is anyone interested in frames?
+    pub fn is_active(&self) -> bool {
+        self.rgb.receiver_count() > 0 || self.luma.receiver_count() > 0
+    }
+
+    /// Decode H.264 elementary stream fed through the given receiver until it closes
+    pub fn attach(
+        &self,
+        mut rx: broadcast::Receiver<Bytes>,
+        stats: Arc<PipeStats>,
+    ) -> JoinHandle<()> {
+        let hub = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let interval = hub.config.interval();
+            let mut splitter = NalSplitter::new();
+            let mut sampler = FrameSampler::new(hub.config.decode_mode);
+            let mut decoder: Option<Decoder> = None;
+            let mut last_publish: Option<Instant> = None;
+            let mut sequence = 0u64;
+
+            loop {
+                let data = match rx.blocking_recv() {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) => {
+                        // lost data means broken references, start over from the next keyframe
+                        debug!(target = "frame_hub", "Lagging behind by {} chunks", n);
+                        stats.add_dropped(PipeTap::Frames, n);
+                        decoder = None;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let units = splitter.push(&data);
+
+                if !hub.is_active() {
+                    decoder = None;
+                    continue;
+                }
+
+                for unit in units {
+                    if decoder.is_none() {
+                        // (re)join the stream on a sequence header
+                        if nal_type(&unit) != Some(H264_NAL_SPS) {
+                            continue;
+                        }
+
+                        match Decoder::new() {
+                            Ok(d) => decoder = Some(d),
+                            Err(e) => {
+                                error!(target = "frame_hub", "Unable to open h264 decoder: {}", e);
+                                return;
+                            }
+                        }
+                    }
+
+                    let Some(decoder) = decoder.as_mut() else {
+                        continue;
+                    };
+
+                    if !sampler.decode(&unit) {
+                        if is_first_slice(&unit) {
+                            stats.add_skipped_picture();
+                        }
+                        continue;
+                    }
+
+                    let decode_start = Instant::now();
+                    let decoded = decoder.decode(&unit);
+                    let decode_time = decode_start.elapsed();
+
+                    let yuv = match decoded {
+                        Ok(Some(yuv)) => yuv,
+                        Ok(None) => {
+                            stats.add_decode(decode_time, false);
+                            continue;
+                        }
+                        Err(e) => {
+                            stats.add_decode(decode_time, false);
+                            debug!(target = "frame_hub", "Decode error: {}", e);
+                            continue;
+                        }
+                    };
+                    stats.add_decode(decode_time, true);
+
+                    if !sampler.publish() || last_publish.is_some_and(|t| t.elapsed() < interval) {
+                        continue;
+                    }
+
+                    last_publish = Some(Instant::now());
+                    sequence += 1;
+
+                    hub.publish(&yuv, sequence);
+                    stats.add_conversion(last_publish.map(|t| t.elapsed()).unwrap_or_default());
+                }
+            }
+
+            info!(
+                target = "frame_hub",
+                "Video tap closed, frame decoding stopped"
+            );
+        })
+    }
+
+    fn publish(&self, yuv: &DecodedYUV<'_>, sequence: u64) {
+        let (width, height) = yuv.dimensions();
+        let (target_width, target_height) =
+            self.config.target_dimensions(width as u32, height as u32);
+
+        if self.luma.receiver_count() > 0 {
+            if let Some(frame) = luma_frame(yuv, sequence, target_width, target_height) {
+                let frame = self.dewarp(frame);
+                self.luma.send_replace(Some(Arc::new(frame)));
+            }
+        }
+
+        if self.rgb.receiver_count() > 0 {
+            if let Some(frame) = rgb_frame(yuv, sequence, target_width, target_height) {
+                let frame = self.dewarp(frame);
+                self.rgb.send_replace(Some(Arc::new(frame)));
+            }
+        }
+    }
+
+    fn dewarp(&self, frame: Frame) -> Frame {
+        match self.config.lens.as_ref() {
+            Some(lens) => lens.apply_frame(frame),
+            None => frame,
+        }
+    }
+}
+
+/// Luma plane of a decoded picture, scaled to the given size
+pub fn luma_frame(
+    yuv: &DecodedYUV<'_>,
+    sequence: u64,
+    target_width: u32,
+    target_height: u32,
+) -> Option<Frame> {
+    let (width, height) = yuv.dimensions();
+    let (stride, _, _) = yuv.strides();
+
+    let mut data = Vec::with_capacity(width * height);
+    yuv.y()
+        .chunks(stride)
+        .take(height)
+        .for_each(|row| data.extend_from_slice(&row[..width]));
+
+    let mut image = GrayImage::from_raw(width as u32, height as u32, data)?;
+    if (target_width, target_height) != image.dimensions() {
+        image = resize(&image, target_width, target_height, FilterType::Triangle);
+    }
+
+    Some(Frame {
+        sequence,
+        decoded_at: Instant::now(),
+        width: image.width(),
+        height: image.height(),
+        format: FrameFormat::Luma,
+        data: image.into_raw(),
+    })
+}
+
+/// Decoded picture as RGB, scaled to the given size
+pub fn rgb_frame(
+    yuv: &DecodedYUV<'_>,
+    sequence: u64,
+    target_width: u32,
+    target_height: u32,
+) -> Option<Frame> {
+    let (width, height) = yuv.dimensions();
+
+    let mut data = vec![0; width * height * 3];
+    yuv.write_rgb8(&mut data);
+
+    let mut image = RgbImage::from_raw(width as u32, height as u32, data)?;
+    if (target_width, target_height) != image.dimensions() {
+        image = resize(&image, target_width, target_height, FilterType::Triangle);
+    }
+
+    Some(Frame {
+        sequence,
+        decoded_at: Instant::now(),
+        width: image.width(),
+        height: image.height(),
+        format: FrameFormat::Rgb,
+        data: image.into_raw(),
+    })
+}
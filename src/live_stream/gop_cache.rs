@@ -0,0 +1,150 @@
+use image::RgbImage;
+use openh264::decoder::Decoder;
+use openh264::formats::YUVSource;
+
+use crate::live_stream::h264::{
+    is_first_slice, nal_type, H264_NAL_IDR, H264_NAL_PPS, H264_NAL_SPS,
+};
+
+pub const GOP_CACHE_DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// Latest parameter sets and the group of pictures since the last IDR frame.
+///
+/// Everything needed to decode the most recent picture without waiting
+/// for the camera to produce the next keyframe.
+#[derive(Debug)]
+pub struct GopCache {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    gop: Vec<Vec<u8>>,
+    gop_bytes: usize,
+    pictures: usize,
+    max_bytes: usize,
+    truncated: bool,
+}
+
+impl Default for GopCache {
+    fn default() -> Self {
+        Self::new(GOP_CACHE_DEFAULT_MAX_BYTES)
+    }
+}
+
+impl GopCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            sps: None,
+            pps: None,
+            gop: Vec::new(),
+            gop_bytes: 0,
+            pictures: 0,
+            max_bytes,
+            truncated: false,
+        }
+    }
+
+    /// Feed a single NAL unit, start code included
+    pub fn push(&mut self, unit: &[u8]) {
+        match nal_type(unit) {
+            Some(H264_NAL_SPS) => {
+                self.sps = Some(unit.to_vec());
+                return;
+            }
+            Some(H264_NAL_PPS) => {
+                self.pps = Some(unit.to_vec());
+                return;
+            }
+            Some(H264_NAL_IDR) if is_first_slice(unit) => {
+                self.gop.clear();
+                self.gop_bytes = 0;
+                self.pictures = 0;
+                self.truncated = false;
+            }
+            _ => {
+                // nothing to decode against until the first keyframe
+                if self.gop.is_empty() {
+                    return;
+                }
+            }
+        }
+
+        if self.truncated || self.gop_bytes + unit.len() > self.max_bytes {
+            // keep the head of the GOP, it still decodes, just to an older picture
+            self.truncated = true;
+            return;
+        }
+
+        if is_first_slice(unit) {
+            self.pictures += 1;
+        }
+
+        self.gop_bytes += unit.len();
+        self.gop.push(unit.to_vec());
+    }
+
+    /// Do we have enough to decode a picture?
+    pub fn is_ready(&self) -> bool {
+        self.sps.is_some() && self.pps.is_some() && self.pictures > 0
+    }
+
+    /// Drop all cached data, e.g. after the stream was interrupted
+    pub fn clear(&mut self) {
+        *self = Self::new(self.max_bytes);
+    }
+
+    /// Self contained Annex B stream ending at the most recent cached picture
+    pub fn stream(&self) -> Option<GopStream> {
+        if !self.is_ready() {
+            return None;
+        }
+
+        let mut units = Vec::with_capacity(self.gop.len() + 2);
+        units.extend(self.sps.clone());
+        units.extend(self.pps.clone());
+        units.extend(self.gop.iter().cloned());
+
+        Some(GopStream {
+            units,
+            pictures: self.pictures,
+        })
+    }
+}
+
+/// Owned copy of the cached GOP, decodable away from the cache lock
+#[derive(Debug)]
+pub struct GopStream {
+    units: Vec<Vec<u8>>,
+    pictures: usize,
+}
+
+impl GopStream {
+    /// Decode the whole GOP and return the last picture.
+    ///
+    /// CPU heavy, meant to be run on a blocking thread.
+    pub fn decode_last(&self) -> Option<RgbImage> {
+        let mut decoder = Decoder::new().ok()?;
+        let mut decoded = 0;
+
+        for unit in &self.units {
+            if let Ok(Some(yuv)) = decoder.decode(unit) {
+                decoded += 1;
+
+                // only the final picture is worth the colour conversion
+                if decoded == self.pictures {
+                    let (width, height) = yuv.dimensions();
+                    let mut data = vec![0; width * height * 3];
+                    yuv.write_rgb8(&mut data);
+
+                    return RgbImage::from_raw(width as u32, height as u32, data);
+                }
+            }
+        }
+
+        let remaining = decoder.flush_remaining().ok()?;
+        let yuv = remaining.last()?;
+        let (width, height) = yuv.dimensions();
+        let mut data = vec![0; width * height * 3];
+        yuv.write_rgb8(&mut data);
+
+        RgbImage::from_raw(width as u32, height as u32, data)
+    }
+}
@@ -0,0 +1,94 @@
+pub const H264_NAL_SLICE: u8 = 1;
+pub const H264_NAL_IDR: u8 = 5;
+pub const H264_NAL_SEI: u8 = 6;
+pub const H264_NAL_SPS: u8 = 7;
+pub const H264_NAL_PPS: u8 = 8;
+pub const H264_NAL_AUD: u8 = 9;
+
+/// Splits an Annex B byte stream into NAL units across arbitrary chunk boundaries.
+///
+/// Emitted units keep their start code prefix so they can be fed
+/// straight into a decoder or concatenated back into a valid stream.
+#[derive(Debug, Default)]
+pub struct NalSplitter {
+    buffer: Vec<u8>,
+    scan_from: usize,
+    unit_start: Option<usize>,
+}
+
+impl NalSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed stream data, returns all NAL units completed by it
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut units = Vec::new();
+        let mut i = self.scan_from;
+
+        while i + 3 <= self.buffer.len() {
+            if self.buffer[i] == 0 && self.buffer[i + 1] == 0 && self.buffer[i + 2] == 1 {
+                let start_code = if i > 0 && self.buffer[i - 1] == 0 {
+                    i - 1
+                } else {
+                    i
+                };
+
+                if let Some(start) = self.unit_start {
+                    if start_code > start {
+                        units.push(self.buffer[start..start_code].to_vec());
+                    }
+                }
+
+                self.unit_start = Some(start_code);
+                i += 3;
+            } else {
+                i += 1;
+            }
+        }
+
+        match self.unit_start {
+            Some(start) => {
+                self.buffer.drain(..start);
+                self.scan_from = i - start;
+                self.unit_start = Some(0);
+            }
+            None => {
+                // no start code yet, keep just enough to catch one split across chunks
+                let keep = self.buffer.len().min(3);
+                self.buffer.drain(..self.buffer.len() - keep);
+                self.scan_from = 0;
+            }
+        }
+
+        units
+    }
+}
+
+/// Payload of a NAL unit, start code stripped
+pub fn nal_payload(unit: &[u8]) -> &[u8] {
+    if unit.starts_with(&[0, 0, 0, 1]) {
+        &unit[4..]
+    } else if unit.starts_with(&[0, 0, 1]) {
+        &unit[3..]
+    } else {
+        unit
+    }
+}
+
+/// NAL unit type, start code aware
+pub fn nal_type(unit: &[u8]) -> Option<u8> {
+    nal_payload(unit).first().map(|header| header & 0x1f)
+}
+
+/// Is this the first slice of a picture? (`first_mb_in_slice == 0`)
+pub fn is_first_slice(unit: &[u8]) -> bool {
+    let payload = nal_payload(unit);
+
+    matches!(
+        payload.first().map(|header| header & 0x1f),
+        Some(H264_NAL_SLICE | H264_NAL_IDR)
+    ) && payload.get(1).is_some_and(|b| b & 0x80 != 0)
+}
@@ -0,0 +1,344 @@
+use tracing::debug;
+
+pub const MPEGTS_PACKET_SIZE: usize = 188;
+pub const MPEGTS_SYNC_BYTE: u8 = 0x47;
+pub const MPEGTS_PTS_CLOCK: u64 = 90_000;
+pub const MPEGTS_PTS_WRAP: u64 = 1 << 33;
+
+const MPEGTS_PID_PAT: u16 = 0x0000;
+
+const MPEGTS_STREAM_TYPE_H264: u8 = 0x1b;
+const MPEGTS_STREAM_TYPES_AUDIO: [u8; 5] = [0x03, 0x04, 0x0f, 0x11, 0x81];
+
+/// Output of a single demux pass
+#[derive(Debug, Default)]
+pub struct MpegTsDemuxed {
+    /// H.264 Annex B elementary stream bytes
+    pub video: Vec<u8>,
+    /// Presentation timestamps (90 kHz) of video PES packets started in this pass
+    pub video_pts: Vec<u64>,
+    /// Presentation timestamps (90 kHz) of audio PES packets started in this pass
+    pub audio_pts: Vec<u64>,
+}
+
+/// Minimal MPEG-TS demuxer, just enough to pull the H.264 elementary stream
+/// and the audio/video presentation timestamps out of the `rpicam-vid` libav output
+#[derive(Debug, Default)]
+pub struct MpegTsDemuxer {
+    remainder: Vec<u8>,
+    pmt_pid: Option<u16>,
+    video_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    video_pes_header: Vec<u8>,
+    /// Packet boundaries known, otherwise a sync byte needs the next packet to confirm it
+    synced: bool,
+}
+
+impl MpegTsDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed arbitrary sized chunk of transport stream data
+    pub fn demux(&mut self, data: &[u8]) -> MpegTsDemuxed {
+        let mut output = MpegTsDemuxed::default();
+
+        let mut buffer = std::mem::take(&mut self.remainder);
+        buffer.extend_from_slice(data);
+
+        let mut offset = 0;
+        while offset + MPEGTS_PACKET_SIZE <= buffer.len() {
+            if !self.synced {
+                // payload is full of 0x47 bytes, only two sync bytes a packet apart are a start
+                let next = offset + MPEGTS_PACKET_SIZE;
+                if next >= buffer.len() {
+                    break;
+                }
+
+                if buffer[offset] != MPEGTS_SYNC_BYTE || buffer[next] != MPEGTS_SYNC_BYTE {
+                    offset += 1;
+                    continue;
+                }
+
+                self.synced = true;
+            } else if buffer[offset] != MPEGTS_SYNC_BYTE {
+                debug!(target = "mpegts", "Lost sync");
+                self.synced = false;
+                continue;
+            }
+
+            self.demux_packet(&buffer[offset..offset + MPEGTS_PACKET_SIZE], &mut output);
+            offset += MPEGTS_PACKET_SIZE;
+        }
+
+        self.remainder = buffer.split_off(offset);
+
+        output
+    }
+
+    /// Forget partial data after a gap in the input, the stream layout is kept
+    pub fn discard_partial(&mut self) {
+        self.remainder.clear();
+        self.video_pes_header.clear();
+        self.synced = false;
+    }
+
+    fn demux_packet(&mut self, packet: &[u8], output: &mut MpegTsDemuxed) {
+        // transport error indicator
+        if packet[1] & 0x80 != 0 {
+            return;
+        }
+
+        let payload_start = packet[1] & 0x40 != 0;
+        let pid = ((packet[1] as u16 & 0x1f) << 8) | packet[2] as u16;
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+
+        let mut payload_offset = 4;
+        if adaptation_field_control & 0x02 != 0 {
+            payload_offset += 1 + packet[4] as usize;
+        }
+
+        if adaptation_field_control & 0x01 == 0 || payload_offset >= MPEGTS_PACKET_SIZE {
+            return;
+        }
+
+        let payload = &packet[payload_offset..];
+
+        if pid == MPEGTS_PID_PAT {
+            if payload_start {
+                self.parse_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if payload_start {
+                self.parse_pmt(payload);
+            }
+        } else if Some(pid) == self.video_pid {
+            if payload_start {
+                self.video_pes_header.clear();
+            }
+
+            if !self.video_pes_header.is_empty() || payload_start {
+                // PES header may in theory straddle packets, collect until we can parse it
+                self.video_pes_header.extend_from_slice(payload);
+
+                if let Some((header_len, pts)) = parse_pes_header(&self.video_pes_header) {
+                    if let Some(pts) = pts {
+                        output.video_pts.push(pts);
+                    }
+
+                    output
+                        .video
+                        .extend_from_slice(&self.video_pes_header[header_len..]);
+                    self.video_pes_header.clear();
+                } else if self.video_pes_header.len() > 2 * MPEGTS_PACKET_SIZE {
+                    debug!(target = "mpegts", "Dropping malformed PES header");
+                    self.video_pes_header.clear();
+                }
+            } else {
+                output.video.extend_from_slice(payload);
+            }
+        } else if Some(pid) == self.audio_pid && payload_start {
+            if let Some((_, Some(pts))) = parse_pes_header(payload) {
+                output.audio_pts.push(pts);
+            }
+        }
+    }
+
+    fn parse_pat(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else {
+            return;
+        };
+
+        // program loop starts after transport_stream_id, version, section numbers
+        for program in section.get(5..).unwrap_or_default().chunks_exact(4) {
+            let program_number = u16::from_be_bytes([program[0], program[1]]);
+            if program_number != 0 {
+                let pmt_pid = ((program[2] as u16 & 0x1f) << 8) | program[3] as u16;
+
+                if self.pmt_pid != Some(pmt_pid) {
+                    debug!(target = "mpegts", "Found PMT on PID {:#06x}", pmt_pid);
+                    self.pmt_pid = Some(pmt_pid);
+                }
+
+                break;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else {
+            return;
+        };
+
+        if section.len() < 9 {
+            return;
+        }
+
+        let program_info_length = ((section[7] as usize & 0x0f) << 8) | section[8] as usize;
+        let mut offset = 9 + program_info_length;
+
+        while offset + 5 <= section.len() {
+            let stream_type = section[offset];
+            let pid = ((section[offset + 1] as u16 & 0x1f) << 8) | section[offset + 2] as u16;
+            let es_info_length =
+                ((section[offset + 3] as usize & 0x0f) << 8) | section[offset + 4] as usize;
+
+            if stream_type == MPEGTS_STREAM_TYPE_H264 && self.video_pid.is_none() {
+                debug!(target = "mpegts", "Found H.264 stream on PID {:#06x}", pid);
+                self.video_pid = Some(pid);
+            } else if MPEGTS_STREAM_TYPES_AUDIO.contains(&stream_type) && self.audio_pid.is_none() {
+                debug!(target = "mpegts", "Found audio stream on PID {:#06x}", pid);
+                self.audio_pid = Some(pid);
+            }
+
+            offset += 5 + es_info_length;
+        }
+    }
+}
+
+/// Strip pointer field and return the PSI section body between the length field and CRC
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let table = payload.get(1 + pointer..)?;
+
+    if table.len() < 3 {
+        return None;
+    }
+
+    let section_length = ((table[1] as usize & 0x0f) << 8) | table[2] as usize;
+
+    // exclude trailing CRC32
+    table.get(3..(3 + section_length).checked_sub(4)?)
+}
+
+/// Parse PES header, returning header length and PTS, if any
+fn parse_pes_header(data: &[u8]) -> Option<(usize, Option<u64>)> {
+    if data.len() < 9 || data[0..3] != [0x00, 0x00, 0x01] {
+        return None;
+    }
+
+    let header_len = 9 + data[8] as usize;
+    if data.len() < header_len {
+        return None;
+    }
+
+    let pts = if data[7] & 0x80 != 0 && header_len >= 14 {
+        let p = &data[9..14];
+        Some(
+            ((p[0] as u64 >> 1) & 0x07) << 30
+                | (p[1] as u64) << 22
+                | (p[2] as u64 >> 1) << 15
+                | (p[3] as u64) << 7
+                | (p[4] as u64 >> 1),
+        )
+    } else {
+        None
+    };
+
+    Some((header_len, pts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First 20 frames of `motion.h264` at 30 fps with an audio stream 20 ms ahead, one PES per
+    /// frame, video timestamps wrapping after the 10th frame
+    const FIXTURE: &[u8] = include_bytes!("../../tests/fixtures/av.ts");
+    const FIXTURE_H264: &[u8] = include_bytes!("../../tests/fixtures/motion.h264");
+    const FIXTURE_VIDEO_START: u64 = MPEGTS_PTS_WRAP - 10 * 3_000;
+
+    fn demux_chunks(demuxer: &mut MpegTsDemuxer, data: &[u8], size: usize) -> MpegTsDemuxed {
+        let mut output = MpegTsDemuxed::default();
+
+        for chunk in data.chunks(size) {
+            let demuxed = demuxer.demux(chunk);
+
+            output.video.extend(demuxed.video);
+            output.video_pts.extend(demuxed.video_pts);
+            output.audio_pts.extend(demuxed.audio_pts);
+        }
+
+        output
+    }
+
+    #[test]
+    fn extracts_video_and_timestamps() {
+        let output = MpegTsDemuxer::new().demux(FIXTURE);
+
+        assert_eq!(output.video, FIXTURE_H264[..output.video.len()]);
+        assert_eq!(
+            output.video_pts,
+            (0..20)
+                .map(|i| (FIXTURE_VIDEO_START + i * 3_000) % MPEGTS_PTS_WRAP)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(output.audio_pts.len(), 31);
+        assert_eq!(output.audio_pts[0], FIXTURE_VIDEO_START + 1_800);
+        assert_eq!(
+            output.audio_pts[30],
+            (FIXTURE_VIDEO_START + 1_800 + 30 * 1_920) % MPEGTS_PTS_WRAP
+        );
+    }
+
+    #[test]
+    fn chunking_does_not_matter() {
+        let whole = MpegTsDemuxer::new().demux(FIXTURE);
+
+        for size in [1, 100, 187, 189, 1_000] {
+            let chunked = demux_chunks(&mut MpegTsDemuxer::new(), FIXTURE, size);
+
+            assert_eq!(chunked.video, whole.video, "{} byte chunks", size);
+            assert_eq!(chunked.video_pts, whole.video_pts, "{} byte chunks", size);
+            assert_eq!(chunked.audio_pts, whole.audio_pts, "{} byte chunks", size);
+        }
+    }
+
+    #[test]
+    fn lone_sync_bytes_are_not_packets() {
+        let mut data = vec![MPEGTS_SYNC_BYTE; 50];
+        data.extend_from_slice(FIXTURE);
+
+        let output = MpegTsDemuxer::new().demux(&data);
+        let expected = MpegTsDemuxer::new().demux(FIXTURE);
+
+        assert_eq!(output.video, expected.video);
+        assert_eq!(output.video_pts, expected.video_pts);
+        assert_eq!(output.audio_pts, expected.audio_pts);
+    }
+
+    #[test]
+    fn discard_partial_resyncs_after_a_gap() {
+        let gap_end = 20 * MPEGTS_PACKET_SIZE + 77;
+
+        let mut demuxer = MpegTsDemuxer::new();
+        demuxer.demux(&FIXTURE[..1_000]);
+        demuxer.discard_partial();
+        let output = demuxer.demux(&FIXTURE[gap_end..]);
+
+        // same stream layout, picked up from the next packet boundary
+        let mut expected_demuxer = MpegTsDemuxer::new();
+        expected_demuxer.demux(&FIXTURE[..2 * MPEGTS_PACKET_SIZE]);
+        let expected = expected_demuxer.demux(&FIXTURE[21 * MPEGTS_PACKET_SIZE..]);
+
+        assert!(!output.video_pts.is_empty());
+        assert_eq!(output.video, expected.video);
+        assert_eq!(output.video_pts, expected.video_pts);
+        assert_eq!(output.audio_pts, expected.audio_pts);
+    }
+
+    #[test]
+    fn pes_header_pts() {
+        // 33 bit PTS 0x1_2345_6789 split over marker bits
+        let header = [
+            0x00, 0x00, 0x01, 0xe0, 0x00, 0x00, 0x80, 0x80, 0x05, 0x29, 0x8d, 0x15, 0xcf, 0x13,
+        ];
+
+        assert_eq!(parse_pes_header(&header), Some((14, Some(0x1_2345_6789))));
+        assert_eq!(parse_pes_header(&header[..10]), None);
+        assert_eq!(
+            parse_pes_header(&[0x00, 0x00, 0x02, 0xe0, 0, 0, 0x80, 0x00, 0x00]),
+            None
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::live_stream::h264::{is_first_slice, nal_type, H264_NAL_SPS};
+
+pub const RING_BUFFER_DEFAULT_DURATION: Duration = Duration::from_secs(30);
+pub const RING_BUFFER_DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Group of pictures starting with a sequence header
+#[derive(Debug)]
+struct Gop {
+    id: u64,
+    started_at: Instant,
+    data: Vec<u8>,
+    pictures: usize,
+}
+
+/// GOP aligned ring buffer of recent H.264 video.
+///
+/// Every GOP starts with SPS/PPS (the camera repeats them inline), so
+/// any suffix of the buffer is a standalone decodable stream.
+#[derive(Debug)]
+pub struct GopRingBuffer {
+    duration: Duration,
+    max_bytes: usize,
+    gops: VecDeque<Gop>,
+    bytes: usize,
+    next_id: u64,
+    /// Units go to the last GOP, unset until the next sequence header after a gap
+    open: bool,
+    /// Exports in progress, keyed by export id, valued by the first GOP they need
+    pins: BTreeMap<u64, u64>,
+    next_pin: u64,
+}
+
+impl Default for GopRingBuffer {
+    fn default() -> Self {
+        Self::new(RING_BUFFER_DEFAULT_DURATION, RING_BUFFER_DEFAULT_MAX_BYTES)
+    }
+}
+
+impl GopRingBuffer {
+    pub fn new(duration: Duration, max_bytes: usize) -> Self {
+        Self {
+            duration,
+            max_bytes,
+            gops: VecDeque::new(),
+            bytes: 0,
+            next_id: 0,
+            open: false,
+            pins: BTreeMap::new(),
+            next_pin: 0,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Feed a single NAL unit, start code included
+    pub fn push(&mut self, unit: &[u8]) {
+        if nal_type(unit) == Some(H264_NAL_SPS) {
+            self.gops.push_back(Gop {
+                id: self.next_id,
+                started_at: Instant::now(),
+                data: Vec::new(),
+                pictures: 0,
+            });
+            self.next_id += 1;
+            self.open = true;
+        }
+
+        // nothing to attach to until the first sequence header
+        let Some(gop) = self.gops.back_mut().filter(|_| self.open) else {
+            return;
+        };
+
+        if is_first_slice(unit) {
+            gop.pictures += 1;
+        }
+
+        gop.data.extend_from_slice(unit);
+        self.bytes += unit.len();
+
+        self.evict();
+    }
+
+    /// Drop all buffered video, e.g. after the stream was interrupted
+    pub fn clear(&mut self) {
+        self.gops.clear();
+        self.bytes = 0;
+        self.open = false;
+    }
+
+    /// Drop the GOP being received after some of it was lost, complete GOPs are kept
+    pub fn discard_partial(&mut self) {
+        if !self.open {
+            return;
+        }
+
+        if let Some(gop) = self.gops.pop_back() {
+            self.bytes -= gop.data.len();
+        }
+        self.open = false;
+    }
+
+    /// Buffered video span and size
+    pub fn usage(&self) -> (Duration, usize) {
+        let span = self
+            .gops
+            .front()
+            .map(|gop| gop.started_at.elapsed())
+            .unwrap_or_default();
+
+        (span, self.bytes)
+    }
+
+    /// Hold on to everything from `pre` ago onward until released, returns a pin id
+    pub fn pin(&mut self, pre: Duration) -> u64 {
+        let first = self
+            .gops
+            .iter()
+            .rev()
+            .find(|gop| gop.started_at.elapsed() >= pre)
+            .or(self.gops.front())
+            .map(|gop| gop.id)
+            .unwrap_or(self.next_id);
+
+        let pin = self.next_pin;
+        self.next_pin += 1;
+        self.pins.insert(pin, first);
+
+        pin
+    }
+
+    /// Release a pin, returns the pinned video as a standalone Annex B stream
+    pub fn release(&mut self, pin: u64) -> Option<Vec<u8>> {
+        let first = self.pins.remove(&pin)?;
+
+        let data = self
+            .gops
+            .iter()
+            .filter(|gop| gop.id >= first && gop.pictures > 0)
+            .flat_map(|gop| gop.data.iter().copied())
+            .collect::<Vec<u8>>();
+
+        self.evict();
+
+        (!data.is_empty()).then_some(data)
+    }
+
+    fn evict(&mut self) {
+        let pinned = self.pins.values().min().copied();
+
+        while self.gops.len() > 1 {
+            let Some(front) = self.gops.front() else {
+                break;
+            };
+
+            // keep the GOP straddling the window start, the window must stay decodable
+            let expired = self
+                .gops
+                .get(1)
+                .is_some_and(|next| next.started_at.elapsed() >= self.duration)
+                && pinned.is_none_or(|pinned| front.id < pinned);
+            // hard cap, even pinned exports can't blow the memory budget
+            let oversized = self.bytes > self.max_bytes;
+
+            if !expired && !oversized {
+                break;
+            }
+
+            if let Some(gop) = self.gops.pop_front() {
+                self.bytes -= gop.data.len();
+            }
+        }
+    }
+}
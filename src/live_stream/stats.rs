@@ -0,0 +1,283 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Lossy consumers of the camera stream
+#[derive(Clone, Copy, Debug)]
+pub enum PipeTap {
+    /// Container demux feeding the GOP cache and video taps
+    Demux,
+    /// Frame hub decoder
+    Frames,
+}
+
+/// Throughput counters shared between the IO pipe and the watchdog
+#[derive(Debug)]
+pub struct PipeStats {
+    epoch: Instant,
+    bytes_total: AtomicU64,
+    frames_total: AtomicU64,
+    segments_total: AtomicU64,
+    // milliseconds since epoch, offset by one so zero means "never"
+    last_byte_ms: AtomicU64,
+    last_frame_ms: AtomicU64,
+    last_segment_ms: AtomicU64,
+    snapshots_total: AtomicU64,
+    snapshot_failures_total: AtomicU64,
+    last_snapshot_latency_ms: AtomicU64,
+    backpressure_total: AtomicU64,
+    backpressure_ms_total: AtomicU64,
+    demux_lagged_total: AtomicU64,
+    demux_dropped_chunks_total: AtomicU64,
+    frames_lagged_total: AtomicU64,
+    frames_dropped_chunks_total: AtomicU64,
+    pictures_decoded_total: AtomicU64,
+    pictures_skipped_total: AtomicU64,
+    pictures_published_total: AtomicU64,
+    decode_us_total: AtomicU64,
+    convert_us_total: AtomicU64,
+    rates: Mutex<PipeRates>,
+}
+
+#[derive(Debug)]
+struct PipeRates {
+    sampled_at: Instant,
+    bytes_total: u64,
+    frames_total: u64,
+    bytes_per_sec: f64,
+    frames_per_sec: f64,
+}
+
+/// Pipe metrics snapshot
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PipeMetrics {
+    pub bytes_total: u64,
+    pub frames_total: u64,
+    pub segments_total: u64,
+    pub bytes_per_sec: f64,
+    pub frames_per_sec: f64,
+    pub last_byte_age_ms: Option<u64>,
+    pub last_frame_age_ms: Option<u64>,
+    pub last_segment_age_ms: Option<u64>,
+    pub snapshots_total: u64,
+    pub snapshot_failures_total: u64,
+    pub last_snapshot_latency_ms: Option<u64>,
+    /// Times ffmpeg fell behind and the camera reader had to wait
+    pub backpressure_total: u64,
+    pub backpressure_ms_total: u64,
+    pub demux_lagged_total: u64,
+    pub demux_dropped_chunks_total: u64,
+    pub frames_lagged_total: u64,
+    pub frames_dropped_chunks_total: u64,
+    /// Pictures out of the frame hub decoder
+    pub pictures_decoded_total: u64,
+    /// Pictures the decode mode kept away from the decoder
+    pub pictures_skipped_total: u64,
+    /// Pictures converted and handed to analytics
+    pub pictures_published_total: u64,
+    pub decode_ms_total: u64,
+    pub convert_ms_total: u64,
+    /// Share of one CPU core spent decoding and converting frames since the pipe started
+    pub decode_load: f64,
+}
+
+impl Default for PipeStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipeStats {
+    pub fn new() -> Self {
+        let epoch = Instant::now();
+
+        Self {
+            epoch,
+            bytes_total: AtomicU64::new(0),
+            frames_total: AtomicU64::new(0),
+            segments_total: AtomicU64::new(0),
+            last_byte_ms: AtomicU64::new(0),
+            last_frame_ms: AtomicU64::new(0),
+            last_segment_ms: AtomicU64::new(0),
+            snapshots_total: AtomicU64::new(0),
+            snapshot_failures_total: AtomicU64::new(0),
+            last_snapshot_latency_ms: AtomicU64::new(0),
+            backpressure_total: AtomicU64::new(0),
+            backpressure_ms_total: AtomicU64::new(0),
+            demux_lagged_total: AtomicU64::new(0),
+            demux_dropped_chunks_total: AtomicU64::new(0),
+            frames_lagged_total: AtomicU64::new(0),
+            frames_dropped_chunks_total: AtomicU64::new(0),
+            pictures_decoded_total: AtomicU64::new(0),
+            pictures_skipped_total: AtomicU64::new(0),
+            pictures_published_total: AtomicU64::new(0),
+            decode_us_total: AtomicU64::new(0),
+            convert_us_total: AtomicU64::new(0),
+            rates: Mutex::new(PipeRates {
+                sampled_at: epoch,
+                bytes_total: 0,
+                frames_total: 0,
+                bytes_per_sec: 0.0,
+                frames_per_sec: 0.0,
+            }),
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64 + 1
+    }
+
+    fn age(&self, marker: &AtomicU64) -> Option<Duration> {
+        match marker.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(self.now_ms().saturating_sub(ms))),
+        }
+    }
+
+    /// Time since the pipe was set up
+    pub fn uptime(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    pub fn add_bytes(&self, n: usize) {
+        self.bytes_total.fetch_add(n as u64, Ordering::Relaxed);
+        self.last_byte_ms.store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn add_frames(&self, n: usize) {
+        self.frames_total.fetch_add(n as u64, Ordering::Relaxed);
+        self.last_frame_ms.store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn add_segment(&self) {
+        self.segments_total.fetch_add(1, Ordering::Relaxed);
+        self.last_segment_ms.store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    /// Record the primary consumer holding back the reader
+    pub fn add_backpressure(&self, waited: Duration) {
+        self.backpressure_total.fetch_add(1, Ordering::Relaxed);
+        self.backpressure_ms_total
+            .fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a lossy tap skipping ahead
+    pub fn add_dropped(&self, tap: PipeTap, chunks: u64) {
+        let (lagged, dropped) = match tap {
+            PipeTap::Demux => (&self.demux_lagged_total, &self.demux_dropped_chunks_total),
+            PipeTap::Frames => (&self.frames_lagged_total, &self.frames_dropped_chunks_total),
+        };
+
+        lagged.fetch_add(1, Ordering::Relaxed);
+        dropped.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    /// Record time spent in the frame hub decoder, `picture` when it output one
+    pub fn add_decode(&self, elapsed: Duration, picture: bool) {
+        self.decode_us_total
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+
+        if picture {
+            self.pictures_decoded_total.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record a picture the decode mode kept away from the decoder
+    pub fn add_skipped_picture(&self) {
+        self.pictures_skipped_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a picture converted for frame subscribers
+    pub fn add_conversion(&self, elapsed: Duration) {
+        self.pictures_published_total
+            .fetch_add(1, Ordering::Relaxed);
+        self.convert_us_total
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a snapshot attempt, `None` latency for a failed one
+    pub fn add_snapshot(&self, latency: Option<Duration>) {
+        self.snapshots_total.fetch_add(1, Ordering::Relaxed);
+
+        match latency {
+            // offset by one like the age markers, zero means "never"
+            Some(latency) => self
+                .last_snapshot_latency_ms
+                .store(latency.as_millis() as u64 + 1, Ordering::Relaxed),
+            None => {
+                self.snapshot_failures_total.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Time since the camera last produced any data
+    pub fn last_byte_age(&self) -> Option<Duration> {
+        self.age(&self.last_byte_ms)
+    }
+
+    /// Time since ffmpeg last produced a segment
+    pub fn last_segment_age(&self) -> Option<Duration> {
+        self.age(&self.last_segment_ms)
+    }
+
+    /// Recalculate rates since previous sample, meant to be called periodically
+    pub fn sample_rates(&self) {
+        let bytes_total = self.bytes_total.load(Ordering::Relaxed);
+        let frames_total = self.frames_total.load(Ordering::Relaxed);
+
+        if let Ok(mut rates) = self.rates.lock() {
+            let elapsed = rates.sampled_at.elapsed().as_secs_f64();
+            if elapsed > 0.0 {
+                rates.bytes_per_sec = (bytes_total - rates.bytes_total) as f64 / elapsed;
+                rates.frames_per_sec = (frames_total - rates.frames_total) as f64 / elapsed;
+            }
+
+            rates.sampled_at = Instant::now();
+            rates.bytes_total = bytes_total;
+            rates.frames_total = frames_total;
+        }
+    }
+
+    pub fn metrics(&self) -> PipeMetrics {
+        let (bytes_per_sec, frames_per_sec) = self
+            .rates
+            .lock()
+            .map(|rates| (rates.bytes_per_sec, rates.frames_per_sec))
+            .unwrap_or_default();
+
+        let decode_us_total = self.decode_us_total.load(Ordering::Relaxed);
+        let convert_us_total = self.convert_us_total.load(Ordering::Relaxed);
+        let uptime_us = self.uptime().as_micros().max(1) as f64;
+
+        PipeMetrics {
+            bytes_total: self.bytes_total.load(Ordering::Relaxed),
+            frames_total: self.frames_total.load(Ordering::Relaxed),
+            segments_total: self.segments_total.load(Ordering::Relaxed),
+            bytes_per_sec,
+            frames_per_sec,
+            last_byte_age_ms: self.last_byte_age().map(|d| d.as_millis() as u64),
+            last_frame_age_ms: self.age(&self.last_frame_ms).map(|d| d.as_millis() as u64),
+            last_segment_age_ms: self.last_segment_age().map(|d| d.as_millis() as u64),
+            snapshots_total: self.snapshots_total.load(Ordering::Relaxed),
+            snapshot_failures_total: self.snapshot_failures_total.load(Ordering::Relaxed),
+            last_snapshot_latency_ms: match self.last_snapshot_latency_ms.load(Ordering::Relaxed) {
+                0 => None,
+                ms => Some(ms - 1),
+            },
+            backpressure_total: self.backpressure_total.load(Ordering::Relaxed),
+            backpressure_ms_total: self.backpressure_ms_total.load(Ordering::Relaxed),
+            demux_lagged_total: self.demux_lagged_total.load(Ordering::Relaxed),
+            demux_dropped_chunks_total: self.demux_dropped_chunks_total.load(Ordering::Relaxed),
+            frames_lagged_total: self.frames_lagged_total.load(Ordering::Relaxed),
+            frames_dropped_chunks_total: self.frames_dropped_chunks_total.load(Ordering::Relaxed),
+            pictures_decoded_total: self.pictures_decoded_total.load(Ordering::Relaxed),
+            pictures_skipped_total: self.pictures_skipped_total.load(Ordering::Relaxed),
+            pictures_published_total: self.pictures_published_total.load(Ordering::Relaxed),
+            decode_ms_total: decode_us_total / 1000,
+            convert_ms_total: convert_us_total / 1000,
+            decode_load: (decode_us_total + convert_us_total) as f64 / uptime_us,
+        }
+    }
+}
@@ -1,40 +1,277 @@
+use std::collections::VecDeque;
 use std::fmt::Display;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
+use serde::Serialize;
+use serde::Serializer;
 use tokio::io::AsyncBufReadExt;
 use tokio::io::BufReader;
 use tokio::process::Child;
 use tokio::sync::oneshot::channel as oneshot_channel;
 use tokio::sync::oneshot::Receiver;
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
 use tokio::task::JoinHandle;
 use tracing::debug;
 use tracing::error;
 use tracing::info;
 use tracing::warn;
 
-#[derive(Clone, Debug)]
+use crate::process_control::resources::ProcessStats;
+use crate::process_control::resources::ResourceSampler;
+use crate::process_control::resources::ResourceWatch;
+use crate::telemetry::events::EventDispatcher;
+
+pub mod limits;
+pub mod resources;
+
+/// Grace period given to each signal during shutdown
+pub const PROCESS_DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// Stderr lines kept for exit diagnostics
+pub const PROCESS_DEFAULT_STDERR_LINES: usize = 50;
+/// How long to wait for the remaining stderr output once the child is reaped
+pub const PROCESS_STDERR_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Known fatal conditions, recognized from stderr lines (lowercase)
+const PROCESS_FATAL_PATTERNS: [(&str, ProcessFatal); 9] = [
+    ("failed to acquire camera", ProcessFatal::CameraBusy),
+    ("no cameras available", ProcessFatal::CameraNotFound),
+    ("unknown encoder", ProcessFatal::EncoderUnavailable),
+    (
+        "error while opening encoder",
+        ProcessFatal::EncoderUnavailable,
+    ),
+    (
+        "cannot open audio device",
+        ProcessFatal::AudioDeviceUnavailable,
+    ),
+    ("no space left on device", ProcessFatal::DiskFull),
+    ("cannot allocate memory", ProcessFatal::OutOfMemory),
+    ("out of memory", ProcessFatal::OutOfMemory),
+    ("permission denied", ProcessFatal::PermissionDenied),
+];
+
+/// Typed cause of a process failure, restarting usually won't help
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessFatal {
+    /// Camera held by another process
+    CameraBusy,
+    CameraNotFound,
+    EncoderUnavailable,
+    AudioDeviceUnavailable,
+    DiskFull,
+    OutOfMemory,
+    PermissionDenied,
+}
+
+impl ProcessFatal {
+    /// Match a stderr line against the known fatal patterns
+    pub fn classify(line: &str) -> Option<Self> {
+        let line = line.to_lowercase();
+
+        PROCESS_FATAL_PATTERNS
+            .iter()
+            .find(|(pattern, _)| line.contains(pattern))
+            .map(|(_, fatal)| *fatal)
+    }
+}
+
+impl Display for ProcessFatal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::CameraBusy => "camera in use by another process",
+            Self::CameraNotFound => "no camera detected",
+            Self::EncoderUnavailable => "encoder unavailable",
+            Self::AudioDeviceUnavailable => "audio device unavailable",
+            Self::DiskFull => "disk full",
+            Self::OutOfMemory => "out of memory",
+            Self::PermissionDenied => "permission denied",
+        };
+
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ProcessExit {
-    code: i32,
+    /// `None` when terminated by a signal
+    code: Option<i32>,
+    #[serde(serialize_with = "serialize_signal")]
+    signal: Option<Signal>,
+    core_dumped: bool,
     message: Option<String>,
+    /// Last stderr lines, oldest first
+    stderr: Vec<String>,
+    /// First known fatal condition seen on stderr
+    fatal: Option<ProcessFatal>,
 }
 
 impl ProcessExit {
-    pub fn new(code: i32, message: Option<String>) -> Self {
-        Self { code, message }
+    pub fn new(status: ExitStatus) -> Self {
+        Self {
+            code: status.code(),
+            signal: status
+                .signal()
+                .and_then(|signal| Signal::try_from(signal).ok()),
+            core_dumped: status.core_dumped(),
+            message: None,
+            stderr: Vec::new(),
+            fatal: None,
+        }
+    }
+
+    /// Exit status unknown, waiting on the child failed
+    pub fn error(message: impl ToString) -> Self {
+        Self {
+            code: None,
+            signal: None,
+            core_dumped: false,
+            message: Some(message.to_string()),
+            stderr: Vec::new(),
+            fatal: None,
+        }
+    }
+
+    /// Attach the stderr tail and its classification
+    pub fn with_stderr(mut self, stderr: Vec<String>, fatal: Option<ProcessFatal>) -> Self {
+        self.stderr = stderr;
+        self.fatal = fatal;
+
+        self
+    }
+
+    pub fn code(&self) -> Option<i32> {
+        self.code
+    }
+
+    pub fn signal(&self) -> Option<Signal> {
+        self.signal
+    }
+
+    pub fn core_dumped(&self) -> bool {
+        self.core_dumped
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn stderr(&self) -> &[String] {
+        &self.stderr
+    }
+
+    pub fn fatal(&self) -> Option<ProcessFatal> {
+        self.fatal
+    }
+
+    /// Exited on its own with code `0`
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
     }
 }
 
 impl Display for ProcessExit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "code = \"{}\"; message = \"{}\"",
-            self.code,
-            self.message.as_deref().unwrap_or_default()
-        )
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "code = \"{}\"", code)?,
+            (None, Some(signal)) => write!(f, "signal = \"{}\"", signal)?,
+            (None, None) => write!(f, "code = \"unknown\"")?,
+        }
+
+        if self.core_dumped {
+            write!(f, " (core dumped)")?;
+        }
+
+        if let Some(fatal) = self.fatal {
+            write!(f, "; fatal = \"{}\"", fatal)?;
+        }
+
+        if let Some(message) = self.message.as_deref() {
+            write!(f, "; message = \"{}\"", message)?;
+        }
+
+        if let Some(line) = self.stderr.last() {
+            write!(f, "; stderr = \"{}\"", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn serialize_signal<S: Serializer>(
+    signal: &Option<Signal>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_some(&signal.map(|signal| signal.as_str()))
+}
+
+/// Most recent stderr lines of a process
+#[derive(Debug)]
+struct StderrTail {
+    lines: VecDeque<String>,
+    capacity: usize,
+    fatal: Option<ProcessFatal>,
+}
+
+impl StderrTail {
+    fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(capacity),
+            capacity,
+            fatal: None,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.fatal.is_none() {
+            self.fatal = ProcessFatal::classify(&line);
+        }
+
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(line);
+    }
+}
+
+/// How a shutdown ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProcessShutdown {
+    /// Exited before any signal was sent
+    AlreadyExited(ProcessExit),
+    /// Exited after the given signal, earlier ones were ignored
+    Signaled { signal: Signal, exit: ProcessExit },
+    /// Still not reaped after `SIGKILL`
+    Unreaped,
+}
+
+impl ProcessShutdown {
+    /// Did it take more than a polite `SIGINT`?
+    pub fn escalated(&self) -> bool {
+        matches!(self, Self::Signaled { signal, .. } if *signal != Signal::SIGINT)
+            || matches!(self, Self::Unreaped)
+    }
+}
+
+impl Display for ProcessShutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyExited(exit) => write!(f, "already exited, {}", exit),
+            Self::Signaled { signal, exit } => write!(f, "exited after {}, {}", signal, exit),
+            Self::Unreaped => write!(f, "not reaped after {}", Signal::SIGKILL),
+        }
     }
 }
 
@@ -42,9 +279,14 @@ impl Display for ProcessExit {
 pub struct ProcessControl {
     id: String,
     pid: u32,
-    logger: JoinHandle<()>,
+    logger: AbortHandle,
     waiter: JoinHandle<()>,
     exit_rx: Option<Receiver<ProcessExit>>,
+    exit_watch: watch::Receiver<Option<ProcessExit>>,
+    stderr: Arc<Mutex<StderrTail>>,
+    /// Set as soon as the child is reaped, before its exit is published
+    reaped: Arc<AtomicBool>,
+    resources: Option<ResourceWatch>,
     stopped: bool,
 }
 
@@ -61,8 +303,11 @@ impl ProcessControl {
             .take()
             .ok_or_else(|| anyhow!("Failed to capture child process output for {}", &log_id))?;
 
+        let stderr_tail = Arc::new(Mutex::new(StderrTail::new(PROCESS_DEFAULT_STDERR_LINES)));
+        let tail = stderr_tail.clone();
+
         // logger
-        let handle_logger = tokio::spawn(async move {
+        let mut handle_logger = tokio::spawn(async move {
             let mut reader = BufReader::new(stderr).lines();
 
             while let Ok(Some(line)) = reader.next_line().await.map_err(|e| {
@@ -79,29 +324,52 @@ impl ProcessControl {
                     target = "process_control",
                     "PROC[{}] STDERR: {}", &log_id, line
                 );
+
+                tail.lock().unwrap_or_else(|e| e.into_inner()).push(line);
             }
         });
+        let logger = handle_logger.abort_handle();
 
         let log_id = id.to_string();
+        let tail = stderr_tail.clone();
 
         let (exit_tx, exit_rx) = oneshot_channel::<ProcessExit>();
+        let (exit_watch_tx, exit_watch) = watch::channel::<Option<ProcessExit>>(None);
+        let reaped = Arc::new(AtomicBool::new(false));
+        let waiter_reaped = reaped.clone();
 
-        // waiter
+        // waiter, also reaps the child
         let handle_waiter = tokio::spawn(async move {
-            match child.wait().await {
-                Ok(code) => {
-                    let code = code.code().unwrap_or(-1);
-                    info!(
-                        target = "process_control",
-                        "Child process `{}` exited with code: {}", &log_id, code
-                    );
+            let status = child.wait().await;
+
+            // the PID may be reused from here on, signals must stop before the stderr drain
+            waiter_reaped.store(true, Ordering::Release);
 
-                    if let Err(e) = exit_tx.send(ProcessExit::new(code, None)) {
-                        error!(
+            let exit = match status {
+                Ok(status) => {
+                    let exit = ProcessExit::new(status);
+
+                    match (exit.code(), exit.signal()) {
+                        (None, Some(signal)) => info!(
+                            target = "process_control",
+                            "Child process `{}` terminated by {}{}",
+                            &log_id,
+                            signal,
+                            if exit.core_dumped() {
+                                ", core dumped"
+                            } else {
+                                ""
+                            }
+                        ),
+                        (code, _) => info!(
                             target = "process_control",
-                            "Failed to report process `{}` exit: {}", &log_id, e
-                        );
+                            "Child process `{}` exited with code: {}",
+                            &log_id,
+                            code.unwrap_or(-1)
+                        ),
                     }
+
+                    exit
                 }
                 Err(e) => {
                     error!(
@@ -109,24 +377,54 @@ impl ProcessControl {
                         "Child process `{}` await error: {}", &log_id, e
                     );
 
-                    if let Err(e) =
-                        exit_tx.send(ProcessExit::new(-1, Some(format!("Error: {}", e))))
-                    {
-                        error!(
-                            target = "process_control",
-                            "Failed to report process `{}` exit: {}", &log_id, e
-                        );
-                    }
+                    ProcessExit::error(format!("Error: {}", e))
                 }
+            };
+
+            // the last lines usually explain the exit, give the logger a chance to read them
+            if tokio::time::timeout(PROCESS_STDERR_DRAIN_TIMEOUT, &mut handle_logger)
+                .await
+                .is_err()
+            {
+                debug!(
+                    target = "process_control",
+                    "Stderr of process `{}` still open after exit", &log_id
+                );
+            }
+
+            let exit = {
+                let tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+                exit.with_stderr(tail.lines.iter().cloned().collect(), tail.fatal)
+            };
+
+            if let Some(fatal) = exit.fatal() {
+                warn!(
+                    target = "process_control",
+                    "Child process `{}` hit a fatal condition: {}", &log_id, fatal
+                );
+            }
+
+            exit_watch_tx.send_replace(Some(exit.clone()));
+
+            // nobody watching is fine once a shutdown was requested
+            if exit_tx.send(exit).is_err() {
+                debug!(
+                    target = "process_control",
+                    "Nobody watching process `{}` exit", &log_id
+                );
             }
         });
 
         Ok(Self {
             id: id.to_string(),
             pid,
-            logger: handle_logger,
+            logger,
             waiter: handle_waiter,
             exit_rx: Some(exit_rx),
+            exit_watch,
+            stderr: stderr_tail,
+            reaped,
+            resources: None,
             stopped: false,
         })
     }
@@ -143,17 +441,121 @@ impl ProcessControl {
         self.exit_rx.take()
     }
 
+    /// Sample CPU, memory and I/O usage from `/proc` until the process exits
+    pub fn watch_resources(&mut self, interval: Duration, events: Option<EventDispatcher>) {
+        if let Some(previous) = self.resources.take() {
+            previous.stop();
+        }
+
+        self.resources = Some(ResourceWatch::spawn(
+            ResourceSampler::new(&self.id, self.pid),
+            interval,
+            events,
+        ));
+    }
+
+    /// Latest resource usage sample, if watched
+    pub fn resources(&self) -> Option<ProcessStats> {
+        self.resources.as_ref().and_then(|watch| watch.latest())
+    }
+
+    /// Most recent stderr lines, oldest first
+    pub fn stderr_tail(&self) -> Vec<String> {
+        let tail = self.stderr.lock().unwrap_or_else(|e| e.into_inner());
+
+        tail.lines.iter().cloned().collect()
+    }
+
+    /// Has the child been reaped, its exit may still be pending while stderr drains
+    pub fn reaped(&self) -> bool {
+        self.reaped.load(Ordering::Acquire)
+    }
+
+    /// Exit status once the child is reaped
+    pub fn exit(&self) -> Option<ProcessExit> {
+        self.exit_watch.borrow().clone()
+    }
+
+    /// Ask the process to quit with `SIGINT`, without waiting
     pub fn stop(&mut self) -> Result<()> {
         self.stopped = true;
         self.send_signal_inner(Signal::SIGINT)
     }
 
+    /// Force the process out with `SIGKILL`, without waiting
     pub fn kill(&mut self) -> Result<()> {
         self.stopped = true;
-        self.send_signal_inner(Signal::SIGTERM)
+        self.send_signal_inner(Signal::SIGKILL)
+    }
+
+    /// Stop the process and wait until it is reaped
+    ///
+    /// Sends `SIGINT`, then `SIGTERM` and finally `SIGKILL`, waiting up to `timeout` after
+    /// each of them.
+    pub async fn shutdown(&mut self, timeout: Duration) -> ProcessShutdown {
+        self.stopped = true;
+
+        if let Some(exit) = self.exit() {
+            return ProcessShutdown::AlreadyExited(exit);
+        }
+
+        if self.reaped() {
+            if let Some(exit) = self.wait().await {
+                return ProcessShutdown::AlreadyExited(exit);
+            }
+        }
+
+        for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGKILL] {
+            // failing to signal usually means it is already gone, the waiter will tell
+            if let Err(e) = self.send_signal_inner(signal) {
+                debug!(target = "process_control", "{}", e);
+            }
+
+            match tokio::time::timeout(timeout, self.wait()).await {
+                Ok(Some(exit)) => return ProcessShutdown::Signaled { signal, exit },
+                Ok(None) => break,
+                // reaped, only the stderr drain is left before the exit is published
+                Err(_) if self.reaped() => match self.wait().await {
+                    Some(exit) => return ProcessShutdown::Signaled { signal, exit },
+                    None => break,
+                },
+                Err(_) => warn!(
+                    target = "process_control",
+                    "Process `{}` still running {} ms after {}",
+                    self.id,
+                    timeout.as_millis(),
+                    signal
+                ),
+            }
+        }
+
+        error!(
+            target = "process_control",
+            "Unable to reap process `{}` with PID {}", self.id, self.pid
+        );
+
+        ProcessShutdown::Unreaped
+    }
+
+    /// Wait for the child to be reaped, `None` when the waiter is gone
+    pub async fn wait(&mut self) -> Option<ProcessExit> {
+        self.exit_watch
+            .wait_for(|exit| exit.is_some())
+            .await
+            .ok()
+            .and_then(|exit| exit.clone())
     }
 
     fn send_signal_inner(&mut self, sig: Signal) -> Result<()> {
+        // never signal a reaped PID, it may belong to another process by now
+        if self.reaped() {
+            return Err(anyhow!(
+                "Not sending {} to process with PID {}, already reaped",
+                sig,
+                self.pid
+            ));
+        }
+
         let nix_pid = Pid::from_raw(self.pid as i32);
         kill(nix_pid, sig).map_err(|e| {
             anyhow!(
@@ -168,6 +570,10 @@ impl ProcessControl {
 
 impl Drop for ProcessControl {
     fn drop(&mut self) {
+        if let Some(resources) = self.resources.take() {
+            resources.stop();
+        }
+
         if !self.stopped {
             warn!(
                 target = "process_control",
@@ -181,3 +587,39 @@ impl Drop for ProcessControl {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::Stdio;
+
+    use tokio::process::Command;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_after_reap_sends_no_signal() {
+        // the background sleep keeps stderr open past the drain timeout
+        let child = Command::new("sh")
+            .args(["-c", "sleep 2 >&2 & exit 3"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut process = ProcessControl::new("reaped", child).unwrap();
+
+        while !process.reaped() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(process.exit().is_none());
+        assert!(process.stop().is_err());
+
+        let shutdown = process.shutdown(Duration::from_millis(50)).await;
+
+        assert!(!shutdown.escalated(), "{}", shutdown);
+        assert!(
+            matches!(&shutdown, ProcessShutdown::AlreadyExited(exit) if exit.code() == Some(3)),
+            "{}",
+            shutdown
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+/// Parent of the per-process cgroups, must be delegated to us with `cpu` and `memory` enabled
+pub const PROCESS_DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup/babypi";
+/// `cpu.max` accounting period, in microseconds
+pub const PROCESS_CGROUP_CPU_PERIOD: u64 = 100_000;
+
+// not exposed by libc
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// I/O scheduling class, see `ionice(1)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    /// Only served when nobody else needs the disk
+    Idle,
+}
+
+impl IoClass {
+    fn value(&self) -> libc::c_int {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+}
+
+/// Errno of the priority, I/O priority and affinity calls made in the child before `exec`
+///
+/// Written by the child to a non-blocking close-on-exec pipe, `0` when the call succeeded
+/// or wasn't made.
+#[derive(Debug)]
+pub struct ChildLimitErrors(File);
+
+impl ChildLimitErrors {
+    const SIZE: usize = 3 * std::mem::size_of::<libc::c_int>();
+
+    fn pipe() -> Option<(Self, OwnedFd)> {
+        let mut fds = [0 as libc::c_int; 2];
+
+        // SAFETY: both descriptors are owned right after a successful call
+        unsafe {
+            if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) != 0 {
+                return None;
+            }
+
+            Some((
+                Self(File::from(OwnedFd::from_raw_fd(fds[0]))),
+                OwnedFd::from_raw_fd(fds[1]),
+            ))
+        }
+    }
+
+    /// Errors reported by the child, none when it didn't get to write them
+    fn read(mut self) -> Option<[libc::c_int; 3]> {
+        let mut bytes = [0u8; Self::SIZE];
+        self.0.read_exact(&mut bytes).ok()?;
+
+        let errno = |i: usize| {
+            let size = std::mem::size_of::<libc::c_int>();
+            libc::c_int::from_ne_bytes(bytes[i * size..(i + 1) * size].try_into().unwrap())
+        };
+
+        Some([errno(0), errno(1), errno(2)])
+    }
+}
+
+/// Why a limit is missing, from the errno the child got while applying it
+fn missing_reason(errno: Option<libc::c_int>, needs_privilege: bool) -> String {
+    match errno {
+        Some(0) => "reverted after it was applied".to_string(),
+        Some(errno) => {
+            let error = std::io::Error::from_raw_os_error(errno);
+
+            if needs_privilege && matches!(errno, libc::EPERM | libc::EACCES) {
+                format!("{}, requires CAP_SYS_NICE", error)
+            } else {
+                error.to_string()
+            }
+        }
+        None => "not applied".to_string(),
+    }
+}
+
+/// Scheduling priority and resource caps of a child process
+///
+/// Priority and affinity are set in the child before `exec`, so every thread inherits them.
+/// They are best effort like the cgroup limits applied right after spawning, a process
+/// missing `CAP_SYS_NICE` still starts and a warning tells what didn't apply.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessLimits {
+    /// `-20` (favorable) to `19`, raising priority requires `CAP_SYS_NICE`
+    pub nice: Option<i32>,
+    pub ionice_class: Option<IoClass>,
+    /// `0` (highest) to `7` within the class, ignored for `idle`
+    pub ionice_level: Option<u8>,
+    /// Allowed CPU cores
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// Cgroup v2 CPU quota in cores, `0.5` is half of one core
+    pub cpu_max: Option<f32>,
+    /// Cgroup v2 memory cap, in MiB
+    pub memory_max: Option<u64>,
+    /// Where per-process cgroups are created, defaults to `/sys/fs/cgroup/babypi`
+    pub cgroup_root: Option<PathBuf>,
+}
+
+impl ProcessLimits {
+    pub fn validate(&self) -> Result<()> {
+        if self.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+            return Err(anyhow!("Nice level must be within -20 and 19."));
+        }
+
+        if self.ionice_level.is_some_and(|level| level > 7) {
+            return Err(anyhow!("I/O priority level must be within 0 and 7."));
+        }
+
+        if let Some(cores) = self.cpu_affinity.as_ref() {
+            let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+            if cores.is_empty() || cores.iter().any(|core| *core >= available) {
+                return Err(anyhow!(
+                    "CPU affinity must list cores within 0 and {}.",
+                    available - 1
+                ));
+            }
+        }
+
+        if self.cpu_max.is_some_and(|cores| cores <= 0.0) {
+            return Err(anyhow!("Cgroup CPU quota must be positive."));
+        }
+
+        if self.memory_max == Some(0) {
+            return Err(anyhow!("Cgroup memory cap must be positive."));
+        }
+
+        Ok(())
+    }
+
+    fn has_cgroup(&self) -> bool {
+        self.cpu_max.is_some() || self.memory_max.is_some()
+    }
+
+    fn ioprio(&self) -> Option<libc::c_int> {
+        self.ionice_class.map(|class| {
+            let level = match class {
+                IoClass::Idle => 0,
+                _ => self.ionice_level.unwrap_or(4) as libc::c_int,
+            };
+
+            (class.value() << IOPRIO_CLASS_SHIFT) | level
+        })
+    }
+
+    /// Set priority and affinity in the child before it executes, failures are left to `verify`
+    pub fn configure(&self, command: &mut Command) -> Option<ChildLimitErrors> {
+        let nice = self.nice;
+        let ioprio = self.ioprio();
+        let cores = self.cpu_affinity.clone();
+
+        if nice.is_none() && ioprio.is_none() && cores.is_none() {
+            return None;
+        }
+
+        // the parent copy of the write end goes away with the command
+        let (errors, errors_tx) = ChildLimitErrors::pipe().unzip();
+
+        // SAFETY: only async-signal-safe syscalls run between fork and exec, the CPU set
+        // is built on the stack from data captured before forking
+        unsafe {
+            command.pre_exec(move || {
+                let mut errors: [libc::c_int; 3] = [0; 3];
+
+                if let Some(nice) = nice {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                        errors[0] = *libc::__errno_location();
+                    }
+                }
+
+                if let Some(ioprio) = ioprio {
+                    if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0 {
+                        errors[1] = *libc::__errno_location();
+                    }
+                }
+
+                if let Some(cores) = cores.as_ref() {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+                    for core in cores {
+                        libc::CPU_SET(*core, &mut set);
+                    }
+
+                    if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0
+                    {
+                        errors[2] = *libc::__errno_location();
+                    }
+                }
+
+                if let Some(errors_tx) = errors_tx.as_ref() {
+                    libc::write(
+                        errors_tx.as_raw_fd(),
+                        errors.as_ptr() as *const libc::c_void,
+                        ChildLimitErrors::SIZE,
+                    );
+                }
+
+                Ok(())
+            });
+        }
+
+        errors
+    }
+
+    /// Warn about the priority and affinity a freshly spawned process didn't get, and why
+    pub fn verify(&self, name: &str, pid: u32, errors: Option<ChildLimitErrors>) {
+        let errors = errors.and_then(ChildLimitErrors::read);
+        let errno = |i: usize| errors.map(|errors| errors[i]);
+        let mut missing = Vec::new();
+
+        if let Some(nice) = self.nice {
+            // SAFETY: plain syscalls on a pid, -1 is a valid priority so errno tells errors apart
+            let actual = unsafe {
+                *libc::__errno_location() = 0;
+                let actual = libc::getpriority(libc::PRIO_PROCESS, pid);
+                (*libc::__errno_location() == 0).then_some(actual)
+            };
+
+            if actual != Some(nice) {
+                missing.push(format!(
+                    "nice {} ({})",
+                    nice,
+                    missing_reason(errno(0), true)
+                ));
+            }
+        }
+
+        if let Some(ioprio) = self.ioprio() {
+            // SAFETY: plain syscall on a pid
+            let actual = unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, pid) };
+
+            if actual != ioprio as libc::c_long {
+                missing.push(format!("I/O priority ({})", missing_reason(errno(1), true)));
+            }
+        }
+
+        if let Some(cores) = self.cpu_affinity.as_ref() {
+            // SAFETY: both sets are zeroed and sized for the syscall
+            let applied = unsafe {
+                let size = std::mem::size_of::<libc::cpu_set_t>();
+                let mut expected: libc::cpu_set_t = std::mem::zeroed();
+                let mut actual: libc::cpu_set_t = std::mem::zeroed();
+                for core in cores {
+                    libc::CPU_SET(*core, &mut expected);
+                }
+
+                libc::sched_getaffinity(pid as libc::pid_t, size, &mut actual) == 0
+                    && libc::CPU_EQUAL(&expected, &actual)
+            };
+
+            if !applied {
+                missing.push(format!(
+                    "CPU affinity ({})",
+                    missing_reason(errno(2), false)
+                ));
+            }
+        }
+
+        if !missing.is_empty() {
+            warn!(
+                target = "process_control",
+                "Process `{}` runs without {}",
+                name,
+                missing.join(", ")
+            );
+        }
+    }
+
+    /// Move a freshly spawned process into its own cgroup with the CPU and memory caps
+    pub fn attach(&self, name: &str, pid: u32) -> Result<()> {
+        if !self.has_cgroup() {
+            return Ok(());
+        }
+
+        let root = self
+            .cgroup_root
+            .clone()
+            .unwrap_or(PROCESS_DEFAULT_CGROUP_ROOT.into());
+
+        // usually already enabled by whoever delegated the root
+        if let Err(e) = std::fs::write(root.join("cgroup.subtree_control"), "+cpu +memory") {
+            debug!(
+                target = "process_control",
+                "Unable to enable cgroup controllers in {}: {}",
+                root.display(),
+                e
+            );
+        }
+
+        let cgroup = root.join(name);
+        std::fs::create_dir_all(&cgroup)
+            .map_err(|e| anyhow!("Failed to create cgroup {}: {}", cgroup.display(), e))?;
+
+        let write = |file: &str, value: String| {
+            std::fs::write(cgroup.join(file), &value).map_err(|e| {
+                anyhow!(
+                    "Failed to set {} to `{}` for cgroup {}: {}",
+                    file,
+                    value,
+                    cgroup.display(),
+                    e
+                )
+            })
+        };
+
+        write(
+            "cpu.max",
+            match self.cpu_max {
+                Some(cores) => format!(
+                    "{} {}",
+                    (cores * PROCESS_CGROUP_CPU_PERIOD as f32) as u64,
+                    PROCESS_CGROUP_CPU_PERIOD
+                ),
+                None => format!("max {}", PROCESS_CGROUP_CPU_PERIOD),
+            },
+        )?;
+        write(
+            "memory.max",
+            match self.memory_max {
+                Some(mib) => (mib * 1024 * 1024).to_string(),
+                None => "max".to_string(),
+            },
+        )?;
+        write("cgroup.procs", pid.to_string())?;
+
+        info!(
+            target = "process_control",
+            "Process `{}` moved to cgroup {}",
+            name,
+            cgroup.display()
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn child_reports_each_failing_call() {
+        let limits = ProcessLimits {
+            // lowering priority needs no privilege
+            nice: Some(5),
+            // past the last core, the kernel refuses the whole set
+            cpu_affinity: Some(vec![libc::CPU_SETSIZE as usize - 1]),
+            ..Default::default()
+        };
+
+        let mut command = Command::new("true");
+        let errors = limits.configure(&mut command).unwrap();
+        command.spawn().unwrap().wait().await.unwrap();
+
+        assert_eq!(errors.read(), Some([0, 0, libc::EINVAL]));
+    }
+
+    #[test]
+    fn reasons_name_the_error() {
+        assert_eq!(
+            missing_reason(Some(libc::EACCES), true),
+            "Permission denied (os error 13), requires CAP_SYS_NICE"
+        );
+        assert_eq!(
+            missing_reason(Some(libc::EINVAL), false),
+            "Invalid argument (os error 22)"
+        );
+        assert_eq!(missing_reason(None, true), "not applied");
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+use tracing::debug;
+
+use crate::serde_stuff::float_precision_two;
+use crate::telemetry::events::{Event, EventDispatcher};
+
+pub const PROCESS_DEFAULT_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// `USER_HZ`, the unit of `/proc` CPU times, fixed at 100 on Linux
+const PROC_CLOCK_TICKS: f64 = 100.0;
+
+/// Resource usage of a process
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessStats {
+    pub process: String,
+    pub pid: u32,
+    /// Share of one core since the previous sample, can exceed 100
+    #[serde(with = "float_precision_two")]
+    pub cpu_percent: f32,
+    pub rss_bytes: u64,
+    pub threads: u32,
+    /// Storage I/O, `None` when `/proc/<pid>/io` is not readable
+    pub read_bytes: Option<u64>,
+    pub write_bytes: Option<u64>,
+}
+
+/// Reads `/proc/<pid>` and turns CPU times into a usage between two samples
+#[derive(Debug)]
+pub struct ResourceSampler {
+    process: String,
+    pid: u32,
+    previous: Option<(Instant, u64)>,
+}
+
+impl ResourceSampler {
+    pub fn new(process: impl ToString, pid: u32) -> Self {
+        Self {
+            process: process.to_string(),
+            pid,
+            previous: None,
+        }
+    }
+
+    /// Current usage, CPU usage is zero on the first sample
+    pub fn sample(&mut self) -> Result<ProcessStats> {
+        let dir = PathBuf::from(format!("/proc/{}", self.pid));
+
+        let stat = std::fs::read_to_string(dir.join("stat"))?;
+        let cpu_ticks = parse_stat_cpu_ticks(&stat)
+            .ok_or_else(|| anyhow!("Malformed /proc/{}/stat", self.pid))?;
+
+        let status = std::fs::read_to_string(dir.join("status"))?;
+        let rss_bytes = parse_field(&status, "VmRSS:").unwrap_or(0) * 1024;
+        let threads = parse_field(&status, "Threads:").unwrap_or(0) as u32;
+
+        // owned by the same user but still restricted on some kernels
+        let io = std::fs::read_to_string(dir.join("io")).ok();
+        let read_bytes = io.as_deref().and_then(|io| parse_field(io, "read_bytes:"));
+        let write_bytes = io.as_deref().and_then(|io| parse_field(io, "write_bytes:"));
+
+        let now = Instant::now();
+        let cpu_percent = match self.previous {
+            Some((at, ticks)) if now > at => {
+                let busy = cpu_ticks.saturating_sub(ticks) as f64 / PROC_CLOCK_TICKS;
+
+                (100.0 * busy / now.duration_since(at).as_secs_f64()) as f32
+            }
+            _ => 0.0,
+        };
+        self.previous = Some((now, cpu_ticks));
+
+        Ok(ProcessStats {
+            process: self.process.clone(),
+            pid: self.pid,
+            cpu_percent,
+            rss_bytes,
+            threads,
+            read_bytes,
+            write_bytes,
+        })
+    }
+}
+
+/// `utime + stime`, fields 14 and 15, counted after the parenthesized command name
+fn parse_stat_cpu_ticks(stat: &str) -> Option<u64> {
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+
+    // field 3 (state) is the first one after the command name
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+
+    Some(utime + stime)
+}
+
+/// First number following `key` in a `key: value` listing
+fn parse_field(content: &str, key: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(key))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Periodic sampling of a process until it is gone
+#[derive(Clone, Debug)]
+pub struct ResourceWatch {
+    latest: watch::Receiver<Option<ProcessStats>>,
+    handle: AbortHandle,
+}
+
+impl ResourceWatch {
+    /// Sample every `interval`, publishing `Event::ProcessStats` when given a dispatcher
+    pub fn spawn(
+        mut sampler: ResourceSampler,
+        interval: Duration,
+        events: Option<EventDispatcher>,
+    ) -> Self {
+        let (tx, latest) = watch::channel(None);
+
+        let handle = tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+
+            loop {
+                timer.tick().await;
+
+                let stats = match sampler.sample() {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        debug!(
+                            target = "process_control",
+                            "Stopped sampling process `{}`: {}", sampler.process, e
+                        );
+
+                        break;
+                    }
+                };
+
+                if let Some(events) = events.as_ref() {
+                    events.send(Event::ProcessStats(stats.clone()));
+                }
+
+                tx.send_replace(Some(stats));
+            }
+        })
+        .abort_handle();
+
+        Self { latest, handle }
+    }
+
+    /// Most recent sample
+    pub fn latest(&self) -> Option<ProcessStats> {
+        self.latest.borrow().clone()
+    }
+
+    pub fn stop(&self) {
+        self.handle.abort();
+    }
+}
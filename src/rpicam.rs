@@ -15,6 +15,11 @@ use tokio::process::Child;
 use tokio::process::Command;
 use tracing::debug;
 use tracing::error;
+use tracing::warn;
+
+use crate::ffmpeg::audio::FfmpegAudio;
+use crate::ffmpeg::audio::FFMPEG_DEFAULT_AUDIO_OUTPUT_BITRATE;
+use crate::process_control::limits::ProcessLimits;
 
 pub const RPICAM_BIN: &str = "rpicam-vid";
 
@@ -47,6 +52,26 @@ pub struct Rpicam {
     // pub output_file: Option<PathBuf>,
     pub extra_args: Option<Vec<String>>,
     // pub psips_pipe: bool,
+    pub libav: Option<RpicamLibav>,
+    /// Keyframe period in frames, encoder default when `None`
+    pub intra: Option<u32>,
+    pub limits: Option<ProcessLimits>,
+}
+
+/// Timestamped MPEG-TS output through `--codec libav`
+///
+/// Video frames carry the sensor capture timestamps and the optional
+/// audio track is captured and muxed by `rpicam-vid` on the same clock.
+#[derive(Clone, Debug, Default)]
+pub struct RpicamLibav {
+    pub video_codec: Option<String>,
+    pub audio: Option<FfmpegAudio>,
+}
+
+impl RpicamLibav {
+    pub fn new(video_codec: Option<String>, audio: Option<FfmpegAudio>) -> Self {
+        Self { video_codec, audio }
+    }
 }
 
 // impl Default for Rpicam {
@@ -312,13 +337,42 @@ impl Rpicam {
             // output_file,
             extra_args,
             // psips_pipe: psips,
+            libav: None,
+            intra: None,
+            limits: None,
         }
     }
 
+    /// Frames between two keyframes
+    pub fn with_intra(mut self, intra: Option<u32>) -> Self {
+        self.intra = intra;
+
+        self
+    }
+
+    /// Scheduling priority and resource caps of the camera process
+    pub fn with_limits(mut self, limits: Option<ProcessLimits>) -> Self {
+        self.limits = limits;
+
+        self
+    }
+
+    /// Output timestamped MPEG-TS instead of raw H.264
+    pub fn with_libav(mut self, libav: Option<RpicamLibav>) -> Self {
+        self.libav = libav;
+
+        self
+    }
+
+    /// Does the output carry capture timestamps?
+    pub fn is_timestamped(&self) -> bool {
+        self.libav.is_some()
+    }
+
     //
     // rpicam-vid -t 0 -n --tuning-file /usr/share/libcamera/ipa/rpi/vc4/imx219_noir.json --codec h264 --framerate 30 --width 1920 --height 1080 --inline --listen -o - | psips > live.h264
     //
-    fn build_rpicam_cmd_args(&self) -> Vec<String> {
+    fn build_rpicam_cmd_args(&self) -> Result<Vec<String>> {
         let mut args = Vec::new();
 
         if let Some(camera) = self.camera.as_ref() {
@@ -336,7 +390,53 @@ impl Rpicam {
             args.push(tuning_file.to_string_lossy().to_string());
         }
 
-        if let Some(codec) = &self.codec {
+        if let Some(libav) = self.libav.as_ref() {
+            args.push("--codec".to_string());
+            args.push("libav".to_string());
+
+            args.push("--libav-format".to_string());
+            args.push("mpegts".to_string());
+
+            if let Some(video_codec) = libav.video_codec.as_deref() {
+                args.push("--libav-video-codec".to_string());
+                args.push(video_codec.to_string());
+            }
+
+            // ask for SPS/PPS with every keyframe so taps can join mid-stream
+            args.push("--inline".to_string());
+
+            if let Some(audio) = libav.audio.as_ref() {
+                args.push("--libav-audio".to_string());
+
+                args.push("--audio-source".to_string());
+                args.push(audio.device_type.to_string());
+
+                args.push("--audio-device".to_string());
+                args.push(audio.device_node.clone());
+
+                if let Some(sample_rate) = audio.sample_rate {
+                    args.push("--audio-samplerate".to_string());
+                    args.push(sample_rate.to_string());
+                }
+
+                args.push("--audio-channels".to_string());
+                args.push(audio.channels.unwrap_or(1).to_string());
+
+                args.push("--audio-codec".to_string());
+                args.push(audio.output_format.clone().unwrap_or_default().to_string());
+
+                args.push("--audio-bitrate".to_string());
+                args.push(
+                    bitrate_to_bps(
+                        audio
+                            .output_bitrate
+                            .as_deref()
+                            .unwrap_or(FFMPEG_DEFAULT_AUDIO_OUTPUT_BITRATE),
+                    )?
+                    .to_string(),
+                );
+            }
+        } else if let Some(codec) = &self.codec {
             args.push("--codec".to_string());
             args.push(codec.to_string());
 
@@ -355,6 +455,11 @@ impl Rpicam {
         args.push("--framerate".to_string());
         args.push(fps.to_string());
 
+        if let Some(intra) = self.intra {
+            args.push("--intra".to_string());
+            args.push(intra.to_string());
+        }
+
         args.push("--width".to_string());
         args.push(w.to_string());
 
@@ -385,26 +490,77 @@ impl Rpicam {
         args.push("-o".to_string());
         args.push(output.to_string());
 
-        args
+        Ok(args)
     }
 
     pub fn spawn(&self) -> Result<Child> {
-        let args = self.build_rpicam_cmd_args();
+        let args = self.build_rpicam_cmd_args()?;
 
         debug!(
             target = "rpicam",
             "Spawning {} with arguments: {:?}", RPICAM_BIN, args
         );
 
-        let child = Command::new(RPICAM_BIN)
+        let mut command = Command::new(RPICAM_BIN);
+        command
             .args(&args)
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .kill_on_drop(true)
+            .kill_on_drop(true);
+
+        let limit_errors = self
+            .limits
+            .as_ref()
+            .and_then(|limits| limits.configure(&mut command));
+
+        let child = command
             .spawn()
             .map_err(|e| anyhow!("Failed to spawn child process {}: {}", RPICAM_BIN, e))?;
 
+        if let (Some(limits), Some(pid)) = (self.limits.as_ref(), child.id()) {
+            limits.verify(RPICAM_BIN, pid, limit_errors);
+
+            if let Err(e) = limits.attach(RPICAM_BIN, pid) {
+                warn!(target = "rpicam", "{}", e);
+            }
+        }
+
         Ok(child)
     }
 }
+
+/// Convert ffmpeg style bitrate (`128k`, `1M`, `64000`) to bits per second
+pub fn bitrate_to_bps(bitrate: &str) -> Result<u32> {
+    let trimmed = bitrate.trim();
+
+    let (value, multiplier) = if let Some(value) = trimmed.strip_suffix(['k', 'K']) {
+        (value, 1_000)
+    } else if let Some(value) = trimmed.strip_suffix(['m', 'M']) {
+        (value, 1_000_000)
+    } else {
+        (trimmed, 1)
+    };
+
+    match value.parse::<f32>() {
+        Ok(v) if v.is_finite() && v * multiplier as f32 >= 1.0 => {
+            Ok((v * multiplier as f32) as u32)
+        }
+        _ => Err(anyhow!("Invalid audio bitrate `{}`", bitrate)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitrate_units() {
+        assert_eq!(bitrate_to_bps("128k").unwrap(), 128_000);
+        assert_eq!(bitrate_to_bps(" 1.5M ").unwrap(), 1_500_000);
+        assert_eq!(bitrate_to_bps("64000").unwrap(), 64_000);
+        assert!(bitrate_to_bps("fast").is_err());
+        assert!(bitrate_to_bps("-96k").is_err());
+        assert!(bitrate_to_bps("k").is_err());
+    }
+}
@@ -1,4 +1,6 @@
+pub mod api;
 pub mod middleware;
+pub mod mjpeg;
 pub mod websocket;
 
 pub const DEFAULT_MICRO_UI: &str = include_str!("../docs/index.html");
@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use actix_web::http::header::{CacheControl, CacheDirective, ACCEPT};
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+
+use crate::audio_monitor::AudioLevelWatch;
+use crate::live_stream::clip::{ClipExporter, ClipFormat, CLIP_DEFAULT_POST_DURATION};
+use crate::live_stream::LiveStream;
+use crate::process_control::resources::{ProcessStats, ResourceWatch};
+use crate::server::mjpeg::{MjpegStream, MJPEG_BOUNDARY};
+use crate::snapshot::history::{SnapshotEntry, SnapshotHistory};
+use crate::snapshot::{encode, SnapshotCache, SnapshotFormat, SnapshotOptions};
+use crate::telemetry::events::{Event, EventDispatcher};
+use crate::timelapse::{TimelapseEntry, TimelapseLibrary};
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotQuery {
+    pub format: Option<SnapshotFormat>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub quality: Option<u8>,
+}
+
+/// Live stream status endpoint handler
+pub async fn api_handler_stream_status(live_stream: web::Data<LiveStream>) -> HttpResponse {
+    HttpResponse::Ok().json(live_stream.status().await)
+}
+
+/// Live stream manual reset endpoint handler
+pub async fn api_handler_stream_reset(live_stream: web::Data<LiveStream>) -> HttpResponse {
+    live_stream.reset().await;
+
+    HttpResponse::Ok().json(live_stream.status().await)
+}
+
+/// Metrics endpoint handler
+pub async fn api_handler_metrics(
+    live_stream: web::Data<LiveStream>,
+    resources: Option<web::Data<ResourceWatch>>,
+    audio_levels: Option<web::Data<AudioLevelWatch>>,
+) -> HttpResponse {
+    let processes: Vec<ProcessStats> = resources
+        .and_then(|resources| resources.latest())
+        .into_iter()
+        .chain(live_stream.process_stats().await)
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "stream": live_stream.metrics().await,
+        "processes": processes,
+        "audio": audio_levels.and_then(|levels| levels.latest()),
+        "frame_hub": {
+            "decode_mode": live_stream.frame_hub().config().decode_mode.to_string(),
+        },
+    }))
+}
+
+/// Fresh still picture endpoint handler
+///
+/// Format comes from the `format` query parameter, then the `Accept` header, JPEG otherwise.
+pub async fn api_handler_snapshot(
+    req: HttpRequest,
+    query: web::Query<SnapshotQuery>,
+    live_stream: web::Data<LiveStream>,
+    cache: web::Data<SnapshotCache>,
+) -> HttpResponse {
+    let format = query
+        .format
+        .or_else(|| {
+            req.headers()
+                .get(ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .and_then(SnapshotFormat::from_accept)
+        })
+        .unwrap_or_default();
+
+    let Some(image) = cache.get(&live_stream).await else {
+        return HttpResponse::ServiceUnavailable().json(json!({
+            "error": "No picture available, is the stream running?",
+        }));
+    };
+
+    let options = SnapshotOptions::new(format, query.width, query.height, query.quality);
+
+    match web::block(move || encode(&image, &options))
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r)
+    {
+        Ok(data) => HttpResponse::Ok()
+            .content_type(format.mime())
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .body(data),
+        Err(e) => {
+            error!(target = "web_server", "Unable to encode snapshot: {}", e);
+
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Unable to encode snapshot",
+            }))
+        }
+    }
+}
+
+/// MJPEG multipart stream endpoint handler
+pub async fn api_handler_mjpeg(mjpeg: web::Data<MjpegStream>) -> HttpResponse {
+    let rx = mjpeg.subscribe();
+
+    // dropping the body on disconnect drops the receiver, which lets the encoder stop
+    let body = stream::unfold(rx, |mut rx| async move {
+        loop {
+            rx.changed().await.ok()?;
+
+            let part = rx.borrow_and_update().as_deref().map(MjpegStream::part);
+            if let Some(part) = part {
+                return Some((Ok::<_, actix_web::Error>(part), rx));
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(format!(
+            "multipart/x-mixed-replace; boundary={}",
+            MJPEG_BOUNDARY
+        ))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(body)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClipQuery {
+    /// Seconds of buffered video before now, whole buffer if omitted
+    pub pre: Option<u64>,
+    /// Seconds of video to wait for after now
+    pub post: Option<u64>,
+    pub format: Option<ClipFormat>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotIndexEntry {
+    pub url: String,
+    #[serde(flatten)]
+    pub entry: SnapshotEntry,
+}
+
+/// Snapshot history index endpoint handler
+pub async fn api_handler_snapshot_index(history: web::Data<SnapshotHistory>) -> HttpResponse {
+    let history = history.get_ref().clone();
+
+    match web::block(move || history.list())
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r)
+    {
+        Ok(entries) => HttpResponse::Ok().json(json!({
+            "snapshots": entries
+                .into_iter()
+                .map(|entry| SnapshotIndexEntry {
+                    url: format!("/snapshots/{}", entry.filename),
+                    entry,
+                })
+                .collect::<Vec<_>>(),
+        })),
+        Err(e) => {
+            error!(target = "web_server", "Unable to list snapshots: {}", e);
+
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Unable to list snapshots",
+            }))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelapseIndexEntry {
+    pub url: String,
+    #[serde(flatten)]
+    pub entry: TimelapseEntry,
+}
+
+/// Time-lapse index endpoint handler
+pub async fn api_handler_timelapse_index(library: web::Data<TimelapseLibrary>) -> HttpResponse {
+    let library = library.get_ref().clone();
+
+    match web::block(move || library.list())
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r)
+    {
+        Ok(entries) => HttpResponse::Ok().json(json!({
+            "timelapses": entries
+                .into_iter()
+                .map(|entry| TimelapseIndexEntry {
+                    url: format!("/recordings/{}", entry.filename),
+                    entry,
+                })
+                .collect::<Vec<_>>(),
+        })),
+        Err(e) => {
+            error!(target = "web_server", "Unable to list time-lapses: {}", e);
+
+            HttpResponse::InternalServerError().json(json!({
+                "error": "Unable to list time-lapses",
+            }))
+        }
+    }
+}
+
+/// Pre-event clip export endpoint handler
+///
+/// Returns right away, the outcome is published as a clip event once the post roll is recorded.
+pub async fn api_handler_clip_export(
+    query: web::Query<ClipQuery>,
+    exporter: web::Data<ClipExporter>,
+    events: web::Data<EventDispatcher>,
+) -> HttpResponse {
+    let exporter = exporter.get_ref().clone();
+    let events = events.get_ref().clone();
+    let pre = query.pre.map(Duration::from_secs);
+    let post = query
+        .post
+        .map(Duration::from_secs)
+        .unwrap_or(CLIP_DEFAULT_POST_DURATION);
+    let format = query.format.unwrap_or_default();
+
+    tokio::spawn(async move {
+        match exporter.export(pre, post, format).await {
+            Ok(clip) => {
+                events.send(Event::ClipSaved {
+                    filename: clip.filename,
+                    filesize: clip.filesize,
+                });
+            }
+            Err(e) => {
+                error!(target = "web_server", "Unable to export clip: {}", e);
+
+                events.send(Event::ClipError {
+                    message: e.to_string(),
+                });
+            }
+        }
+    });
+
+    HttpResponse::Accepted().json(json!({
+        "post_secs": post.as_secs(),
+        "format": format,
+    }))
+}
@@ -0,0 +1,167 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+use crate::live_stream::frame_hub::{FrameFormat, FrameHub};
+use crate::snapshot::{encode, SnapshotFormat, SnapshotOptions};
+
+pub const MJPEG_DEFAULT_FPS: f32 = 5.0;
+pub const MJPEG_BOUNDARY: &str = "frame";
+
+/// How often the encoder checks whether anyone is still watching
+const MJPEG_VIEWER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct MjpegOptions {
+    /// Upper bound, the effective rate can't exceed the frame hub rate, see `LiveStream::mjpeg_hub`
+    pub fps: f32,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub quality: Option<u8>,
+}
+
+impl Default for MjpegOptions {
+    fn default() -> Self {
+        Self {
+            fps: MJPEG_DEFAULT_FPS,
+            width: None,
+            height: None,
+            quality: None,
+        }
+    }
+}
+
+impl MjpegOptions {
+    pub fn new(fps: f32, width: Option<u32>, height: Option<u32>, quality: Option<u8>) -> Self {
+        Self {
+            fps,
+            width,
+            height,
+            quality,
+        }
+    }
+}
+
+/// JPEG frames encoded once and shared by all MJPEG viewers.
+///
+/// The encoder only runs while at least one viewer is subscribed.
+#[derive(Clone, Debug)]
+pub struct MjpegStream {
+    frame_hub: FrameHub,
+    options: MjpegOptions,
+    tx: Arc<watch::Sender<Option<Bytes>>>,
+    encoder: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl MjpegStream {
+    pub fn new(frame_hub: FrameHub, options: MjpegOptions) -> Self {
+        Self {
+            frame_hub,
+            options,
+            tx: Arc::new(watch::Sender::new(None)),
+            encoder: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Receive encoded JPEG frames, starting the encoder if needed
+    pub fn subscribe(&self) -> watch::Receiver<Option<Bytes>> {
+        // the encoder decides to quit under the same lock, so we never miss it
+        let mut encoder = self.encoder.lock().unwrap_or_else(|e| e.into_inner());
+        let rx = self.tx.subscribe();
+
+        if encoder.is_none() {
+            *encoder = Some(self.spawn_encoder());
+        }
+
+        rx
+    }
+
+    /// Multipart body part for a single frame
+    pub fn part(jpeg: &[u8]) -> Bytes {
+        let mut part = format!(
+            "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            MJPEG_BOUNDARY,
+            jpeg.len()
+        )
+        .into_bytes();
+        part.extend_from_slice(jpeg);
+        part.extend_from_slice(b"\r\n");
+
+        Bytes::from(part)
+    }
+
+    fn spawn_encoder(&self) -> JoinHandle<()> {
+        let tx = self.tx.clone();
+        let encoder = self.encoder.clone();
+        let mut frames = self.frame_hub.subscribe(FrameFormat::Rgb);
+        let options = SnapshotOptions::new(
+            SnapshotFormat::Jpeg,
+            self.options.width,
+            self.options.height,
+            self.options.quality,
+        );
+        let interval = if self.options.fps > 0.0 {
+            Duration::from_secs_f32(1.0 / self.options.fps)
+        } else {
+            Duration::ZERO
+        };
+
+        debug!(target = "mjpeg", "Starting MJPEG encoder");
+
+        tokio::spawn(async move {
+            let mut last_frame: Option<Instant> = None;
+
+            loop {
+                if tx.receiver_count() == 0 {
+                    let mut encoder = encoder.lock().unwrap_or_else(|e| e.into_inner());
+                    if tx.receiver_count() == 0 {
+                        // don't hand a stale frame to the next viewer
+                        tx.send_replace(None);
+                        *encoder = None;
+                        break;
+                    }
+                }
+
+                match tokio::time::timeout(MJPEG_VIEWER_CHECK_INTERVAL, frames.changed()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(_)) => {
+                        if let Ok(mut encoder) = encoder.lock() {
+                            *encoder = None;
+                        }
+                        break;
+                    }
+                    Err(_) => continue,
+                }
+
+                if last_frame.is_some_and(|t| t.elapsed() < interval) {
+                    continue;
+                }
+
+                let Some(image) = frames
+                    .borrow_and_update()
+                    .as_ref()
+                    .and_then(|f| f.to_rgb_image())
+                else {
+                    continue;
+                };
+
+                last_frame = Some(Instant::now());
+
+                let options = options.clone();
+                match tokio::task::spawn_blocking(move || encode(&image, &options)).await {
+                    Ok(Ok(jpeg)) => {
+                        tx.send_replace(Some(Bytes::from(jpeg)));
+                    }
+                    Ok(Err(e)) => error!(target = "mjpeg", "Unable to encode frame: {}", e),
+                    Err(e) => error!(target = "mjpeg", "Encoder task failed: {}", e),
+                }
+            }
+
+            debug!(target = "mjpeg", "No viewers left, MJPEG encoder stopped");
+        })
+    }
+}
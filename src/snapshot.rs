@@ -0,0 +1,237 @@
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{resize, FilterType};
+use image::{ExtendedColorType, ImageEncoder, RgbImage};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::live_stream::LiveStream;
+
+pub mod history;
+
+pub const SNAPSHOT_DEFAULT_QUALITY: u8 = 85;
+pub const SNAPSHOT_DEFAULT_CACHE_TTL: Duration = Duration::from_secs(2);
+pub const SNAPSHOT_DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+pub const SNAPSHOT_DEFAULT_FORMAT: SnapshotFormat = SnapshotFormat::Webp;
+pub const SNAPSHOT_DEFAULT_DIR_NAME: &str = "snapshots";
+pub const SNAPSHOT_DEFAULT_HISTORY_COUNT: usize = 60;
+pub const SNAPSHOT_DEFAULT_HISTORY_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotFormat {
+    #[default]
+    Jpeg,
+    /// Always lossless, quality is ignored
+    Webp,
+    Png,
+}
+
+impl SnapshotFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Png => "image/png",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Png => "png",
+        }
+    }
+
+    /// Pick the first supported format from an `Accept` header, honouring q-values
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut candidates = accept
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';').map(str::trim);
+                let mime = parts.next()?;
+                let q = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+
+                let format = match mime {
+                    "image/jpeg" | "image/jpg" => Self::Jpeg,
+                    "image/webp" => Self::Webp,
+                    "image/png" => Self::Png,
+                    "image/*" | "*/*" => Self::default(),
+                    _ => return None,
+                };
+
+                (q > 0.0).then_some((format, q))
+            })
+            .collect::<Vec<_>>();
+
+        // stable sort keeps the client order for equal weights
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        candidates.first().map(|(format, _)| *format)
+    }
+}
+
+impl fmt::Display for SnapshotFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "webp" => Ok(Self::Webp),
+            "png" => Ok(Self::Png),
+            _ => Err(anyhow!("Unsupported snapshot format `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotOptions {
+    pub format: SnapshotFormat,
+    /// Fit within these dimensions, aspect ratio preserved, never upscaled
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// JPEG quality `1..=100`
+    pub quality: Option<u8>,
+}
+
+impl SnapshotOptions {
+    pub fn new(
+        format: SnapshotFormat,
+        width: Option<u32>,
+        height: Option<u32>,
+        quality: Option<u8>,
+    ) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            quality,
+        }
+    }
+
+    /// Output dimensions for a picture of the given size
+    pub fn dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        fit_dimensions(width, height, self.width, self.height)
+    }
+}
+
+/// Fit within optional bounds, aspect ratio preserved, never upscaled
+pub fn fit_dimensions(
+    width: u32,
+    height: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> (u32, u32) {
+    let scale_w = max_width.map(|w| w as f32 / width as f32);
+    let scale_h = max_height.map(|h| h as f32 / height as f32);
+    let scale = match (scale_w, scale_h) {
+        (Some(w), Some(h)) => w.min(h),
+        (Some(s), None) | (None, Some(s)) => s,
+        (None, None) => 1.0,
+    };
+
+    if scale >= 1.0 {
+        return (width, height);
+    }
+
+    (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    )
+}
+
+/// Scale down and encode a picture, CPU heavy
+pub fn encode(image: &RgbImage, options: &SnapshotOptions) -> Result<Vec<u8>> {
+    let (width, height) = options.dimensions(image.width(), image.height());
+
+    let resized;
+    let image = if (width, height) != image.dimensions() {
+        resized = resize(image, width, height, FilterType::Triangle);
+        &resized
+    } else {
+        image
+    };
+
+    let mut data = Cursor::new(Vec::new());
+
+    match options.format {
+        SnapshotFormat::Jpeg => JpegEncoder::new_with_quality(
+            &mut data,
+            options
+                .quality
+                .unwrap_or(SNAPSHOT_DEFAULT_QUALITY)
+                .clamp(1, 100),
+        )
+        .write_image(image, width, height, ExtendedColorType::Rgb8)?,
+        SnapshotFormat::Webp => WebPEncoder::new_lossless(&mut data).write_image(
+            image,
+            width,
+            height,
+            ExtendedColorType::Rgb8,
+        )?,
+        SnapshotFormat::Png => {
+            PngEncoder::new(&mut data).write_image(image, width, height, ExtendedColorType::Rgb8)?
+        }
+    }
+
+    Ok(data.into_inner())
+}
+
+type CachedPicture = (Instant, Arc<RgbImage>);
+
+/// Short lived cache in front of the live stream snapshot.
+///
+/// Requests arriving while a decode is in flight wait for it and share the result.
+#[derive(Clone, Debug)]
+pub struct SnapshotCache {
+    ttl: Duration,
+    latest: Arc<Mutex<Option<CachedPicture>>>,
+}
+
+impl Default for SnapshotCache {
+    fn default() -> Self {
+        Self::new(SNAPSHOT_DEFAULT_CACHE_TTL)
+    }
+}
+
+impl SnapshotCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            latest: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Latest picture, decoded anew only once the cached one expires
+    pub async fn get(&self, live_stream: &LiveStream) -> Option<Arc<RgbImage>> {
+        let mut latest = self.latest.lock().await;
+
+        if let Some((captured_at, image)) = latest.as_ref() {
+            if captured_at.elapsed() < self.ttl {
+                return Some(image.clone());
+            }
+        }
+
+        let image = Arc::new(live_stream.snapshot().await?);
+        *latest = Some((Instant::now(), image.clone()));
+
+        Some(image)
+    }
+}
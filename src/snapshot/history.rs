@@ -0,0 +1,177 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+
+use crate::snapshot::SnapshotFormat;
+
+pub const SNAPSHOT_HISTORY_PREFIX: &str = "snapshot-";
+/// Safe sleep alert evidence, kept apart from the periodic snapshots
+pub const SNAPSHOT_ALERT_PREFIX: &str = "alert-";
+
+/// Past snapshot on disk
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotEntry {
+    pub filename: String,
+    pub format: SnapshotFormat,
+    pub filesize: u64,
+    /// Capture time, milliseconds since the unix epoch
+    pub timestamp: i64,
+    pub created_at: String,
+}
+
+impl SnapshotEntry {
+    fn from_path(path: &Path, prefix: &str) -> Option<Self> {
+        let filename = path.file_name()?.to_str()?.to_string();
+        let (stem, extension) = filename.strip_prefix(prefix)?.split_once('.')?;
+
+        let timestamp = stem.parse::<i64>().ok()?;
+        let format = extension.parse::<SnapshotFormat>().ok()?;
+        let filesize = fs::metadata(path).ok()?.len();
+        let created_at = Utc.timestamp_millis_opt(timestamp).single()?.to_rfc3339();
+
+        Some(Self {
+            filename,
+            format,
+            filesize,
+            timestamp,
+            created_at,
+        })
+    }
+}
+
+/// Timestamped snapshots in a directory, pruned by count and age.
+///
+/// Only files with the history prefix are listed and pruned, so histories with different
+/// prefixes can share a directory. All calls do blocking file system IO.
+#[derive(Clone, Debug)]
+pub struct SnapshotHistory {
+    dir: PathBuf,
+    prefix: &'static str,
+    /// `None` keeps everything
+    max_count: Option<usize>,
+    max_age: Option<Duration>,
+}
+
+impl SnapshotHistory {
+    pub fn new(dir: PathBuf, max_count: Option<usize>, max_age: Option<Duration>) -> Self {
+        Self {
+            dir,
+            prefix: SNAPSHOT_HISTORY_PREFIX,
+            max_count,
+            max_age,
+        }
+    }
+
+    /// Filename prefix, `snapshot-` by default
+    pub fn with_prefix(mut self, prefix: &'static str) -> Self {
+        self.prefix = prefix;
+
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store a new encoded snapshot
+    pub fn save(&self, data: &[u8], format: SnapshotFormat) -> Result<SnapshotEntry> {
+        fs::create_dir_all(&self.dir)?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = self.dir.join(format!(
+            "{}{}.{}",
+            self.prefix,
+            timestamp,
+            format.extension()
+        ));
+
+        write_atomic(&path, data)?;
+
+        SnapshotEntry::from_path(&path, self.prefix)
+            .ok_or_else(|| anyhow::anyhow!("Unable to read back `{}`", path.display()))
+    }
+
+    /// All snapshots, newest first
+    pub fn list(&self) -> Result<Vec<SnapshotEntry>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| SnapshotEntry::from_path(&entry.path(), self.prefix))
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|entry| Reverse(entry.timestamp));
+
+        Ok(entries)
+    }
+
+    /// Remove snapshots beyond the retention limits, returns how many were removed
+    pub fn prune(&self) -> Result<usize> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let mut removed = 0;
+
+        for (i, entry) in self.list()?.iter().enumerate() {
+            let too_many = self.max_count.is_some_and(|max| i >= max);
+            let too_old = self
+                .max_age
+                .is_some_and(|age| now - entry.timestamp > age.as_millis() as i64);
+
+            if too_many || too_old {
+                fs::remove_file(self.dir.join(&entry.filename))?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Write through a temporary file so readers never see a partial image
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_other_prefixes() {
+        let dir = std::env::temp_dir().join(format!("babypi-history-{}", std::process::id()));
+        let history = SnapshotHistory::new(dir.clone(), Some(1), None);
+        let alerts =
+            SnapshotHistory::new(dir.clone(), None, None).with_prefix(SNAPSHOT_ALERT_PREFIX);
+
+        let alert = alerts.save(b"alert", SnapshotFormat::Jpeg).unwrap();
+        for _ in 0..3 {
+            history.save(b"snapshot", SnapshotFormat::Jpeg).unwrap();
+            std::thread::sleep(Duration::from_millis(2));
+        }
+
+        assert_eq!(history.prune().unwrap(), 2);
+        assert_eq!(history.list().unwrap().len(), 1);
+        assert_eq!(
+            alerts
+                .list()
+                .unwrap()
+                .iter()
+                .map(|entry| entry.filename.clone())
+                .collect::<Vec<_>>(),
+            vec![alert.filename]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,636 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use futures_util::future::{join_all, select_all};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use crate::backoff::ExponentialBackoff;
+use crate::process_control::resources::{ProcessStats, PROCESS_DEFAULT_SAMPLE_INTERVAL};
+use crate::process_control::{ProcessControl, ProcessExit, PROCESS_DEFAULT_SHUTDOWN_TIMEOUT};
+use crate::telemetry::events::{Event, EventDispatcher, Service, Status};
+
+pub const SUPERVISOR_DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+pub const SUPERVISOR_DEFAULT_RESTART_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// When to bring a group back after it went down on its own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    Never,
+    /// Unless every process exited cleanly
+    #[default]
+    OnFailure,
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn should_restart(&self, failed: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// At most `max_restarts` within any `window`, on top of the backoff retry budget
+#[derive(Clone, Copy, Debug)]
+pub struct RestartLimit {
+    pub max_restarts: u32,
+    pub window: Duration,
+}
+
+impl RestartLimit {
+    pub fn new(max_restarts: u32, window: Duration) -> Self {
+        Self {
+            max_restarts,
+            window,
+        }
+    }
+}
+
+/// Processes started, checked and stopped as a unit
+///
+/// When one of them exits the whole group goes down and restarts together, so processes
+/// depending on each other, like a camera piped into an encoder, stay in step.
+pub trait SupervisedGroup: Send + Sync + 'static {
+    /// Spawn and connect the processes
+    fn start(&mut self) -> impl Future<Output = Result<Vec<ProcessControl>>> + Send;
+
+    /// Periodic health check while running, returns why the group should restart
+    fn check(&mut self) -> Option<String> {
+        None
+    }
+
+    /// Release whatever `start` set up besides the processes, they are already reaped
+    fn stop(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+/// Supervisor view for the API
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SupervisorStatus {
+    pub running: bool,
+    pub status: Status,
+    /// Consecutive failed starts, reset once healthy
+    pub retry_count: u32,
+    pub uptime_secs: Option<u64>,
+    /// Most recent unexpected process exit, kept across restarts
+    pub last_exit: Option<SupervisorExit>,
+}
+
+/// Process exit that brought the group down
+#[derive(Clone, Debug, Serialize)]
+pub struct SupervisorExit {
+    pub process: String,
+    #[serde(flatten)]
+    pub exit: ProcessExit,
+}
+
+#[derive(Debug, Default)]
+struct SupervisorState {
+    running: bool,
+    status: Status,
+    retry_count: u32,
+    started_at: Option<Instant>,
+    last_exit: Option<SupervisorExit>,
+    resources: Vec<ProcessStats>,
+}
+
+#[derive(Debug)]
+enum SupervisorCommand {
+    Stop(oneshot::Sender<()>),
+    Reset,
+}
+
+/// Why a running group went down
+enum Downfall {
+    Exited {
+        id: String,
+        exit: Option<ProcessExit>,
+    },
+    Unhealthy(String),
+    Command(Option<SupervisorCommand>),
+}
+
+#[derive(Debug)]
+struct SupervisorTask {
+    commands: mpsc::Sender<SupervisorCommand>,
+    handle: JoinHandle<()>,
+}
+
+/// Keeps a group of processes running according to a restart policy, reporting status
+/// changes as service events
+#[derive(Debug)]
+pub struct Supervisor<G> {
+    service: Service,
+    group: Arc<RwLock<G>>,
+    events: EventDispatcher,
+    policy: RestartPolicy,
+    backoff: ExponentialBackoff,
+    limit: Option<RestartLimit>,
+    check_interval: Duration,
+    shutdown_timeout: Duration,
+    sample_interval: Option<Duration>,
+    stats_events: bool,
+    memory_limit: Option<u64>,
+    state: Arc<RwLock<SupervisorState>>,
+    task: Arc<Mutex<Option<SupervisorTask>>>,
+}
+
+impl<G> Clone for Supervisor<G> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            group: self.group.clone(),
+            events: self.events.clone(),
+            policy: self.policy,
+            backoff: self.backoff.clone(),
+            limit: self.limit,
+            check_interval: self.check_interval,
+            shutdown_timeout: self.shutdown_timeout,
+            sample_interval: self.sample_interval,
+            stats_events: self.stats_events,
+            memory_limit: self.memory_limit,
+            state: self.state.clone(),
+            task: self.task.clone(),
+        }
+    }
+}
+
+impl<G: SupervisedGroup> Supervisor<G> {
+    pub fn new(service: Service, group: G, events: EventDispatcher) -> Self {
+        Self {
+            service,
+            group: Arc::new(RwLock::new(group)),
+            events,
+            policy: RestartPolicy::default(),
+            backoff: ExponentialBackoff::default(),
+            limit: None,
+            check_interval: SUPERVISOR_DEFAULT_CHECK_INTERVAL,
+            shutdown_timeout: PROCESS_DEFAULT_SHUTDOWN_TIMEOUT,
+            sample_interval: Some(PROCESS_DEFAULT_SAMPLE_INTERVAL),
+            stats_events: false,
+            memory_limit: None,
+            state: Arc::new(RwLock::new(SupervisorState::default())),
+            task: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+
+        self
+    }
+
+    /// Restart delays and consecutive failures budget
+    pub fn with_backoff(mut self, backoff: ExponentialBackoff) -> Self {
+        self.backoff = backoff;
+
+        self
+    }
+
+    pub fn with_restart_limit(mut self, limit: Option<RestartLimit>) -> Self {
+        self.limit = limit;
+
+        self
+    }
+
+    /// Grace period for each signal when stopping processes
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+
+        self
+    }
+
+    /// Sample processes resource usage, `None` disables
+    pub fn with_sample_interval(mut self, interval: Option<Duration>) -> Self {
+        self.sample_interval = interval;
+
+        self
+    }
+
+    /// Publish every sample as `Event::ProcessStats`, off by default to keep the bus quiet
+    pub fn with_stats_events(mut self, stats_events: bool) -> Self {
+        self.stats_events = stats_events;
+
+        self
+    }
+
+    /// Restart the group when one of its processes uses more memory, in bytes
+    pub fn with_memory_limit(mut self, limit: Option<u64>) -> Self {
+        self.memory_limit = limit;
+
+        self
+    }
+
+    /// Latest resource usage of the running processes
+    pub async fn resources(&self) -> Vec<ProcessStats> {
+        self.state.read().await.resources.clone()
+    }
+
+    /// The supervised group, locked for writing while starting and stopping
+    pub fn group(&self) -> Arc<RwLock<G>> {
+        self.group.clone()
+    }
+
+    pub async fn status(&self) -> SupervisorStatus {
+        let state = self.state.read().await;
+
+        SupervisorStatus {
+            running: state.running,
+            status: state.status.clone(),
+            retry_count: state.retry_count,
+            uptime_secs: state.started_at.map(|t| t.elapsed().as_secs()),
+            last_exit: state.last_exit.clone(),
+        }
+    }
+
+    pub async fn is_running(&self) -> bool {
+        self.state.read().await.running
+    }
+
+    /// Start supervising, no-op when already supervising
+    pub async fn start(&self) {
+        let mut task = self.task.lock().await;
+        if task.as_ref().is_some_and(|t| !t.handle.is_finished()) {
+            return;
+        }
+
+        let (commands, rx) = mpsc::channel(4);
+        let handle = tokio::spawn(self.clone().run(rx));
+
+        *task = Some(SupervisorTask { commands, handle });
+    }
+
+    /// Stop the group and wait until its processes are reaped
+    pub async fn stop(&self) {
+        let Some(task) = self.task.lock().await.take() else {
+            return;
+        };
+
+        let (ack, done) = oneshot::channel();
+        if task
+            .commands
+            .send(SupervisorCommand::Stop(ack))
+            .await
+            .is_ok()
+        {
+            let _ = done.await;
+        }
+
+        let _ = task.handle.await;
+    }
+
+    /// Restart the group right away with a fresh retry budget
+    pub async fn reset(&self) {
+        let task = self.task.lock().await;
+
+        match task.as_ref().filter(|t| !t.handle.is_finished()) {
+            Some(task) => {
+                let _ = task.commands.send(SupervisorCommand::Reset).await;
+            }
+            None => {
+                drop(task);
+                self.start().await;
+            }
+        }
+    }
+
+    async fn transition(&self, status: Status) {
+        self.state.write().await.status = status.clone();
+
+        self.events.send(Event::ServiceStatus {
+            service: self.service.clone(),
+            status,
+        });
+    }
+
+    async fn run(self, mut commands: mpsc::Receiver<SupervisorCommand>) {
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+        let mut attempt = 0u32;
+
+        loop {
+            if attempt > 0 {
+                let delay = self.backoff.delay(attempt);
+
+                info!(
+                    target = "supervisor",
+                    "Restarting {:?} in {} ms, attempt {}",
+                    self.service,
+                    delay.as_millis(),
+                    attempt
+                );
+
+                self.transition(Status::Restarting {
+                    attempt,
+                    delay_ms: delay.as_millis() as u64,
+                })
+                .await;
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    command = commands.recv() => match command {
+                        Some(SupervisorCommand::Reset) => {
+                            attempt = 0;
+                            restarts.clear();
+                        }
+                        command => return self.finish(command).await,
+                    }
+                }
+
+                restarts.push_back(Instant::now());
+            }
+
+            self.state.write().await.retry_count = attempt;
+            self.transition(Status::Starting).await;
+
+            let started = self.group.write().await.start().await;
+            let mut processes = match started {
+                Ok(processes) => processes,
+                Err(e) => {
+                    error!(
+                        target = "supervisor",
+                        "Error while starting {:?}: {}", self.service, e
+                    );
+
+                    self.group.write().await.stop().await;
+
+                    match self
+                        .next_attempt(attempt, true, &mut restarts, e.to_string(), &mut commands)
+                        .await
+                    {
+                        Some(next) => {
+                            attempt = next;
+                            continue;
+                        }
+                        None => return,
+                    }
+                }
+            };
+
+            if let Some(interval) = self.sample_interval {
+                for process in processes.iter_mut() {
+                    let events = self.stats_events.then(|| self.events.clone());
+                    process.watch_resources(interval, events);
+                }
+            }
+
+            {
+                let mut state = self.state.write().await;
+                state.running = true;
+                state.started_at = Some(Instant::now());
+            }
+            self.transition(Status::Running).await;
+
+            let downfall = self
+                .watch(&mut processes, &mut attempt, &mut commands)
+                .await;
+
+            self.shutdown(processes).await;
+            self.group.write().await.stop().await;
+
+            {
+                let mut state = self.state.write().await;
+                state.running = false;
+                state.started_at = None;
+                state.resources.clear();
+            }
+
+            let (failed, reason) = match downfall {
+                Downfall::Command(Some(SupervisorCommand::Reset)) => {
+                    info!(target = "supervisor", "Manual {:?} reset", self.service);
+
+                    attempt = 0;
+                    restarts.clear();
+                    continue;
+                }
+                Downfall::Command(command) => return self.finish(command).await,
+                Downfall::Exited { id, exit } => {
+                    let failed = exit.as_ref().is_none_or(|exit| !exit.success());
+                    let reason = match exit {
+                        Some(exit) => {
+                            let reason = format!("Process `{}` exit: {}", id, exit);
+
+                            self.state.write().await.last_exit =
+                                Some(SupervisorExit { process: id, exit });
+
+                            reason
+                        }
+                        None => format!("Process `{}` watch error", id),
+                    };
+
+                    warn!(target = "supervisor", "{}", reason);
+
+                    (failed, reason)
+                }
+                Downfall::Unhealthy(reason) => {
+                    warn!(
+                        target = "supervisor",
+                        "{:?} unhealthy: {}", self.service, reason
+                    );
+
+                    (true, reason)
+                }
+            };
+
+            match self
+                .next_attempt(attempt, failed, &mut restarts, reason, &mut commands)
+                .await
+            {
+                Some(next) => attempt = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Wait for a process to exit, a failed health check or a command
+    async fn watch(
+        &self,
+        processes: &mut [ProcessControl],
+        attempt: &mut u32,
+        commands: &mut mpsc::Receiver<SupervisorCommand>,
+    ) -> Downfall {
+        let mut ticker = tokio::time::interval(self.check_interval);
+
+        loop {
+            tokio::select! {
+                (id, exit) = wait_any(processes) => return Downfall::Exited { id, exit },
+                command = commands.recv() => return Downfall::Command(command),
+                _ = ticker.tick() => {
+                    if let Some(reason) = self.group.write().await.check() {
+                        return Downfall::Unhealthy(reason);
+                    }
+
+                    let resources: Vec<ProcessStats> =
+                        processes.iter().filter_map(|p| p.resources()).collect();
+
+                    if let Some(reason) = self
+                        .memory_limit
+                        .and_then(|limit| over_memory_limit(&resources, limit))
+                    {
+                        return Downfall::Unhealthy(reason);
+                    }
+
+                    let mut state = self.state.write().await;
+                    state.resources = resources;
+                    if *attempt > 0
+                        && state
+                            .started_at
+                            .is_some_and(|t| t.elapsed() >= self.backoff.healthy_period)
+                    {
+                        info!(
+                            target = "supervisor",
+                            "{:?} healthy for {}s, resetting retry budget",
+                            self.service,
+                            self.backoff.healthy_period.as_secs()
+                        );
+
+                        *attempt = 0;
+                        state.retry_count = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Next restart attempt according to the policy and limits, otherwise park until a
+    /// reset, `None` once stopped
+    async fn next_attempt(
+        &self,
+        attempt: u32,
+        failed: bool,
+        restarts: &mut VecDeque<Instant>,
+        reason: String,
+        commands: &mut mpsc::Receiver<SupervisorCommand>,
+    ) -> Option<u32> {
+        let attempt = attempt + 1;
+        self.state.write().await.retry_count = attempt;
+
+        if let Some(limit) = self.limit {
+            while restarts.front().is_some_and(|t| t.elapsed() > limit.window) {
+                restarts.pop_front();
+            }
+        }
+
+        let status = if !self.policy.should_restart(failed) {
+            info!(
+                target = "supervisor",
+                "{:?} not restarted, policy {:?}", self.service, self.policy
+            );
+
+            if failed {
+                Status::Error(reason)
+            } else {
+                Status::Stopped
+            }
+        } else if self.backoff.is_exhausted(attempt) {
+            error!(
+                target = "supervisor",
+                "Too many retries: {}, waiting for manual reset", attempt
+            );
+
+            Status::Error(format!("Too many retries: {}", attempt))
+        } else if let Some(limit) = self
+            .limit
+            .filter(|limit| restarts.len() >= limit.max_restarts as usize)
+        {
+            error!(
+                target = "supervisor",
+                "{} restarts within {}s, waiting for manual reset",
+                restarts.len(),
+                limit.window.as_secs()
+            );
+
+            Status::Error(format!(
+                "Too many restarts: {} within {}s",
+                restarts.len(),
+                limit.window.as_secs()
+            ))
+        } else {
+            self.transition(Status::Error(reason)).await;
+
+            return Some(attempt);
+        };
+
+        self.transition(status).await;
+
+        match commands.recv().await {
+            Some(SupervisorCommand::Reset) => {
+                info!(target = "supervisor", "Manual {:?} reset", self.service);
+
+                restarts.clear();
+                Some(0)
+            }
+            command => {
+                self.finish(command).await;
+                None
+            }
+        }
+    }
+
+    async fn finish(&self, command: Option<SupervisorCommand>) {
+        self.transition(Status::Stopped).await;
+
+        info!(
+            target = "supervisor",
+            "Stopped supervising {:?}", self.service
+        );
+
+        if let Some(SupervisorCommand::Stop(ack)) = command {
+            let _ = ack.send(());
+        }
+    }
+
+    /// Shut all processes down at once and wait for them to be reaped
+    async fn shutdown(&self, processes: Vec<ProcessControl>) {
+        join_all(processes.into_iter().map(|mut process| async move {
+            let outcome = process.shutdown(self.shutdown_timeout).await;
+
+            if outcome.escalated() {
+                warn!(
+                    target = "supervisor",
+                    "Process `{}` {}",
+                    process.id(),
+                    outcome
+                );
+            } else {
+                debug!(
+                    target = "supervisor",
+                    "Process `{}` {}",
+                    process.id(),
+                    outcome
+                );
+            }
+        }))
+        .await;
+    }
+}
+
+/// Reason to restart when a process resident memory exceeds `limit` bytes
+fn over_memory_limit(resources: &[ProcessStats], limit: u64) -> Option<String> {
+    let stats = resources.iter().find(|stats| stats.rss_bytes > limit)?;
+
+    Some(format!(
+        "Process `{}` uses {} MiB, over the {} MiB limit",
+        stats.process,
+        stats.rss_bytes / (1024 * 1024),
+        limit / (1024 * 1024)
+    ))
+}
+
+/// First process to exit, never resolves without processes
+async fn wait_any(processes: &mut [ProcessControl]) -> (String, Option<ProcessExit>) {
+    if processes.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let (exit, index, _) = select_all(processes.iter_mut().map(|p| Box::pin(p.wait()))).await;
+
+    (processes[index].id().to_string(), exit)
+}
@@ -1,4 +1,10 @@
 #![allow(dead_code)]
+use crate::analysis::motion::MotionBox;
+use crate::analysis::pose::{Keypoint, SleepingPosition};
+use crate::analysis::safe_sleep::UnsafeSleepReason;
+use crate::audio_monitor::activity::AudioActivity;
+use crate::audio_monitor::level::AudioLevel;
+use crate::process_control::resources::ProcessStats;
 use crate::serde_stuff::float_precision_two;
 use image::{ImageBuffer, Rgb};
 use serde::{Deserialize, Serialize};
@@ -17,14 +23,29 @@ pub enum Service {
     AudioMonitor,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Status {
+    Starting,
     Running,
+    #[default]
+    Stopped,
+    Restarting {
+        attempt: u32,
+        delay_ms: u64,
+    },
     Disabled,
     Error(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Event {
@@ -43,15 +64,94 @@ pub enum Event {
     },
 
     SnapshotUpdated {
+        filename: String,
         filesize: u64,
         width: u32,
         height: u32,
     },
 
-    AudioMonitor {
+    SnapshotError {
+        message: String,
+    },
+
+    ClipSaved {
+        filename: String,
+        filesize: u64,
+    },
+
+    ClipError {
+        message: String,
+    },
+
+    TimelapseSaved {
+        filename: String,
+        filesize: u64,
+        frames: u64,
+    },
+
+    TimelapseError {
+        message: String,
+    },
+
+    Motion {
+        zone: String,
         #[serde(with = "float_precision_two")]
-        rms: f32,
+        score: f32,
+        bbox: MotionBox,
+    },
+
+    MotionSettled {
+        zone: String,
     },
+
+    Presence {
+        present: bool,
+        #[serde(with = "float_precision_two")]
+        confidence: f32,
+        bbox: Option<MotionBox>,
+    },
+
+    Pose {
+        keypoints: Vec<Keypoint>,
+        sleeping_position: SleepingPosition,
+    },
+
+    SleepAlert {
+        reason: UnsafeSleepReason,
+        severity: Severity,
+        duration_secs: u64,
+        /// Snapshot history filename
+        snapshot: Option<String>,
+    },
+
+    SleepAlertCleared {
+        reason: UnsafeSleepReason,
+    },
+
+    AudioActivityStarted(AudioLevel),
+
+    AudioActivityOngoing(AudioActivity),
+
+    AudioActivityEnded(AudioActivity),
+
+    CryStarted {
+        #[serde(with = "float_precision_two")]
+        confidence: f32,
+    },
+
+    CryEnded {
+        #[serde(with = "float_precision_two")]
+        confidence: f32,
+        /// Seconds from the first to the last cry burst
+        #[serde(with = "float_precision_two")]
+        duration: f32,
+    },
+
+    AvOffset {
+        offset_ms: i64,
+    },
+
+    ProcessStats(ProcessStats),
 }
 
 #[derive(Debug)]
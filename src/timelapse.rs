@@ -0,0 +1,468 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveTime, TimeZone};
+use image::imageops::{resize, FilterType};
+use image::RgbImage;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+use tracing::{debug, error, info};
+
+use crate::ffmpeg::FFMPEG_BIN;
+use crate::live_stream::LiveStream;
+use crate::snapshot::fit_dimensions;
+use crate::telemetry::events::{Event, EventDispatcher};
+
+/// ~30 s of video for an 11 hour night at the default frame rate
+pub const TIMELAPSE_DEFAULT_INTERVAL: Duration = Duration::from_secs(40);
+pub const TIMELAPSE_DEFAULT_FPS: u32 = 30;
+pub const TIMELAPSE_DEFAULT_START: &str = "20:00";
+pub const TIMELAPSE_DEFAULT_END: &str = "07:00";
+pub const TIMELAPSE_DEFAULT_MAX_WIDTH: u32 = 1280;
+pub const TIMELAPSE_DEFAULT_RETENTION: usize = 14;
+pub const RECORDING_DEFAULT_DIR_NAME: &str = "recordings";
+/// Time for ffmpeg to write out the current recording when stopping
+pub const TIMELAPSE_FINISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+const TIMELAPSE_PREFIX: &str = "timelapse-";
+const TIMELAPSE_TIME_FORMAT: &str = "%H:%M";
+const TIMELAPSE_DATE_FORMAT: &str = "%Y-%m-%d";
+const TIMELAPSE_DATE_LEN: usize = "YYYY-MM-DD".len();
+
+/// Daily recording window in local time, may span midnight
+#[derive(Clone, Copy, Debug)]
+pub struct TimelapseWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimelapseWindow {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self { start, end }
+    }
+
+    /// Parse `HH:MM` bounds
+    pub fn parse(start: &str, end: &str) -> Result<Self> {
+        let parse = |value: &str| {
+            NaiveTime::parse_from_str(value, TIMELAPSE_TIME_FORMAT)
+                .map_err(|_| anyhow!("Invalid time `{}`, expected HH:MM", value))
+        };
+
+        Ok(Self::new(parse(start)?, parse(end)?))
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Date the window containing `at` started on
+    pub fn night_of<Tz: TimeZone>(&self, at: &DateTime<Tz>) -> NaiveDate {
+        let date = at.date_naive();
+
+        if self.start > self.end && at.time() < self.end {
+            date.checked_sub_days(Days::new(1)).unwrap_or(date)
+        } else {
+            date
+        }
+    }
+}
+
+/// Finished time-lapse on disk
+#[derive(Clone, Debug, Serialize)]
+pub struct TimelapseEntry {
+    pub filename: String,
+    /// Night the recording started, `YYYY-MM-DD`
+    pub date: String,
+    pub filesize: u64,
+    /// Recordings interrupted during a night continue in numbered segments
+    #[serde(skip)]
+    segment: u32,
+}
+
+impl TimelapseEntry {
+    fn from_path(path: &Path) -> Option<Self> {
+        let filename = path.file_name()?.to_str()?.to_string();
+        let name = filename
+            .strip_prefix(TIMELAPSE_PREFIX)?
+            .strip_suffix(".mp4")?;
+        let (date, segment) = match name.split_at_checked(TIMELAPSE_DATE_LEN)? {
+            (date, "") => (date, 1),
+            (date, segment) => (date, segment.strip_prefix('-')?.parse().ok()?),
+        };
+
+        NaiveDate::parse_from_str(date, TIMELAPSE_DATE_FORMAT).ok()?;
+
+        Some(Self {
+            date: date.to_string(),
+            filesize: fs::metadata(path).ok()?.len(),
+            filename,
+            segment,
+        })
+    }
+}
+
+/// Videos per night in the recordings directory, pruned by count.
+///
+/// All calls do blocking file system IO.
+#[derive(Clone, Debug)]
+pub struct TimelapseLibrary {
+    dir: PathBuf,
+    /// `None` keeps everything
+    max_count: Option<usize>,
+}
+
+impl TimelapseLibrary {
+    pub fn new(dir: PathBuf, max_count: Option<usize>) -> Self {
+        Self { dir, max_count }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// First free segment of a night, earlier recordings of that night are left alone
+    pub fn path(&self, night: NaiveDate) -> PathBuf {
+        let date = night.format(TIMELAPSE_DATE_FORMAT);
+
+        (1..)
+            .map(|segment| {
+                self.dir.join(match segment {
+                    1 => format!("{}{}.mp4", TIMELAPSE_PREFIX, date),
+                    _ => format!("{}{}-{}.mp4", TIMELAPSE_PREFIX, date, segment),
+                })
+            })
+            .find(|path| !path.exists() && !path.with_extension("part").exists())
+            .unwrap_or_default()
+    }
+
+    /// All time-lapses, newest first
+    pub fn list(&self) -> Result<Vec<TimelapseEntry>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| TimelapseEntry::from_path(&entry.path()))
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| (&b.date, b.segment).cmp(&(&a.date, a.segment)));
+
+        Ok(entries)
+    }
+
+    /// Remove time-lapses beyond the retention limit, returns how many were removed
+    pub fn prune(&self) -> Result<usize> {
+        let Some(max_count) = self.max_count else {
+            return Ok(0);
+        };
+
+        let mut removed = 0;
+        for entry in self.list()?.iter().skip(max_count) {
+            fs::remove_file(self.dir.join(&entry.filename))?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Raw RGB frames piped into an ffmpeg H.264 encoder
+#[derive(Debug)]
+pub struct TimelapseEncoder {
+    child: Child,
+    stdin: ChildStdin,
+    path: PathBuf,
+    tmp: PathBuf,
+    width: u32,
+    height: u32,
+    frames: u64,
+}
+
+impl TimelapseEncoder {
+    /// Start encoding into `path`, the file only shows up once finished
+    pub fn new(path: PathBuf, width: u32, height: u32, fps: u32) -> Result<Self> {
+        // yuv420p needs even dimensions
+        let (width, height) = ((width & !1).max(2), (height & !1).max(2));
+        let tmp = path.with_extension("part");
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let args = [
+            "-hide_banner",
+            "-loglevel",
+            "error",
+            "-y",
+            "-f",
+            "rawvideo",
+            "-pix_fmt",
+            "rgb24",
+            "-s",
+            &format!("{}x{}", width, height),
+            "-framerate",
+            &fps.to_string(),
+            "-i",
+            "pipe:",
+            "-c:v",
+            "libx264",
+            "-preset",
+            "veryfast",
+            "-pix_fmt",
+            "yuv420p",
+            "-movflags",
+            "+faststart",
+            "-f",
+            "mp4",
+            &tmp.to_string_lossy(),
+        ];
+
+        let mut child = Command::new(FFMPEG_BIN)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Failed to spawn child process {}: {}", FFMPEG_BIN, e))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("Failed to open child process input for `{}`", FFMPEG_BIN))?;
+
+        Ok(Self {
+            child,
+            stdin,
+            path,
+            tmp,
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Append a frame, it must match the encoder dimensions
+    pub async fn push(&mut self, image: &RgbImage) -> Result<()> {
+        if image.dimensions() != (self.width, self.height) {
+            return Err(anyhow!(
+                "Frame is {}x{}, encoder expects {}x{}",
+                image.width(),
+                image.height(),
+                self.width,
+                self.height
+            ));
+        }
+
+        self.stdin.write_all(image.as_raw()).await?;
+        self.frames += 1;
+
+        Ok(())
+    }
+
+    /// Close the input and wait for the video to be written
+    pub async fn finish(self) -> Result<TimelapseEntry> {
+        let Self {
+            child,
+            stdin,
+            path,
+            tmp,
+            frames,
+            ..
+        } = self;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() || frames == 0 {
+            let _ = tokio::fs::remove_file(&tmp).await;
+
+            return Err(anyhow!(
+                "{} failed to encode time-lapse: {}",
+                FFMPEG_BIN,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        tokio::fs::rename(&tmp, &path).await?;
+
+        TimelapseEntry::from_path(&path)
+            .ok_or_else(|| anyhow!("Unable to read back `{}`", path.display()))
+    }
+}
+
+/// Samples the live stream during the nightly window and encodes one video per night
+#[derive(Debug)]
+pub struct TimelapseRecorder {
+    live_stream: LiveStream,
+    library: TimelapseLibrary,
+    window: TimelapseWindow,
+    interval: Duration,
+    fps: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+}
+
+impl TimelapseRecorder {
+    pub fn new(
+        live_stream: LiveStream,
+        library: TimelapseLibrary,
+        window: TimelapseWindow,
+        interval: Duration,
+        fps: u32,
+    ) -> Self {
+        Self {
+            live_stream,
+            library,
+            window,
+            interval,
+            fps,
+            max_width: Some(TIMELAPSE_DEFAULT_MAX_WIDTH),
+            max_height: None,
+        }
+    }
+
+    /// Fit frames within these dimensions
+    pub fn with_max_dimensions(mut self, width: Option<u32>, height: Option<u32>) -> Self {
+        self.max_width = width;
+        self.max_height = height;
+        self
+    }
+
+    /// Record until `stop` fires or is dropped, the current recording is finished either way
+    pub async fn run(self, events: EventDispatcher, mut stop: oneshot::Receiver<()>) {
+        let mut timer = tokio::time::interval(self.interval);
+        let mut current: Option<(NaiveDate, TimelapseEncoder)> = None;
+
+        loop {
+            tokio::select! {
+                _ = timer.tick() => {}
+                _ = &mut stop => break,
+            }
+
+            let now = Local::now();
+            let night = self
+                .window
+                .contains(now.time())
+                .then(|| self.window.night_of(&now));
+
+            if current.as_ref().is_some_and(|(n, _)| Some(*n) != night) {
+                if let Some((_, encoder)) = current.take() {
+                    self.finish(encoder, &events).await;
+                }
+            }
+
+            let Some(night) = night else {
+                continue;
+            };
+
+            let Some(image) = self.live_stream.snapshot().await else {
+                debug!(target = "timelapse", "No frame available, skipping");
+                continue;
+            };
+
+            let encoder = match current.as_mut() {
+                Some((_, encoder)) => encoder,
+                None => {
+                    let (width, height) = fit_dimensions(
+                        image.width(),
+                        image.height(),
+                        self.max_width,
+                        self.max_height,
+                    );
+
+                    match TimelapseEncoder::new(self.library.path(night), width, height, self.fps) {
+                        Ok(encoder) => {
+                            info!(target = "timelapse", "Recording time-lapse for {}", night);
+                            &mut current.insert((night, encoder)).1
+                        }
+                        Err(e) => {
+                            self.fail(e, &events);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let (width, height) = encoder.dimensions();
+            let frame = if image.dimensions() == (width, height) {
+                image
+            } else {
+                match tokio::task::spawn_blocking(move || {
+                    resize(&image, width, height, FilterType::Triangle)
+                })
+                .await
+                {
+                    Ok(frame) => frame,
+                    Err(_) => continue,
+                }
+            };
+
+            if let Err(e) = encoder.push(&frame).await {
+                // keep what was written so far
+                if let Some((_, encoder)) = current.take() {
+                    self.finish(encoder, &events).await;
+                }
+                self.fail(e, &events);
+            }
+        }
+
+        if let Some((_, encoder)) = current.take() {
+            self.finish(encoder, &events).await;
+        }
+    }
+
+    async fn finish(&self, encoder: TimelapseEncoder, events: &EventDispatcher) {
+        let frames = encoder.frames();
+
+        match encoder.finish().await {
+            Ok(entry) => {
+                info!(
+                    target = "timelapse",
+                    "Saved {} ({} frames)", entry.filename, frames
+                );
+
+                events.send(Event::TimelapseSaved {
+                    filename: entry.filename,
+                    filesize: entry.filesize,
+                    frames,
+                });
+            }
+            Err(e) => self.fail(e, events),
+        }
+
+        let library = self.library.clone();
+        match tokio::task::spawn_blocking(move || library.prune()).await {
+            Ok(Ok(removed)) if removed > 0 => {
+                debug!(target = "timelapse", "Pruned {} old time-lapses", removed)
+            }
+            Ok(Err(e)) => error!(target = "timelapse", "Failed to prune time-lapses: {}", e),
+            _ => {}
+        }
+    }
+
+    fn fail(&self, e: anyhow::Error, events: &EventDispatcher) {
+        error!(target = "timelapse", "Time-lapse failed: {}", e);
+
+        events.send(Event::TimelapseError {
+            message: e.to_string(),
+        });
+    }
+}